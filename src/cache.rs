@@ -0,0 +1,401 @@
+//! Hashes of projects that were read before, so that commands can tell a project hasn't changed
+//! without unzipping its file or reading it back from a store.
+//!
+//! A project file's hash is kept with the file's size and modification time, and is used only
+//! while they're the same. A store's hashes are kept for its latest revision, and are dropped as
+//! soon as the store has a newer one. Encrypted stores aren't cached, since a commit synced in
+//! from another computer can change what their newest revision has. The cache is kept next to
+//! the config file; deleting it (or running with --no-cache) only makes commands slower.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::dirs::Dirs;
+use crate::project::{self, ProjectID, RawProject};
+use crate::store::Store;
+
+/// Files modified more recently than this aren't cached: a write in the same instant as the one
+/// that was read could change them without changing their modification time.
+const MIN_AGE: Duration = Duration::from_secs(2);
+
+/// A project read from its file, with its hash.
+pub type HashedProject = (RawProject, Vec<u8>);
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheData {
+    files: HashMap<PathBuf, FileHash>,
+    stores: HashMap<PathBuf, StoreHashes>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+struct Stamp {
+    size: u64,
+    /// Modification time as seconds and nanoseconds since the Unix epoch.
+    secs: u64,
+    nanos: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileHash {
+    stamp: Stamp,
+    hash: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoreHashes {
+    revision: String,
+    /// Keyed by program/name.
+    projects: HashMap<String, Vec<u8>>,
+}
+
+/// Can be shared between threads; only looking things up and adding them waits for other threads,
+/// not reading projects.
+pub struct Cache {
+    /// Where the cache is saved, or None if caching is turned off.
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    data: CacheData,
+    changed: bool,
+}
+
+/// Loads the cache for the config. A cache that can't be read starts out empty, since everything
+/// in it can be worked out again.
+pub fn load(cfg: &Config) -> Cache {
+    let Ok(path) = cfg.cache_path() else {
+        return disabled();
+    };
+    let data = fs::read(&path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+    Cache {
+        path: Some(path),
+        state: Mutex::new(State {
+            data,
+            changed: false,
+        }),
+    }
+}
+
+/// A cache that never has anything in it, for --no-cache.
+pub fn disabled() -> Cache {
+    Cache {
+        path: None,
+        state: Mutex::default(),
+    }
+}
+
+impl Cache {
+    /// Returns the hash of the project file if it hasn't changed since it was last hashed.
+    pub fn file_hash(&self, id: &ProjectID, dirs: &Dirs) -> Option<Vec<u8>> {
+        let path = id.path(dirs);
+        let stamp = stamp(&fs::metadata(&path).ok()?)?;
+        let state = self.state();
+        let entry = state.data.files.get(&path)?;
+        (entry.stamp == stamp).then(|| entry.hash.clone())
+    }
+
+    /// Reads the project file and remembers its hash. Returns None if it doesn't exist.
+    pub fn read_project(
+        &self,
+        id: &ProjectID,
+        dirs: &Dirs,
+    ) -> Result<Option<HashedProject>, Box<dyn Error>> {
+        let path = id.path(dirs);
+        // Taken before reading, so that a change made while it's read isn't missed.
+        let before = fs::metadata(&path).ok().and_then(|m| stamp(&m));
+        let Some(raw) = project::read(id, dirs)? else {
+            self.forget_file(&path);
+            return Ok(None);
+        };
+        let hash = raw.hash();
+        match before {
+            Some(stamp) if self.path.is_some() && is_settled(stamp) => {
+                let mut state = self.state();
+                let hash = hash.clone();
+                state.data.files.insert(path, FileHash { stamp, hash });
+                state.changed = true;
+            }
+            _ => self.forget_file(&path),
+        }
+        Ok(Some((raw, hash)))
+    }
+
+    /// Returns the hash of the project file, reading it only if it changed since it was last
+    /// hashed. Returns None if it doesn't exist.
+    pub fn local_hash(
+        &self,
+        id: &ProjectID,
+        dirs: &Dirs,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if let Some(hash) = self.file_hash(id, dirs) {
+            return Ok(Some(hash));
+        }
+        Ok(self.read_project(id, dirs)?.map(|(_, hash)| hash))
+    }
+
+    /// Returns the hash of the project in the store's latest revision, reading it from the store
+    /// only if it isn't cached. Returns None if the store doesn't have the project.
+    pub fn store_hash(
+        &self,
+        store: &Store,
+        id: &ProjectID,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let Some(revision) = store.latest()? else {
+            return Ok(None);
+        };
+        let revision_name = revision.to_string();
+        if let Some(hash) = self.cached(store, &revision_name, id) {
+            return Ok(Some(hash));
+        }
+        let Some(raw) = store.read_project(id, &revision)? else {
+            return Ok(None);
+        };
+        let hash = raw.hash();
+        if self.path.is_some() && store.has_stable_revisions() {
+            let mut state = self.state();
+            let entry = state.data.stores.entry(store.path().to_path_buf());
+            let entry = entry.or_default();
+            // Hashes kept for an older revision don't hold any more.
+            if entry.revision != revision_name {
+                *entry = StoreHashes {
+                    revision: revision_name,
+                    projects: HashMap::new(),
+                };
+            }
+            entry.projects.insert(key(id), hash.clone());
+            state.changed = true;
+        }
+        Ok(Some(hash))
+    }
+
+    /// Returns true if the project file is known to be the same as the project in each of the
+    /// stores, given as the store and its latest revision, without reading anything.
+    pub fn unchanged(
+        &self,
+        id: &ProjectID,
+        dirs: &Dirs,
+        stores: &[(&Store, Option<String>)],
+    ) -> bool {
+        let Some(hash) = self.file_hash(id, dirs) else {
+            return false;
+        };
+        stores.iter().all(|(store, revision)| {
+            revision
+                .as_ref()
+                .is_some_and(|revision| self.cached(store, revision, id) == Some(hash.clone()))
+        })
+    }
+
+    /// Returns the hash of the project as it was last committed to the store at path, as far as the
+    /// cache knows. Unlike store_hash, this doesn't check that the store hasn't changed since, so
+    /// it works for stores that can't be opened.
+    pub fn last_committed(&self, path: &Path, id: &ProjectID) -> Option<Vec<u8>> {
+        let state = self.state();
+        state.data.stores.get(path)?.projects.get(&key(id)).cloned()
+    }
+
+    /// Records the projects that were just committed to the store. before is the store's latest
+    /// revision from just before the commit; the hashes cached for it still hold for projects
+    /// that the commit didn't change.
+    pub fn committed(
+        &self,
+        store: &Store,
+        before: Option<String>,
+        hashes: &[(ProjectID, Vec<u8>)],
+    ) {
+        if self.path.is_none() || !store.has_stable_revisions() {
+            return;
+        }
+        let Ok(Some(after)) = store.latest() else {
+            return;
+        };
+        let mut state = self.state();
+        let mut entry = state.data.stores.remove(store.path()).unwrap_or_default();
+        if before.as_ref() != Some(&entry.revision) {
+            entry.projects.clear();
+        }
+        entry.revision = after.to_string();
+        for (id, hash) in hashes {
+            entry.projects.insert(key(id), hash.clone());
+        }
+        state.data.stores.insert(store.path().to_path_buf(), entry);
+        state.changed = true;
+    }
+
+    /// Saves the cache if anything was added to it.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut state = self.state();
+        if !state.changed {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written to a temporary file first, so that a cache that's cut off isn't left behind.
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&state.data)?)?;
+        fs::rename(&tmp, path)?;
+        state.changed = false;
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // At worst a panic leaves a hash out, which only means reading the project again.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cached(&self, store: &Store, revision: &str, id: &ProjectID) -> Option<Vec<u8>> {
+        if !store.has_stable_revisions() {
+            return None;
+        }
+        let state = self.state();
+        let entry = state.data.stores.get(store.path())?;
+        (entry.revision == revision)
+            .then(|| entry.projects.get(&key(id)).cloned())
+            .flatten()
+    }
+
+    fn forget_file(&self, path: &Path) {
+        let mut state = self.state();
+        if state.data.files.remove(path).is_some() {
+            state.changed = true;
+        }
+    }
+}
+
+fn key(id: &ProjectID) -> String {
+    format!("{}/{}", id.program, id.name)
+}
+
+fn stamp(meta: &Metadata) -> Option<Stamp> {
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(Stamp {
+        size: meta.len(),
+        secs: modified.as_secs(),
+        nanos: modified.subsec_nanos(),
+    })
+}
+
+fn is_settled(stamp: Stamp) -> bool {
+    let modified = UNIX_EPOCH + Duration::new(stamp.secs, stamp.nanos);
+    SystemTime::now()
+        .duration_since(modified)
+        .is_ok_and(|age| age >= MIN_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StoreConfig;
+    use crate::project::test_util::python;
+    use crate::project::Program;
+    use crate::store;
+    use std::fs::{File, FileTimes};
+
+    /// Writes the project and backdates it, so that it's old enough to be cached.
+    fn write(path: &Path, raw: &RawProject, age: u64) {
+        raw.write(File::create(path).unwrap()).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age);
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_times(FileTimes::new().set_modified(modified))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_file_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let spike = dir.path().join("spike");
+        fs::create_dir(&spike).unwrap();
+        let dirs = Dirs {
+            mindstorms: dir.path().join("mindstorms"),
+            spike: spike.clone(),
+        };
+        let id = ProjectID {
+            program: Program::Spike,
+            name: "Robot.llsp3".to_string(),
+        };
+        let new_cache = || Cache {
+            path: Some(dir.path().join("cache.json")),
+            state: Mutex::default(),
+        };
+
+        let cache = new_cache();
+        assert_eq!(cache.local_hash(&id, &dirs).unwrap(), None);
+
+        write(&spike.join(&id.name), &python("1"), 60);
+        assert_eq!(cache.file_hash(&id, &dirs), None);
+        let hash = cache.local_hash(&id, &dirs).unwrap();
+        assert_eq!(hash, Some(python("1").hash()));
+        cache.save().unwrap();
+
+        let cache = new_cache();
+        cache.state().data =
+            serde_json::from_slice(&fs::read(dir.path().join("cache.json")).unwrap()).unwrap();
+        assert_eq!(cache.file_hash(&id, &dirs), hash);
+
+        // A change shows up even though the file is the same size.
+        write(&spike.join(&id.name), &python("2"), 30);
+        assert_eq!(cache.file_hash(&id, &dirs), None);
+        let hash = cache.local_hash(&id, &dirs).unwrap();
+        assert_eq!(hash, Some(python("2").hash()));
+        assert_eq!(cache.file_hash(&id, &dirs), hash);
+
+        // Files that were only just written aren't trusted.
+        write(&spike.join(&id.name), &python("3"), 0);
+        assert_eq!(
+            cache.local_hash(&id, &dirs).unwrap(),
+            Some(python("3").hash())
+        );
+        assert_eq!(cache.file_hash(&id, &dirs), None);
+    }
+
+    #[test]
+    fn test_store_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = ProjectID {
+            program: Program::Spike,
+            name: "Robot.llsp3".to_string(),
+        };
+        let cache = Cache {
+            path: Some(dir.path().join("cache.json")),
+            state: Mutex::default(),
+        };
+        for (name, store_type, passphrase) in [
+            ("store.sqlite", "sqlite", None),
+            ("encrypted", "encrypted", Some("secret".to_string())),
+        ] {
+            let store = store::create(&StoreConfig {
+                path: dir.path().join(name),
+                store_type: store_type.to_string(),
+                rendered_branch: None,
+                branch: None,
+                passphrase,
+                key_file: None,
+            })
+            .unwrap();
+            store.commit(&[(id.clone(), python("1"))], "first").unwrap();
+            let hash = cache.store_hash(&store, &id).unwrap();
+            assert_eq!(hash, Some(python("1").hash()));
+            let revision = store.latest().unwrap().map(|r| r.to_string());
+            let cached = cache.cached(&store, &revision.unwrap(), &id);
+            // A commit synced into an encrypted store can change what its newest revision has.
+            assert_eq!(cached.is_some(), store_type == "sqlite");
+        }
+        assert_eq!(cache.state().data.stores.len(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+//! Copies history between stores, so that a store that was added later (e.g. a new backup) or was
+//! unreachable for a while gets the versions of projects that were committed to the others.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::time::SystemTime;
+
+use crate::project::{ProjectID, RawProject};
+use crate::store::{self, Revision, Store};
+
+/// A version of a project, as its id and content hash.
+type Snapshot = (ProjectID, Vec<u8>);
+
+/// A commit made to the target store.
+pub struct Replayed {
+    pub message: String,
+    pub date: SystemTime,
+    pub projects: Vec<ProjectID>,
+    pub result: store::CommitResult,
+}
+
+pub struct CatchUpResult {
+    /// Oldest first. Stops at the first commit that fails, so that later versions aren't
+    /// committed before earlier ones.
+    pub replayed: Vec<Replayed>,
+    /// Projects whose latest version in the target was committed again after the older versions
+    /// were replayed, and the result.
+    pub restored: Option<(Vec<ProjectID>, store::CommitResult)>,
+    /// Projects that replaying brought into the target but that aren't tracked in the source any
+    /// more, which were untracked again, with the result of each.
+    pub untracked: Vec<(ProjectID, store::CommitResult)>,
+}
+
+/// Commits every version of a project in from's history that isn't anywhere in to's history,
+/// oldest first, with the original message and date. Versions are matched by their contents.
+/// Afterwards the target tracks the projects it tracked before and the ones the source tracks now.
+pub fn catch_up(from: &Store, to: &Store) -> Result<CatchUpResult, Box<dyn Error>> {
+    let mut present = snapshots(to)?;
+    let ids = to.project_ids()?;
+    let tracked: HashSet<ProjectID> = ids.iter().cloned().collect();
+    let mut latest = Vec::new();
+    for id in ids {
+        if let Some(raw) = to.read_project(&id, &Revision::Latest)? {
+            latest.push((id, raw));
+        }
+    }
+
+    let mut commits = from.history()?;
+    commits.reverse();
+    let mut replayed = Vec::new();
+    for commit in commits {
+        let mut missing = Vec::new();
+        for id in commit.changed_projects {
+            let Some(raw) = from.read_project(&id, &commit.revision)? else {
+                continue;
+            };
+            if present.insert((id.clone(), raw.hash())) {
+                missing.push((id, raw));
+            }
+        }
+        if missing.is_empty() {
+            continue;
+        }
+        let result = to.commit_at(&missing, &commit.message, commit.date);
+        let failed = result.is_err();
+        replayed.push(Replayed {
+            message: commit.message,
+            date: commit.date,
+            projects: missing.into_iter().map(|(id, _)| id).collect(),
+            result,
+        });
+        if failed {
+            break;
+        }
+    }
+
+    // Replaying older versions replaces the target's latest ones, so they're committed again.
+    let restored = match replayed.iter().any(|r| r.result.is_ok()) {
+        false => None,
+        true => {
+            let mut changed: Vec<(ProjectID, RawProject)> = Vec::new();
+            for (id, raw) in latest {
+                let current = to.read_project(&id, &Revision::Latest)?;
+                if current.is_none_or(|c| c.hash() != raw.hash()) {
+                    changed.push((id, raw));
+                }
+            }
+            match changed.is_empty() {
+                true => None,
+                false => {
+                    let ids = changed.iter().map(|(id, _)| id.clone()).collect();
+                    let message = "Restore latest versions after catching up";
+                    Some((ids, to.commit(&changed, message)))
+                }
+            }
+        }
+    };
+
+    // Projects untracked in the source were brought back by replaying their older versions.
+    let mut untracked = Vec::new();
+    if replayed.iter().any(|r| r.result.is_ok()) {
+        let source: HashSet<ProjectID> = from.project_ids()?.into_iter().collect();
+        let mut ids = to.project_ids()?;
+        ids.retain(|id| !tracked.contains(id) && !source.contains(id));
+        ids.sort_by_key(|id| id.to_string());
+        for id in ids {
+            let message = format!("Stop tracking {id}, which isn't tracked in the source");
+            let result = to.untrack(&id, &message);
+            untracked.push((id, result));
+        }
+    }
+    Ok(CatchUpResult {
+        replayed,
+        restored,
+        untracked,
+    })
+}
+
+/// Returns every version of every project in the store's history, as project and hash.
+fn snapshots(store: &Store) -> Result<HashSet<Snapshot>, Box<dyn Error>> {
+    let mut res = HashSet::new();
+    for commit in store.history()? {
+        for id in commit.changed_projects {
+            if let Some(raw) = store.read_project(&id, &commit.revision)? {
+                res.insert((id, raw.hash()));
+            }
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StoreConfig;
+    use crate::project::test_util::{id, python};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn create(path: std::path::PathBuf) -> Store {
+        store::create(&StoreConfig {
+            path,
+            store_type: "sqlite".to_string(),
+            rendered_branch: None,
+            branch: None,
+            passphrase: None,
+            key_file: None,
+        })
+        .unwrap()
+    }
+
+    fn latest_hash(store: &Store, name: &str) -> Option<Vec<u8>> {
+        let raw = store.read_project(&id(name), &Revision::Latest).unwrap();
+        raw.map(|raw| raw.hash())
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = create(dir.path().join("from.sqlite"));
+        let to = create(dir.path().join("to.sqlite"));
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs);
+
+        // Both stores start with the same version of a, then diverge.
+        let a1 = [(id("a"), python("1"))];
+        from.commit_at(&a1, "a1", at(0)).unwrap();
+        to.commit_at(&a1, "a1", at(0)).unwrap();
+        from.commit_at(&[(id("a"), python("2"))], "a2", at(10))
+            .unwrap();
+        from.commit_at(&[(id("old"), python("1"))], "old", at(20))
+            .unwrap();
+        from.untrack(&id("old"), "untrack old").unwrap();
+        to.commit_at(&[(id("a"), python("3"))], "a3", at(30))
+            .unwrap();
+        to.commit_at(&[(id("b"), python("1"))], "b", at(40))
+            .unwrap();
+
+        let res = catch_up(&from, &to).unwrap();
+        let replayed: Vec<&str> = res.replayed.iter().map(|r| r.message.as_str()).collect();
+        assert_eq!(replayed, ["a2", "old"]);
+        let (restored, result) = res.restored.unwrap();
+        assert_eq!(restored, [id("a")]);
+        assert!(result.is_ok());
+        assert_eq!(res.untracked.len(), 1);
+        assert_eq!(res.untracked[0].0, id("old"));
+
+        // The target keeps its own latest versions and projects, and doesn't track old.
+        let mut ids = to.project_ids().unwrap();
+        ids.sort_by_key(|id| id.to_string());
+        assert_eq!(ids, [id("a"), id("b")]);
+        assert_eq!(latest_hash(&to, "a"), Some(python("3").hash()));
+        // Both versions of a from the source are in the target's history now.
+        let snapshots = snapshots(&to).unwrap();
+        assert!(snapshots.contains(&(id("a"), python("2").hash())));
+        assert!(snapshots.contains(&(id("old"), python("1").hash())));
+
+        let res = catch_up(&from, &to).unwrap();
+        assert!(res.replayed.is_empty() && res.untracked.is_empty());
+    }
+}
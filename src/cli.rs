@@ -1,7 +1,11 @@
 use std::path::PathBuf;
 use std::time::Duration;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::compact::Keep;
+use crate::import::DateSource;
+use crate::project::Program;
 
 #[derive(Parser, Debug)]
 #[command(name = "mm")]
@@ -11,6 +15,10 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub config: Option<String>,
 
+    /// Read every project instead of trusting the hashes cached from earlier runs.
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -31,13 +39,38 @@ pub enum Commands {
     AutoCommit(AutoCommitCommand),
     /// Show commit history
     Log(LogCommand),
-    /// Render tracked projects to a directory
+    /// Render tracked projects to a directory, a file, a store, or a web site
     Render(RenderCommand),
+    /// Render each new commit onto a branch in the store
+    SyncRenderedBranch(SyncRenderedBranchCommand),
+    /// Browse stores and project history in a web browser
+    Serve(ServeCommand),
+    /// Browse and manage projects in a full-screen terminal interface
+    Ui,
+    /// Check tracked projects for mistakes (exits with an error if any are found)
+    Lint(LintCommand),
+    /// Show how projects have changed over time
+    Stats(StatsCommand),
+    /// Search tracked projects for a pattern, optionally including every past version
+    Grep(GrepCommand),
+    /// Export a project to another format
+    Export(ExportCommand),
+    /// Copy a project into the other app (SPIKE or Mindstorms)
+    Convert(ConvertCommand),
+    /// Add project files from anywhere (e.g. old backups) to the stores, as tracked projects
+    Import(ImportCommand),
+    /// Check that every project in a store can be read back, optionally in every past version
+    Fsck(FsckCommand),
+    /// Create, switch between, and list branches, for separate lines of work in a store
+    Branch(BranchCommand),
+    /// Name a revision (e.g. the version that scored 480) so it can be found and used later
+    Tag(TagCommand),
+    /// List tags with their dates and notes
+    Tags(TagsCommand),
+    /// Squash older history into hourly or daily snapshots and free the space it took
+    Compact(CompactCommand),
     /*
-     * todo: render to file
      * todo: render in GUI (?)
-     * todo: render in browser (?)
-     * todo: render to branch (can do automatically)
      * todo: render diff (given a commit id and store)
      * todo: revert to a specific version
      * todo: accept python branch
@@ -57,6 +90,10 @@ pub struct LogCommand {
     #[arg(long, default_value = "1d", value_parser = parse_duration)]
     pub since: Duration,
 
+    /// Only show commits older than this (e.g., "1w" for commits from before last week)
+    #[arg(long, value_parser = parse_duration)]
+    pub until: Option<Duration>,
+
     /// Store to show logs from (if not specified, uses the only store if there's just one)
     #[arg(long)]
     pub store: Option<PathBuf>,
@@ -94,15 +131,33 @@ pub enum StoreSubcommand {
     Create(CreateStoreArgs),
     /// Remove a store
     Remove(RemoveStoreArgs),
+    /// List stores, with whether they can be opened, their latest commit, and how many projects
+    /// they track
+    List,
+    /// Show a store's size, number of commits, and tracked projects
+    Info(StoreInfoArgs),
+    /// Move a store to a new path on disk and update the config
+    Move(MoveStoreArgs),
+    /// Copy versions of projects that one store has and another doesn't, with their original
+    /// dates (e.g. to fill in a newly added backup store)
+    CatchUp(CatchUpStoreArgs),
 }
 
 #[derive(Args, Debug)]
 pub struct CreateStoreArgs {
-    /// The store type (git is the only option)
+    /// The store type: git, encrypted for syncing through a folder that others can read, or
+    /// sqlite for a single file with quick lookups
     #[arg(long = "type")]
     pub store_type: String,
     /// Path to the repo
     pub path: PathBuf,
+    /// Passphrase for an encrypted store (saved in the config)
+    #[arg(long, conflicts_with = "key_file")]
+    pub passphrase: Option<String>,
+    /// File with the key for an encrypted store, e.g. on a USB stick (its path is saved in the
+    /// config)
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
 }
 
 #[derive(Args, Debug)]
@@ -111,6 +166,30 @@ pub struct RemoveStoreArgs {
     pub path: PathBuf,
 }
 
+#[derive(Args, Debug)]
+pub struct StoreInfoArgs {
+    /// Path to the repo
+    pub path: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct MoveStoreArgs {
+    /// Current path to the repo
+    pub old: PathBuf,
+    /// New path, which must not exist yet
+    pub new: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct CatchUpStoreArgs {
+    /// Store to copy history from (if not specified, every other store)
+    #[arg(long)]
+    pub from: Option<PathBuf>,
+    /// Store to copy history to (if not specified, every other store)
+    #[arg(long)]
+    pub to: Option<PathBuf>,
+}
+
 #[derive(Args, Debug)]
 pub struct TrackCommand {
     /// Track a spike file
@@ -166,6 +245,282 @@ pub struct RenderDest {
     /// Render back to the original store (e.g. as a commit).
     #[arg(short, long)]
     pub to_store: bool,
+
+    /// Render to a single file: a .zip or .tar archive, or a .md document with a section for
+    /// each project.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Render a static HTML site to the specified directory. Each revision gets its own
+    /// subdirectory, with links to the revisions before and after it.
+    #[arg(long)]
+    pub html: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct LintCommand {
+    /// Check the projects in a store at this revision instead of the local copies
+    #[arg(long)]
+    pub revision: Option<String>,
+
+    /// Which store to read the revision from (must be specified if there's more than one store configured)
+    #[arg(long, requires = "revision")]
+    pub store: Option<PathBuf>,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct StatsCommand {
+    /// Only count commits made in this period (e.g., 30d); default is the whole history
+    #[arg(long, value_parser = parse_duration)]
+    pub since: Option<Duration>,
+
+    /// Which store to read history from (must be specified if there's more than one store configured)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+
+    /// Output format: a summary table, or one row per project per commit as CSV or JSON
+    #[arg(long, value_enum, default_value = "table")]
+    pub format: StatsFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum StatsFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct GrepCommand {
+    /// Regular expression to search for in Python source, and in block labels, opcodes, values,
+    /// variable names, and comments
+    pub pattern: String,
+
+    /// Ignore case when matching
+    #[arg(short, long)]
+    pub ignore_case: bool,
+
+    /// Only search projects from this app ("spike" or "mindstorms")
+    #[arg(long)]
+    pub program: Option<Program>,
+
+    /// Also search every version of each project in a store's history
+    #[arg(long)]
+    pub all_revisions: bool,
+
+    /// Which store to read history from (must be specified if there's more than one store configured)
+    #[arg(long, requires = "all_revisions")]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportCommand {
+    /// Export a block project as a Scratch 3 file, which can be opened in Scratch or TurboWarp
+    #[arg(long, required = true)]
+    pub sb3: bool,
+
+    /// File name of the project (e.g., "Project 1.llsp3")
+    pub project: String,
+
+    /// Which app the project is from ("spike" or "mindstorms"); default is based on the file
+    /// extension
+    #[arg(long)]
+    pub program: Option<Program>,
+
+    /// Export the project as of this revision instead of the local copy
+    #[arg(long)]
+    pub revision: Option<String>,
+
+    /// Which store to read the revision from (must be specified if there's more than one store configured)
+    #[arg(long, requires = "revision")]
+    pub store: Option<PathBuf>,
+
+    /// Replace LEGO blocks with custom blocks and variables of the same name, so that the project
+    /// opens in editors that don't have the LEGO extensions (it won't run the same way)
+    #[arg(long)]
+    pub stub_extensions: bool,
+
+    /// Where to write the file (default is the project's name with a .sb3 extension, in the
+    /// current directory)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Overwrite the output file if it already exists
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertCommand {
+    /// File name of the project (e.g., "Project 1.llsp3")
+    pub project: String,
+
+    /// Which app the project is from ("spike" or "mindstorms"); default is based on the file
+    /// extension
+    #[arg(long)]
+    pub program: Option<Program>,
+
+    /// Which app to convert the project for ("spike" or "mindstorms")
+    #[arg(long)]
+    pub to: Program,
+
+    /// Start tracking the converted project
+    #[arg(long)]
+    pub track: bool,
+
+    /// Overwrite the other app's project if there's already one with the same name
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportCommand {
+    /// Project files, or directories to search for project files. Each file is committed
+    /// separately, oldest first, so a directory of backups becomes a history.
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Which app the projects are from ("spike" or "mindstorms"); default is based on each file's
+    /// extension
+    #[arg(long)]
+    pub program: Option<Program>,
+
+    /// Where the date of each commit comes from
+    #[arg(long, value_enum, default_value = "manifest")]
+    pub date: DateSource,
+
+    /// Treat files as dated backups (e.g. "Robot v3 2023-10-02.llsp3"): versions of the same
+    /// project are grouped by name, ignoring dates, version numbers, and "copy", and a date in the
+    /// file name takes precedence over --date
+    #[arg(long)]
+    pub group: bool,
+
+    /// Show what would be imported without committing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TagCommand {
+    /// Name of the tag, which can be used wherever a revision is accepted
+    pub name: String,
+
+    /// Tag the newest commit that changed this project (e.g. "Robot.llsp3"), and note it in the tag
+    #[arg(long)]
+    pub project: Option<String>,
+
+    /// Which app the project is from ("spike" or "mindstorms"); default is based on the extension
+    #[arg(long, requires = "project")]
+    pub program: Option<Program>,
+
+    /// Revision to tag (default is the latest commit)
+    #[arg(long)]
+    pub revision: Option<String>,
+
+    /// A note to keep with the tag, e.g. "scored 480 at regionals"
+    #[arg(short, long, default_value = "")]
+    pub message: String,
+
+    /// Which store to tag in (if not specified, every store)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct TagsCommand {
+    /// Which store to list tags from (if not specified, every store)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct CompactCommand {
+    /// Only squash commits older than this (e.g., "30d", "2w")
+    #[arg(long, default_value = "30d", value_parser = parse_duration)]
+    pub older_than: Duration,
+
+    /// How many snapshots of older history to keep
+    #[arg(long, value_enum, default_value = "daily")]
+    pub keep: Keep,
+
+    /// Show what would be squashed without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Delete the backups of the history from before earlier compactions, so that the space they
+    /// take can be freed
+    #[arg(long)]
+    pub drop_backups: bool,
+
+    /// Store to compact (if not specified, uses the only store if there's just one)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct BranchCommand {
+    #[command(subcommand)]
+    pub subcommand: BranchSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BranchSubcommand {
+    /// Create a branch from the latest commit (or another revision)
+    Create(CreateBranchArgs),
+    /// Make commits, tracking, and untracking go to a branch
+    Switch(SwitchBranchArgs),
+    /// List branches, marking the one that commits go to
+    List(ListBranchesArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct CreateBranchArgs {
+    /// Name of the branch, e.g. "competition-day"
+    pub name: String,
+    /// Revision to start the branch from (default is the latest commit on the current branch)
+    #[arg(long)]
+    pub from: Option<String>,
+    /// Which store to create it in (if not specified, every store)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct SwitchBranchArgs {
+    /// Name of the branch
+    pub name: String,
+    /// Which store to switch (if not specified, every store)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ListBranchesArgs {
+    /// Which store to list branches of (if not specified, every store)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct FsckCommand {
+    /// Also check every version of each project in the store's history
+    #[arg(long)]
+    pub all_revisions: bool,
+
+    /// Which store to check (if not specified, checks every store)
+    #[arg(long)]
+    pub store: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeCommand {
+    /// Port to listen on (0 picks any free port); only connections from this machine are accepted
+    #[arg(long, default_value = "8080")]
+    pub port: u16,
 }
 
 #[derive(Args, Debug)]
@@ -177,4 +532,12 @@ pub struct SyncRenderedBranchCommand {
     /// Which store to pull data from (must be specified if there's more than one store configured)
     #[arg(long)]
     pub store: Option<PathBuf>,
+
+    /// Also sync the branch after every commit and auto-commit
+    #[arg(long, conflicts_with = "no_auto")]
+    pub auto: bool,
+
+    /// Stop syncing the branch after commits
+    #[arg(long)]
+    pub no_auto: bool,
 }
@@ -1,41 +1,164 @@
 use std::error::Error;
+use std::panic;
+use std::path::PathBuf;
+use std::thread;
+use std::time::SystemTime;
 
-use crate::config::StoreConfig;
+use rayon::prelude::*;
+
+use crate::cache::Cache;
+use crate::config::{Config, StoreConfig};
 use crate::dirs::Dirs;
-use crate::project::{self, ProjectID};
+use crate::pending::{self, Queue};
+use crate::project::ProjectID;
 use crate::store::{self, Store};
 
 pub struct CommitResult {
     pub missing_projects: Vec<ProjectID>,
     pub project_read_errors: Vec<(ProjectID, Box<dyn Error>)>,
+    /// Earlier commits that were retried, with their messages.
+    pub retried: Vec<(StoreConfig, String, store::CommitResult)>,
     pub store_results: Vec<(StoreConfig, store::CommitResult)>,
+    /// Stores that the commit was queued for because they couldn't be opened, the commit failed,
+    /// or earlier commits to them are still waiting.
+    pub queued: Result<Vec<StoreConfig>, Box<dyn Error>>,
 }
 
+/// Commits the projects to the stores that could be opened, after retrying any earlier commits
+/// that are waiting for them. Stores that don't get the commit have it queued for next time.
+///
+/// Projects that the cache knows every store already has are left out without being read.
 pub fn commit<'a, P: IntoIterator<Item = &'a ProjectID>>(
+    cfg: &Config,
     stores: &[(StoreConfig, Store)],
     dirs: &Dirs,
     projects: P,
     message: &str,
+    cache: &Cache,
 ) -> CommitResult {
+    let mut queue = pending::load(cfg);
+    let retried = match &mut queue {
+        Ok(queue) => queue.retry(stores),
+        Err(_) => Vec::new(),
+    };
+    let blocked = queue.as_ref().map(Queue::blocked).unwrap_or_default();
+
+    let mut to_queue: Vec<StoreConfig> = cfg
+        .stores
+        .iter()
+        .filter(|st| !stores.iter().any(|(s, _)| s.path == st.path))
+        .cloned()
+        .collect();
+    // The revisions the commit goes on top of. A project can only be left out if it's known to be
+    // in every store as it is now, so not if some store's commits are queued.
+    let latest: Vec<(&Store, Option<String>)> = stores
+        .iter()
+        .map(|(_, store)| {
+            let revision = store.latest().ok().flatten();
+            (store, revision.map(|r| r.to_string()))
+        })
+        .collect();
+    let can_skip = to_queue.is_empty() && !stores.iter().any(|(st, _)| blocked.contains(&st.path));
+
+    // Projects are read in parallel, but kept in a fixed order so that the commit and what's
+    // printed about it don't depend on which finishes first.
+    let mut projects: Vec<&ProjectID> = projects.into_iter().collect();
+    projects.sort_by_key(|id| id.to_string());
+    let read: Vec<_> = projects
+        .par_iter()
+        .map(|id| {
+            if can_skip && cache.unchanged(id, dirs, &latest) {
+                return None;
+            }
+            // Errors can't be sent between threads, so only their messages are kept.
+            Some(cache.read_project(id, dirs).map_err(|e| e.to_string()))
+        })
+        .collect();
+
     let mut missing_projects = Vec::new();
     let mut project_read_errors = Vec::new();
     let mut projects_to_commit = Vec::new();
-    for proj_id in projects {
-        match project::read(proj_id, dirs) {
-            Ok(Some(raw_project)) => projects_to_commit.push((proj_id.clone(), raw_project)),
-            Ok(None) => missing_projects.push(proj_id.clone()),
-            Err(e) => project_read_errors.push((proj_id.clone(), e)),
+    let mut hashes = Vec::new();
+    for (proj_id, res) in projects.into_iter().zip(read) {
+        match res {
+            None => {}
+            Some(Ok(Some((raw_project, hash)))) => {
+                projects_to_commit.push((proj_id.clone(), raw_project));
+                hashes.push((proj_id.clone(), hash));
+            }
+            Some(Ok(None)) => missing_projects.push(proj_id.clone()),
+            Some(Err(e)) => project_read_errors.push((proj_id.clone(), e.into())),
         };
     }
 
+    // Each store is committed to on its own thread. Blocked stores get None.
+    let results: Vec<Option<Result<&'static str, String>>> = thread::scope(|s| {
+        let threads: Vec<_> = stores
+            .iter()
+            .map(|(st, store)| {
+                (!blocked.contains(&st.path)).then(|| {
+                    s.spawn(|| {
+                        let res = store.commit(&projects_to_commit, message);
+                        res.map_err(|e| e.to_string())
+                    })
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|t| t.map(|t| t.join().unwrap_or_else(|e| panic::resume_unwind(e))))
+            .collect()
+    });
+
     let mut store_results = Vec::new();
-    for (st, store) in stores {
-        store_results.push((st.clone(), store.commit(&projects_to_commit, message)));
+    for (((st, store), (_, before)), res) in stores.iter().zip(latest).zip(results) {
+        let Some(res) = res else {
+            to_queue.push(st.clone());
+            continue;
+        };
+        let res: store::CommitResult = res.map_err(Into::into);
+        match res {
+            Ok(_) => cache.committed(store, before, &hashes),
+            Err(_) => to_queue.push(st.clone()),
+        }
+        store_results.push((st.clone(), res));
     }
 
+    let queued = match queue {
+        _ if to_queue.is_empty() || projects_to_commit.is_empty() => Ok(Vec::new()),
+        Err(e) => Err(e),
+        Ok(mut queue) => {
+            let paths: Vec<PathBuf> = to_queue.iter().map(|st| st.path.clone()).collect();
+            // Only versions that some of the stores don't have or aren't already waiting for are
+            // saved, so that a store that's away for a while doesn't collect a copy of every
+            // project for every commit.
+            let changed: Vec<_> = projects_to_commit
+                .into_iter()
+                .zip(hashes)
+                .filter(|(_, (id, hash))| {
+                    paths.iter().any(|path| {
+                        let last = queue
+                            .queued_hash(path, id)
+                            .or_else(|| cache.last_committed(path, id));
+                        last.as_ref() != Some(hash)
+                    })
+                })
+                .map(|(proj, _)| proj)
+                .collect();
+            match changed.is_empty() {
+                true => Ok(Vec::new()),
+                false => queue
+                    .add(paths, &changed, message, SystemTime::now())
+                    .map(|_| to_queue),
+            }
+        }
+    };
+
     CommitResult {
         missing_projects,
         project_read_errors,
+        retried,
         store_results,
+        queued,
     }
 }
@@ -0,0 +1,158 @@
+//! Squashes older history into coarser snapshots, so that the many small commits auto-commit makes
+//! don't pile up forever. Each run of old commits in the same hour or day becomes one commit with
+//! the contents of the newest, so every project keeps its last version from that hour or day.
+
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+
+use crate::project::ProjectID;
+
+/// How coarse the snapshots of older history are.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Keep {
+    /// One snapshot per hour
+    Hourly,
+    /// One snapshot per day
+    Daily,
+}
+
+pub struct CompactOptions<'a> {
+    /// Only commits older than this are squashed.
+    pub older_than: Duration,
+    pub keep: Keep,
+    /// Work out what would be squashed without changing anything.
+    pub dry_run: bool,
+    /// Delete the backups made by earlier runs, so that pruning can free the history they keep.
+    pub drop_backups: bool,
+    /// The store's rendered branch, which is pointed at the rewritten history.
+    pub rendered_branch: Option<&'a str>,
+}
+
+/// Commits that were (or would be) squashed into one.
+pub struct Squash {
+    /// Dates of the oldest and newest of the commits.
+    pub from: SystemTime,
+    pub to: SystemTime,
+    pub commits: usize,
+    /// Projects that changed in any of the commits.
+    pub projects: Vec<ProjectID>,
+}
+
+pub struct CompactResult {
+    /// Oldest first.
+    pub squashed: Vec<Squash>,
+    /// Commits that were copied into the new history as they were.
+    pub kept: usize,
+    /// The ref that still points at the original history, if it was rewritten.
+    pub backup: Option<String>,
+    /// Backups from earlier runs that were (or would be) deleted.
+    pub dropped_backups: Vec<String>,
+    /// How many objects nothing referred to any more and were deleted, or None if pruning was
+    /// skipped.
+    pub pruned: Option<usize>,
+}
+
+/// Picks the runs of commits to squash. commits is the history oldest first, as each commit's
+/// date and whether it's tagged. The commits up to the first one that isn't older than cutoff are
+/// split into runs in the same hour or day, and a tagged commit always ends its run so that the
+/// version it names is kept. Returns only the runs of more than one commit.
+pub fn plan(commits: &[(SystemTime, bool)], cutoff: SystemTime, keep: Keep) -> Vec<Range<usize>> {
+    let old = commits
+        .iter()
+        .position(|(date, _)| *date >= cutoff)
+        .unwrap_or(commits.len());
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for (i, (date, tagged)) in commits[..old].iter().enumerate() {
+        let ends_run =
+            *tagged || i + 1 == old || period(commits[i + 1].0, keep) != period(*date, keep);
+        if ends_run {
+            if i > start {
+                runs.push(start..i + 1);
+            }
+            start = i + 1;
+        }
+    }
+    runs
+}
+
+/// The hour or day that date falls in, in local time.
+fn period(date: SystemTime, keep: Keep) -> String {
+    let date: DateTime<Local> = date.into();
+    match keep {
+        Keep::Hourly => date.format("%Y-%m-%d %H").to_string(),
+        Keep::Daily => date.format("%Y-%m-%d").to_string(),
+    }
+}
+
+/// The message for a commit that replaces commits with the given titles, oldest first.
+pub fn squash_message(titles: &[String]) -> String {
+    let mut distinct: Vec<&String> = Vec::new();
+    for title in titles {
+        if !distinct.contains(&title) {
+            distinct.push(title);
+        }
+    }
+    match distinct.as_slice() {
+        [title] => format!("{title}\n\nCompacted from {} commits.", titles.len()),
+        _ => {
+            let list: Vec<String> = distinct.iter().map(|t| format!("- {t}")).collect();
+            format!("Compacted {} commits\n\n{}", titles.len(), list.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> SystemTime {
+        Local
+            .with_ymd_and_hms(2026, 3, day, hour, minute, 0)
+            .unwrap()
+            .into()
+    }
+
+    #[test]
+    fn test_plan() {
+        let commits = [
+            (at(1, 9, 0), false),
+            (at(1, 9, 30), false),
+            (at(1, 10, 0), false),
+            (at(1, 10, 10), true),
+            (at(1, 10, 20), false),
+            (at(1, 10, 30), false),
+            (at(2, 8, 0), false),
+            (at(3, 8, 0), false),
+            (at(3, 8, 5), false),
+        ];
+        let cutoff = at(3, 0, 0);
+        assert_eq!(plan(&commits, cutoff, Keep::Hourly), [0..2, 2..4, 4..6]);
+        assert_eq!(plan(&commits, cutoff, Keep::Daily), [0..4, 4..6]);
+        // Nothing's old enough.
+        assert!(plan(&commits, at(1, 0, 0), Keep::Daily).is_empty());
+        assert_eq!(plan(&commits, at(4, 0, 0), Keep::Daily), [0..4, 4..6, 7..9]);
+    }
+
+    #[test]
+    fn test_squash_message() {
+        let auto = "Update tracked projects via auto-commit".to_string();
+        assert_eq!(
+            squash_message(&[auto.clone(), auto.clone()]),
+            "Update tracked projects via auto-commit\n\nCompacted from 2 commits."
+        );
+        assert_eq!(
+            squash_message(&[
+                auto.clone(),
+                "Start tracking spike: A.llsp3".to_string(),
+                auto
+            ]),
+            "Compacted 3 commits\n\n- Update tracked projects via auto-commit\n\
+             - Start tracking spike: A.llsp3"
+        );
+    }
+}
@@ -5,7 +5,7 @@ use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
 pub struct Config {
     #[serde(default)]
     pub stores: Vec<StoreConfig>,
@@ -17,11 +17,27 @@ pub struct Config {
     config_path: Option<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct StoreConfig {
     pub path: PathBuf,
     #[serde(rename = "type")]
     pub store_type: String,
+
+    /// Branch to keep rendered after every commit, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendered_branch: Option<String>,
+
+    /// Branch that commits go to, instead of the one the store's HEAD points at.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+
+    /// Passphrase that an encrypted store's key is derived from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+
+    /// File whose contents an encrypted store's key is derived from, instead of a passphrase.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<PathBuf>,
 }
 
 impl Display for StoreConfig {
@@ -76,6 +92,25 @@ impl Config {
         Ok(config)
     }
 
+    /// Loads the config again from the same file, e.g. to pick up changes made by another
+    /// command while this one is running.
+    pub fn reload(&self) -> io::Result<Self> {
+        let path = get_config_path(self.config_path.as_deref())?;
+        Self::load(Some(&path.to_string_lossy()))
+    }
+
+    /// Where commits waiting to be retried are kept, next to the config file.
+    pub fn pending_dir(&self) -> io::Result<PathBuf> {
+        let path = get_config_path(self.config_path.as_deref())?;
+        Ok(path.with_file_name("mind-meld-pending"))
+    }
+
+    /// Where hashes of projects that were already read are cached, next to the config file.
+    pub fn cache_path(&self) -> io::Result<PathBuf> {
+        let path = get_config_path(self.config_path.as_deref())?;
+        Ok(path.with_file_name("mind-meld-cache.json"))
+    }
+
     pub fn store(&self) -> io::Result<()> {
         let path = get_config_path(self.config_path.as_deref())?;
         if let Some(parent) = path.parent() {
@@ -148,4 +183,59 @@ mod tests {
         );
         assert_eq!(config.spike_path, Some(PathBuf::from("path/to/spike")));
     }
+
+    #[test]
+    fn test_rendered_branch_parses() {
+        let toml = r#"
+        [[stores]]
+        path = "path1"
+        type = "git"
+        rendered_branch = "rendered"
+        [[stores]]
+        path = "path2"
+        type = "git"
+        "#;
+        let config: Config = Config::load_from_string(toml).unwrap();
+        assert_eq!(
+            config.stores[0].rendered_branch,
+            Some("rendered".to_string())
+        );
+        assert_eq!(config.stores[1].rendered_branch, None);
+    }
+
+    #[test]
+    fn test_branch_parses() {
+        let toml = r#"
+        [[stores]]
+        path = "path1"
+        type = "git"
+        branch = "competition-day"
+        "#;
+        let config: Config = Config::load_from_string(toml).unwrap();
+        assert_eq!(config.stores[0].branch, Some("competition-day".to_string()));
+    }
+
+    #[test]
+    fn test_key_parses() {
+        let toml = r#"
+        [[stores]]
+        path = "path1"
+        type = "encrypted"
+        key_file = "/media/usb/mind-meld.key"
+        [[stores]]
+        path = "path2"
+        type = "encrypted"
+        passphrase = "correct horse"
+        "#;
+        let config: Config = Config::load_from_string(toml).unwrap();
+        assert_eq!(
+            config.stores[0].key_file,
+            Some(PathBuf::from("/media/usb/mind-meld.key"))
+        );
+        assert_eq!(config.stores[0].passphrase, None);
+        assert_eq!(
+            config.stores[1].passphrase,
+            Some("correct horse".to_string())
+        );
+    }
 }
@@ -0,0 +1,406 @@
+//! Converts projects between the SPIKE and Mindstorms apps.
+//!
+//! Both apps are built on the same editor and store projects the same way, so converting mostly
+//! means copying the project, updating its manifest, and renaming the blocks the apps call
+//! differently. What differs beyond that is what each app supports: Mindstorms doesn't have icon
+//! blocks, the apps have different Python modules, and their block extensions only partly
+//! overlap. Anything that won't work in the other app is reported.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+
+use crate::config::Config;
+use crate::dirs::Dirs;
+use crate::lint;
+use crate::project::scratch::{self, ScratchProject};
+use crate::project::{self, Program, Project, ProjectID, RawProject};
+
+/// Word block extensions that both apps have.
+const COMMON_EXTENSIONS: &[&str] = &[
+    "flipperevents",
+    "flippermotor",
+    "flippermove",
+    "flipperlight",
+    "flippersound",
+    "flippersensors",
+    "flippercontrol",
+    "flipperoperator",
+    "flippermoremotor",
+    "flippermoremove",
+    "flippermoresensors",
+    "flippermusic",
+];
+
+/// Extensions for showing data in the app while a program runs, which only SPIKE has.
+const SPIKE_EXTENSIONS: &[&str] = &[
+    "linegraphmonitor",
+    "bargraphmonitor",
+    "displaymonitor",
+    "weather",
+];
+
+/// Icon block extensions start with this, and only SPIKE has them.
+const ICON_PREFIX: &str = "horizontal";
+
+/// Blocks that both apps have under different opcodes, as (SPIKE, Mindstorms). Menus are blocks
+/// too, so the shadow blocks that hold a block's menu choices are listed with the blocks. Each
+/// pair stays within one extension, so a project's list of extensions doesn't change.
+const OPCODES: &[(&str, &str)] = &[
+    // The light matrix.
+    (
+        "flipperlight_lightDisplayImageOnForTime",
+        "flipperlight_displayImageForTime",
+    ),
+    (
+        "flipperlight_lightDisplayImageOn",
+        "flipperlight_displayImage",
+    ),
+    ("flipperlight_lightDisplayText", "flipperlight_displayText"),
+    ("flipperlight_lightDisplayOff", "flipperlight_displayOff"),
+    ("flipperlight_lightDisplaySetPixel", "flipperlight_setPixel"),
+    (
+        "flipperlight_lightDisplayRotate",
+        "flipperlight_displayRotate",
+    ),
+    (
+        "flipperlight_matrix-5x5-brightness-image",
+        "flipperlight_matrix-5x5-image",
+    ),
+    // The hub's orientation.
+    (
+        "flipperevents_whenOrientation",
+        "flipperevents_whenOrientationChanged",
+    ),
+    (
+        "flippersensors_isorientation",
+        "flippersensors_isOrientation",
+    ),
+    ("flippersensors_orientationAxis", "flippersensors_angle"),
+    ("flippersensors_resetYaw", "flippersensors_resetYawAngle"),
+    (
+        "flippersensors_custom-tilted",
+        "flippersensors_custom-orientation",
+    ),
+    // The hub's speaker.
+    ("flippersound_beepForTime", "flippersound_playBeepForTime"),
+    ("flippersound_beep", "flippersound_playBeep"),
+    ("flippersound_stopSound", "flippersound_stopAllSounds"),
+];
+
+/// Manifest fields about the hub that the project was last sent to. They don't apply to the
+/// other kit's hub, and the app fills them in again.
+const HUB_FIELDS: &[&str] = &["hardware", "slotIndex"];
+
+pub struct ConvertResult {
+    pub id: ProjectID,
+    pub path: PathBuf,
+    /// Blocks and lines of Python that the other app doesn't support.
+    pub unsupported: Vec<String>,
+}
+
+/// Writes a copy of the project into the other app's directory. An existing project with the
+/// same name is only overwritten if force is set.
+pub fn convert(
+    cfg: &Config,
+    id: &ProjectID,
+    to: Program,
+    force: bool,
+) -> Result<ConvertResult, Box<dyn Error>> {
+    if id.program == to {
+        return Err(format!("{id} is already a {to} project").into());
+    }
+    let dirs = Dirs::new(cfg)?;
+    let mut raw = project::read(id, &dirs)?.ok_or_else(|| format!("Project not found: {id}"))?;
+    let stem = Path::new(&id.name).file_stem().unwrap_or_default();
+    let new_id = ProjectID {
+        program: to,
+        name: format!("{}.{}", stem.to_string_lossy(), to.extension()),
+    };
+    let path = new_id.path(&dirs);
+    if path.exists() && !force {
+        let path = path.display();
+        return Err(format!("{path} already exists (use --force to overwrite it)").into());
+    }
+
+    convert_raw(&mut raw, to)?;
+    let proj = raw.into_project()?;
+    let unsupported = match &proj {
+        Project::IconBlocks(_) if to == Program::Mindstorms => {
+            return Err("Mindstorms doesn't have icon blocks".into());
+        }
+        Project::IconBlocks(p) | Project::WordBlocks(p) => {
+            unsupported_blocks(&p.get_scratch()?, to)
+        }
+        Project::Python(_) => lint::lint_project(&new_id, &proj)?
+            .into_iter()
+            .filter(|d| d.code == "runtime-api")
+            .map(|d| format!("line {}: {}", d.location, d.message))
+            .collect(),
+    };
+
+    // Write next to the project first so that a failure doesn't leave a partial file behind.
+    let tmp_path = path.with_extension("mind-meld-convert");
+    proj.raw().write(File::create(&tmp_path)?)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(ConvertResult {
+        id: new_id,
+        path,
+        unsupported,
+    })
+}
+
+/// Updates the manifest and the blocks' opcodes for the other app.
+fn convert_raw(raw: &mut RawProject, to: Program) -> Result<(), Box<dyn Error>> {
+    let manifest = raw
+        .archive
+        .get_file_content("manifest.json")
+        .ok_or("no manifest")?;
+    let mut manifest: Map<String, Value> = serde_json::from_slice(manifest)?;
+    convert_manifest(&mut manifest, to);
+    raw.archive
+        .set_file_content("manifest.json", serde_json::to_vec(&manifest)?);
+
+    // Python projects don't have a scratch.sb3.
+    if let Some(sb3) = raw.archive.get_archive_mut("scratch.sb3") {
+        let project_json = sb3
+            .get_file_content("project.json")
+            .ok_or("no project.json in scratch.sb3!")?;
+        let mut project_json: Value = serde_json::from_slice(project_json)?;
+        convert_opcodes(&mut project_json, to);
+        sb3.set_file_content("project.json", serde_json::to_vec(&project_json)?);
+    }
+    Ok(())
+}
+
+fn convert_manifest(manifest: &mut Map<String, Value>, to: Program) {
+    for field in HUB_FIELDS {
+        manifest.remove(*field);
+    }
+    // Newer versions of the apps record which format the file is in.
+    if let Some(app_type) = manifest.get_mut("appType") {
+        *app_type = json!(to.extension());
+    }
+}
+
+/// Renames the opcodes in project.json that the other app calls differently. It's edited as plain
+/// JSON so that nothing mind-meld doesn't model is lost.
+fn convert_opcodes(project_json: &mut Value, to: Program) {
+    let Some(targets) = project_json["targets"].as_array_mut() else {
+        return;
+    };
+    for target in targets {
+        let Some(blocks) = target["blocks"].as_object_mut() else {
+            continue;
+        };
+        // Top-level variable and list reporters are arrays rather than blocks, and are skipped.
+        for block in blocks.values_mut().filter_map(Value::as_object_mut) {
+            let Some(opcode) = block.get("opcode").and_then(Value::as_str) else {
+                continue;
+            };
+            let Some(new) = translate(opcode, to) else {
+                continue;
+            };
+            // A menu's field is named after the menu's opcode.
+            let old_field = format!("field_{opcode}");
+            if let Some(fields) = block.get_mut("fields").and_then(Value::as_object_mut)
+                && let Some(value) = fields.remove(&old_field)
+            {
+                fields.insert(format!("field_{new}"), value);
+            }
+            block.insert("opcode".to_string(), json!(new));
+        }
+    }
+}
+
+/// Returns the other app's opcode for a block that the apps call differently.
+fn translate(opcode: &str, to: Program) -> Option<&'static str> {
+    OPCODES.iter().find_map(|&(spike, mindstorms)| match to {
+        Program::Mindstorms => (opcode == spike).then_some(mindstorms),
+        Program::Spike => (opcode == mindstorms).then_some(spike),
+    })
+}
+
+/// Returns whether the opcode is the app's name for a block that was renamed for it.
+fn is_translated(opcode: &str, program: Program) -> bool {
+    OPCODES.iter().any(|&(spike, mindstorms)| match program {
+        Program::Mindstorms => opcode == mindstorms,
+        Program::Spike => opcode == spike,
+    })
+}
+
+fn has_extension(program: Program, extension: &str) -> bool {
+    COMMON_EXTENSIONS.contains(&extension)
+        || (program == Program::Spike
+            && (SPIKE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                || extension.starts_with(ICON_PREFIX)))
+}
+
+/// Describes the blocks from extensions that the app doesn't have, in a project whose opcodes were
+/// already converted for the app.
+fn unsupported_blocks(proj: &ScratchProject, to: Program) -> Vec<String> {
+    let mut res = Vec::new();
+    for target in &proj.targets {
+        for (id, block) in target.each_block() {
+            let extension = scratch::opcode_prefix(&block.opcode);
+            if block.shadow
+                || !scratch::is_lego(&block.opcode)
+                || is_translated(&block.opcode, to)
+                || has_extension(to, extension)
+            {
+                continue;
+            }
+            let text = target.describe(id).unwrap_or_else(|| block.opcode.clone());
+            res.push(format!("{}: {text} ({extension} extension)", target.name));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{ArchiveEntry, ArchiveEntryContents, RawArchive};
+
+    fn entry(name: &str, contents: ArchiveEntryContents) -> ArchiveEntry {
+        ArchiveEntry {
+            name: name.to_string(),
+            contents,
+        }
+    }
+
+    fn project_json(raw: &RawProject) -> Value {
+        let sb3 = raw.archive.get_archive("scratch.sb3").unwrap();
+        serde_json::from_slice(sb3.get_file_content("project.json").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_convert_round_trip() {
+        let original = json!({"targets": [
+            {"isStage": false, "name": "Robot", "blocks": {
+                "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b",
+                      "topLevel": true, "inputs": {}, "fields": {}},
+                "b": {"opcode": "flipperlight_lightDisplayImageOn", "parent": "a",
+                      "inputs": {"MATRIX": [1, "m"]}, "fields": {}},
+                "m": {"opcode": "flipperlight_matrix-5x5-brightness-image", "parent": "b",
+                      "shadow": true, "inputs": {},
+                      "fields": {"field_flipperlight_matrix-5x5-brightness-image":
+                                 ["9909999099000009000909990", null]}},
+                "v": [12, "score", "v1", 0, 0]
+            }}
+        ]});
+        let data = |v: &Value| ArchiveEntryContents::Data(serde_json::to_vec(v).unwrap());
+        let mut raw = RawProject {
+            archive: RawArchive {
+                entries: vec![
+                    entry(
+                        "manifest.json",
+                        data(&json!({"type": "word-blocks", "appType": "llsp3"})),
+                    ),
+                    entry(
+                        "scratch.sb3",
+                        ArchiveEntryContents::Archive(RawArchive {
+                            entries: vec![entry("project.json", data(&original))],
+                        }),
+                    ),
+                ],
+            },
+        };
+
+        convert_raw(&mut raw, Program::Mindstorms).unwrap();
+        let converted = project_json(&raw);
+        let blocks = &converted["targets"][0]["blocks"];
+        assert_eq!(blocks["b"]["opcode"], "flipperlight_displayImage");
+        assert_eq!(blocks["m"]["opcode"], "flipperlight_matrix-5x5-image");
+        assert_eq!(
+            blocks["m"]["fields"]["field_flipperlight_matrix-5x5-image"][0],
+            "9909999099000009000909990"
+        );
+        assert_eq!(blocks["a"], original["targets"][0]["blocks"]["a"]);
+        let scratch: ScratchProject = serde_json::from_value(converted).unwrap();
+        assert!(unsupported_blocks(&scratch, Program::Mindstorms).is_empty());
+
+        convert_raw(&mut raw, Program::Spike).unwrap();
+        assert_eq!(project_json(&raw), original);
+        let manifest = raw.archive.get_file_content("manifest.json").unwrap();
+        let manifest: Value = serde_json::from_slice(manifest).unwrap();
+        assert_eq!(manifest["appType"], "llsp3");
+    }
+
+    /// Converts a project saved by the SPIKE app, which uses every block in OPCODES, to
+    /// Mindstorms and back.
+    #[test]
+    fn test_convert_fixture_round_trip() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let dirs = Dirs {
+            mindstorms: fixtures.clone(),
+            spike: fixtures,
+        };
+        let id = ProjectID {
+            program: Program::Spike,
+            name: "convert.llsp3".to_string(),
+        };
+        let mut raw = project::read(&id, &dirs).unwrap().unwrap();
+        let original = project_json(&raw);
+        let opcodes = |v: &Value| -> Vec<String> {
+            let blocks = v["targets"][1]["blocks"].as_object().unwrap();
+            let opcodes = blocks.values().filter_map(|b| b["opcode"].as_str());
+            opcodes.map(str::to_string).collect()
+        };
+        for (spike, _) in OPCODES {
+            assert!(opcodes(&original).contains(&spike.to_string()), "{spike}");
+        }
+
+        convert_raw(&mut raw, Program::Mindstorms).unwrap();
+        let converted = project_json(&raw);
+        for (spike, mindstorms) in OPCODES {
+            assert!(!opcodes(&converted).contains(&spike.to_string()), "{spike}");
+            assert!(
+                opcodes(&converted).contains(&mindstorms.to_string()),
+                "{mindstorms}"
+            );
+        }
+        let scratch: ScratchProject = serde_json::from_value(converted).unwrap();
+        assert!(unsupported_blocks(&scratch, Program::Mindstorms).is_empty());
+
+        convert_raw(&mut raw, Program::Spike).unwrap();
+        assert_eq!(project_json(&raw), original);
+    }
+
+    #[test]
+    fn test_convert_manifest() {
+        let mut manifest = json!({
+            "type": "word-blocks",
+            "appType": "llsp3",
+            "name": "Robot",
+            "slotIndex": 2,
+            "hardware": {"python": {"type": "flipper"}}
+        });
+        convert_manifest(manifest.as_object_mut().unwrap(), Program::Mindstorms);
+        assert_eq!(
+            manifest,
+            json!({"type": "word-blocks", "appType": "lms", "name": "Robot"})
+        );
+    }
+
+    #[test]
+    fn test_unsupported_blocks() {
+        let proj: ScratchProject = serde_json::from_str(
+            r#"{"targets": [
+                {"isStage": false, "name": "Robot", "blocks": {
+                    "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b",
+                          "topLevel": true},
+                    "b": {"opcode": "linegraphmonitor_lineGraphClearData", "parent": "a"}
+                }}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            unsupported_blocks(&proj, Program::Mindstorms),
+            ["Robot: line graph clear data (linegraphmonitor extension)"]
+        );
+        assert!(unsupported_blocks(&proj, Program::Spike).is_empty());
+    }
+}
@@ -0,0 +1,451 @@
+//! Exports block projects as standalone Scratch 3 files, which can be opened in Scratch or
+//! TurboWarp.
+//!
+//! The editors don't know the LEGO extensions, so by default the file is only useful to tools
+//! that do. With stubs, LEGO blocks are replaced with Scratch blocks that read the same way:
+//! stack blocks become custom blocks (with empty definitions), hats become "when flag clicked"
+//! or "when I receive", reporters become variables, and menus become plain text.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use crate::project::scratch::{self, is_lego, Block, BlockEntry, ScratchProject, Target};
+use crate::project::{Project, RawProject};
+
+/// Writes the project to out as a .sb3 file. An existing file is only overwritten if force is set.
+/// Returns the number of blocks that were replaced with stubs.
+pub fn export_sb3(
+    raw: RawProject,
+    stub: bool,
+    out: &Path,
+    force: bool,
+) -> Result<usize, Box<dyn Error>> {
+    if out.exists() && !force {
+        let out = out.display();
+        return Err(format!("{out} already exists (use --force to overwrite it)").into());
+    }
+    let proj = match raw.into_project()? {
+        Project::Python(_) => return Err("only block projects can be exported as .sb3".into()),
+        Project::WordBlocks(p) | Project::IconBlocks(p) => p,
+    };
+    let (project_json, stubbed) = match stub {
+        false => (None, 0),
+        true => {
+            let mut scratch = proj.get_scratch()?;
+            let stubbed = stub_extensions(&mut scratch);
+            (Some(serde_json::to_vec(&scratch)?), stubbed)
+        }
+    };
+    // Write next to the output first so that a failure doesn't leave a partial file behind.
+    let tmp_path = out.with_extension("mind-meld-export");
+    proj.write_sb3(project_json, File::create(&tmp_path)?)?;
+    fs::rename(&tmp_path, out)?;
+    Ok(stubbed)
+}
+
+/// Replaces every LEGO block in the project. Returns the number of blocks replaced, not counting
+/// menus.
+pub fn stub_extensions(proj: &mut ScratchProject) -> usize {
+    let mut broadcasts = BTreeMap::new();
+    let count = proj
+        .targets
+        .iter_mut()
+        .map(|t| stub_target(t, &mut broadcasts))
+        .sum();
+    if let Some(stage) = proj.targets.iter_mut().find(|t| t.is_stage) {
+        stage.broadcasts.extend(broadcasts);
+    }
+    proj.extensions.retain(|e| !is_lego(&format!("{e}_")));
+    if let Some(Value::Array(monitors)) = proj.extra.get_mut("monitors") {
+        monitors.retain(|m| !m["opcode"].as_str().is_some_and(is_lego));
+    }
+    count
+}
+
+fn stub_target(target: &mut Target, broadcasts: &mut BTreeMap<String, String>) -> usize {
+    let ids: Vec<String> = target
+        .each_block()
+        .filter(|(_, b)| !b.shadow && is_lego(&b.opcode))
+        .map(|(id, _)| id.to_string())
+        .collect();
+    // Describe the blocks before changing anything, so that the stubs read like the originals.
+    let texts: BTreeMap<&str, String> = ids
+        .iter()
+        .filter_map(|id| Some((id.as_str(), target.describe(id)?)))
+        .collect();
+
+    // Custom blocks by the LEGO opcode they stand in for, with every argument any call passes.
+    let mut procs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut calls = Vec::new();
+    let mut count = 0;
+    for id in &ids {
+        // Reporters inside reporters are removed along with the outer one.
+        let Some(block) = target.block(id).cloned() else {
+            continue;
+        };
+        let text = texts.get(id.as_str()).cloned().unwrap_or_default();
+        count += 1;
+        if block.is_hat() {
+            stub_hat(target, id, block, text, broadcasts);
+        } else if is_reporter(target, id, &block) {
+            stub_reporter(target, id, block, text);
+        } else {
+            let opcode = block.opcode.clone();
+            let args = procs.entry(opcode.clone()).or_default();
+            for arg_id in stub_call(target, id, block) {
+                if !args.contains(&arg_id) {
+                    args.push(arg_id);
+                }
+            }
+            calls.push((id, opcode));
+        }
+    }
+    // Blocks with the same opcode can have different inputs, e.g. an optional one that's only
+    // there sometimes, so every call is pointed at the definition once all of them are known.
+    for (id, opcode) in calls {
+        if let Some(BlockEntry::Block(block)) = target.blocks.get_mut(id) {
+            block.mutation = Some(call_mutation(&opcode, &procs[&opcode]));
+        }
+    }
+    let procs = procs
+        .into_iter()
+        .map(|(opcode, arg_ids)| (proccode(&opcode, arg_ids.len()), arg_ids))
+        .collect();
+
+    for entry in target.blocks.values_mut() {
+        if let BlockEntry::Block(b) = entry
+            && b.shadow
+            && is_lego(&b.opcode)
+        {
+            let value = b.fields.keys().next().and_then(|f| b.field(f));
+            let value = value.unwrap_or_default().to_string();
+            b.opcode = "text".to_string();
+            b.inputs.clear();
+            b.fields = BTreeMap::from([("TEXT".to_string(), json!([value, null]))]);
+        }
+    }
+    add_definitions(target, procs);
+    count
+}
+
+fn new_block(opcode: &str, parent: Option<&str>, shadow: bool) -> Block {
+    Block {
+        opcode: opcode.to_string(),
+        next: None,
+        parent: parent.map(str::to_string),
+        inputs: BTreeMap::new(),
+        fields: BTreeMap::new(),
+        shadow,
+        top_level: false,
+        mutation: None,
+        extra: Map::new(),
+    }
+}
+
+/// Returns the ids of the blocks that a block's inputs refer to.
+fn input_ids(block: &Block) -> Vec<String> {
+    block
+        .inputs
+        .values()
+        .filter_map(Value::as_array)
+        .flat_map(|input| input.iter().skip(1).filter_map(Value::as_str))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Removes a block along with the blocks in its inputs and the blocks after it.
+fn remove_tree(target: &mut Target, id: &str) {
+    let Some(BlockEntry::Block(block)) = target.blocks.remove(id) else {
+        return;
+    };
+    for child in input_ids(&block).into_iter().chain(block.next) {
+        remove_tree(target, &child);
+    }
+}
+
+/// Returns whether the block is plugged into another block's input.
+fn is_reporter(target: &Target, id: &str, block: &Block) -> bool {
+    let Some(parent) = block.parent.as_deref().and_then(|p| target.block(p)) else {
+        return false;
+    };
+    parent.next.as_deref() != Some(id)
+        && parent.inputs.iter().any(|(name, input)| {
+            !name.starts_with("SUBSTACK")
+                && input
+                    .as_array()
+                    .is_some_and(|i| i.iter().skip(1).any(|v| v.as_str() == Some(id)))
+        })
+}
+
+fn stub_hat(
+    target: &mut Target,
+    id: &str,
+    mut block: Block,
+    text: String,
+    broadcasts: &mut BTreeMap<String, String>,
+) {
+    for child in input_ids(&block) {
+        remove_tree(target, &child);
+    }
+    block.inputs.clear();
+    block.fields.clear();
+    block.mutation = None;
+    if scratch::opcode_name(&block.opcode) == "whenProgramStarts" {
+        block.opcode = "event_whenflagclicked".to_string();
+    } else {
+        let broadcast_id = match broadcasts.iter().find(|(_, name)| **name == text) {
+            Some((broadcast_id, _)) => broadcast_id.clone(),
+            None => {
+                let broadcast_id = format!("mm-stub-broadcast-{}", broadcasts.len());
+                broadcasts.insert(broadcast_id.clone(), text.clone());
+                broadcast_id
+            }
+        };
+        block.opcode = "event_whenbroadcastreceived".to_string();
+        let field = json!([text, broadcast_id]);
+        block.fields.insert("BROADCAST_OPTION".to_string(), field);
+    }
+    target
+        .blocks
+        .insert(id.to_string(), BlockEntry::Block(block));
+}
+
+/// Replaces a reporter with a variable of the same name. Boolean reporters become a comparison
+/// with "true", so that they still fit into conditions.
+fn stub_reporter(target: &mut Target, id: &str, mut block: Block, text: String) {
+    for child in input_ids(&block) {
+        remove_tree(target, &child);
+    }
+    let existing = target.variables.iter().find(|(var_id, var)| {
+        var_id.starts_with("mm-stub-") && var[0].as_str() == Some(text.as_str())
+    });
+    let var_id = match existing {
+        Some((var_id, _)) => var_id.clone(),
+        None => {
+            let var_id = format!("mm-stub-variable-{id}");
+            target.variables.insert(var_id.clone(), json!([text, ""]));
+            var_id
+        }
+    };
+
+    block.inputs.clear();
+    block.fields.clear();
+    block.mutation = None;
+    if scratch::label(&block.opcode).starts_with("is ") {
+        block.opcode = "operator_equals".to_string();
+        let inputs = [
+            ("OPERAND1", json!([3, [12, text, var_id], [10, ""]])),
+            ("OPERAND2", json!([1, [10, "true"]])),
+        ];
+        block.inputs = inputs.map(|(k, v)| (k.to_string(), v)).into();
+    } else {
+        block.opcode = "data_variable".to_string();
+        let field = json!([text, var_id]);
+        block.fields.insert("VARIABLE".to_string(), field);
+    }
+    target
+        .blocks
+        .insert(id.to_string(), BlockEntry::Block(block));
+}
+
+/// Replaces a stack block with a call to a custom block. Fields become text inputs. Returns the
+/// ids of the call's arguments; the call's mutation is set by call_mutation.
+fn stub_call(target: &mut Target, id: &str, mut block: Block) -> Vec<String> {
+    let mut arg_ids: Vec<String> = block
+        .inputs
+        .keys()
+        .filter(|name| !name.starts_with("SUBSTACK"))
+        .cloned()
+        .collect();
+    for (name, value) in std::mem::take(&mut block.fields) {
+        let text = value[0].as_str().unwrap_or_default().to_string();
+        let shadow_id = format!("{id}-{name}");
+        let mut shadow = new_block("text", Some(id), true);
+        shadow
+            .fields
+            .insert("TEXT".to_string(), json!([text, null]));
+        target
+            .blocks
+            .insert(shadow_id.clone(), BlockEntry::Block(shadow));
+        block.inputs.insert(name.clone(), json!([1, shadow_id]));
+        arg_ids.push(name);
+    }
+
+    block.opcode = "procedures_call".to_string();
+    target
+        .blocks
+        .insert(id.to_string(), BlockEntry::Block(block));
+    arg_ids
+}
+
+/// The mutation that makes a block a call to the custom block for opcode.
+fn call_mutation(opcode: &str, arg_ids: &[String]) -> Value {
+    json!({
+        "tagName": "mutation",
+        "children": [],
+        "proccode": proccode(opcode, arg_ids.len()),
+        "argumentids": serde_json::to_string(arg_ids).unwrap_or_default(),
+        "warp": "false",
+    })
+}
+
+fn proccode(opcode: &str, args: usize) -> String {
+    let mut res = scratch::label(opcode);
+    for _ in 0..args {
+        res.push_str(" %s");
+    }
+    res
+}
+
+/// Adds an empty definition for each custom block, to the right of the existing scripts.
+fn add_definitions(target: &mut Target, procs: BTreeMap<String, Vec<String>>) {
+    let x = target
+        .each_block()
+        .filter(|(_, b)| b.top_level)
+        .filter_map(|(_, b)| b.extra.get("x").and_then(Value::as_f64))
+        .fold(0.0, f64::max)
+        + 600.0;
+    for (i, (proccode, arg_ids)) in procs.into_iter().enumerate() {
+        let def_id = format!("mm-stub-define-{i}");
+        let proto_id = format!("{def_id}-prototype");
+        let arg_names: Vec<String> = arg_ids.iter().map(|a| a.to_lowercase()).collect();
+
+        let mut def = new_block("procedures_definition", None, false);
+        def.top_level = true;
+        def.inputs
+            .insert("custom_block".to_string(), json!([1, proto_id]));
+        def.extra.insert("x".to_string(), json!(x));
+        def.extra.insert("y".to_string(), json!(i * 120));
+
+        let mut proto = new_block("procedures_prototype", Some(&def_id), true);
+        for (arg_id, name) in arg_ids.iter().zip(&arg_names) {
+            let reporter_id = format!("{proto_id}-{arg_id}");
+            let mut reporter = new_block("argument_reporter_string_number", Some(&proto_id), true);
+            reporter
+                .fields
+                .insert("VALUE".to_string(), json!([name, null]));
+            target
+                .blocks
+                .insert(reporter_id.clone(), BlockEntry::Block(reporter));
+            proto.inputs.insert(arg_id.clone(), json!([1, reporter_id]));
+        }
+        let to_string = |v: &Vec<String>| serde_json::to_string(v).unwrap_or_default();
+        proto.mutation = Some(json!({
+            "tagName": "mutation",
+            "children": [],
+            "proccode": proccode,
+            "argumentids": to_string(&arg_ids),
+            "argumentnames": to_string(&arg_names),
+            "argumentdefaults": to_string(&vec![String::new(); arg_ids.len()]),
+            "warp": "false",
+        }));
+
+        target.blocks.insert(def_id, BlockEntry::Block(def));
+        target.blocks.insert(proto_id, BlockEntry::Block(proto));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stub_extensions() {
+        let mut proj: ScratchProject = serde_json::from_str(
+            r#"{"targets": [
+                {"isStage": true, "name": "Stage", "blocks": {}},
+                {"isStage": false, "name": "Robot", "blocks": {
+                    "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b",
+                          "topLevel": true, "x": 10, "y": 10},
+                    "b": {"opcode": "control_if", "parent": "a", "next": "d",
+                          "inputs": {"CONDITION": [2, "c"]}},
+                    "c": {"opcode": "flippersensors_isColor", "parent": "b",
+                          "inputs": {"PORT": [1, "p"]}, "fields": {"COLOR": ["red", null]}},
+                    "p": {"opcode": "flippersensors_color-sensor-selector", "parent": "c",
+                          "shadow": true, "fields": {"field_flippersensors_color-sensor-selector": ["B", null]}},
+                    "d": {"opcode": "flippermotor_motorStartDirection", "parent": "b",
+                          "inputs": {"PORT": [1, "m"], "SPEED": [3, "r", [4, "50"]]},
+                          "fields": {"DIRECTION": ["clockwise", null]}},
+                    "m": {"opcode": "flippermotor_multiple-port-selector", "parent": "d",
+                          "shadow": true, "fields": {"field_flippermotor_multiple-port-selector": ["A", null]}},
+                    "r": {"opcode": "flippermotor_speed", "parent": "d", "inputs": {"PORT": [1, "q"]}},
+                    "q": {"opcode": "flippermotor_single-motor-selector", "parent": "r",
+                          "shadow": true, "fields": {"field_flippermotor_single-motor-selector": ["C", null]}},
+                    "h": {"opcode": "flipperevents_whenButton", "topLevel": true,
+                          "fields": {"BUTTON": ["left", null]}}
+                }}
+            ],
+            "extensions": ["flippermotor", "flippersensors", "pen"]}"#,
+        )
+        .unwrap();
+        assert_eq!(stub_extensions(&mut proj), 5);
+        assert_eq!(proj.extensions, ["pen"]);
+        let robot = &proj.targets[1];
+        for (id, block) in robot.each_block() {
+            assert!(!is_lego(&block.opcode), "{id} is still {}", block.opcode);
+        }
+
+        assert_eq!(robot.block("a").unwrap().opcode, "event_whenflagclicked");
+        let h = robot.block("h").unwrap();
+        assert_eq!(h.opcode, "event_whenbroadcastreceived");
+        assert_eq!(h.field("BROADCAST_OPTION"), Some("when button [left]"));
+        assert_eq!(proj.targets[0].broadcasts.len(), 1);
+
+        let c = robot.block("c").unwrap();
+        assert_eq!(c.opcode, "operator_equals");
+        let var_id = c.inputs["OPERAND1"][1][2].as_str().unwrap();
+        assert_eq!(robot.variables[var_id][0], "is color [B] [red]");
+        assert!(robot.block("p").is_none());
+        assert!(robot.block("q").is_none());
+        let r = robot.block("r").unwrap();
+        assert_eq!(r.opcode, "data_variable");
+        assert_eq!(r.field("VARIABLE"), Some("speed [C]"));
+
+        let d = robot.block("d").unwrap();
+        assert_eq!(d.opcode, "procedures_call");
+        let proccode = "motor start direction %s %s %s";
+        assert_eq!(d.mutation.as_ref().unwrap()["proccode"], proccode);
+        assert_eq!(
+            robot.block("d-DIRECTION").unwrap().field("TEXT"),
+            Some("clockwise")
+        );
+        assert_eq!(robot.block("m").unwrap().field("TEXT"), Some("A"));
+        let def = robot.block("mm-stub-define-0").unwrap();
+        assert_eq!(def.extra["x"], 610.0);
+        let proto = robot.block("mm-stub-define-0-prototype").unwrap();
+        assert_eq!(proto.mutation.as_ref().unwrap()["proccode"], proccode);
+        assert_eq!(proto.inputs.len(), 3);
+    }
+
+    #[test]
+    fn test_stub_calls_with_different_inputs() {
+        let mut proj: ScratchProject = serde_json::from_str(
+            r#"{"targets": [
+                {"isStage": false, "name": "Robot", "blocks": {
+                    "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b",
+                          "topLevel": true},
+                    "b": {"opcode": "flippermotor_motorStop", "parent": "a", "next": "c",
+                          "inputs": {"PORT": [1, [10, "A"]]}},
+                    "c": {"opcode": "flippermotor_motorStop", "parent": "b",
+                          "fields": {"STOP": ["brake", null]}}
+                }}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(stub_extensions(&mut proj), 3);
+        let robot = &proj.targets[0];
+        let args = r#"["PORT","STOP"]"#;
+        for id in ["b", "c"] {
+            let mutation = robot.block(id).unwrap().mutation.as_ref().unwrap();
+            assert_eq!(mutation["proccode"], "motor stop %s %s");
+            assert_eq!(mutation["argumentids"], args);
+        }
+        let proto = robot.block("mm-stub-define-0-prototype").unwrap();
+        assert_eq!(proto.mutation.as_ref().unwrap()["argumentids"], args);
+        assert!(robot.block("mm-stub-define-1").is_none());
+    }
+}
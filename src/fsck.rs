@@ -0,0 +1,228 @@
+//! Checks that the projects in a store can be read back: that every project has a manifest, that
+//! its program can be parsed, and that the assets its blocks refer to are there.
+
+use std::error::Error;
+use std::time::SystemTime;
+
+use crate::project::scratch::ScratchProject;
+use crate::project::{Project, ProjectID, RawArchive, RawProject};
+use crate::store::{Revision, Store};
+
+/// Something wrong in a revision of the store.
+pub struct Problem {
+    /// The project with the problem, or None if it's with the revision as a whole.
+    pub id: Option<ProjectID>,
+    pub message: String,
+}
+
+/// The problems found in one revision.
+pub struct RevisionProblems {
+    /// The commit hash and date, or None for the latest revision.
+    pub revision: Option<(String, SystemTime)>,
+    pub problems: Vec<Problem>,
+}
+
+pub struct FsckResult {
+    pub revisions: usize,
+    pub projects: usize,
+    /// Only revisions that have problems.
+    pub problems: Vec<RevisionProblems>,
+}
+
+/// Checks every project in the latest revision or, if all_revisions is set, every version of
+/// every project in the store's history. Each commit only contributes the projects that it
+/// changed, so every version is checked once.
+pub fn fsck(store: &Store, all_revisions: bool) -> Result<FsckResult, Box<dyn Error>> {
+    let mut res = FsckResult {
+        revisions: 0,
+        projects: 0,
+        problems: Vec::new(),
+    };
+    let revisions = match all_revisions {
+        false => vec![(Revision::Latest, None, None)],
+        true => store
+            .history()?
+            .into_iter()
+            .map(|c| (c.revision, Some((c.hash, c.date)), Some(c.changed_projects)))
+            .collect(),
+    };
+    for (revision, info, ids) in revisions {
+        let (projects, problems) = check_revision(store, &revision, ids);
+        res.revisions += 1;
+        res.projects += projects;
+        if !problems.is_empty() {
+            res.problems.push(RevisionProblems {
+                revision: info,
+                problems,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// Checks the given projects, or all of them if ids is None, and returns how many were checked.
+fn check_revision(
+    store: &Store,
+    revision: &Revision,
+    ids: Option<Vec<ProjectID>>,
+) -> (usize, Vec<Problem>) {
+    let revision_problem = |message: String| Problem { id: None, message };
+    let mut problems: Vec<Problem> = match store.unexpected_entries(revision) {
+        Ok(entries) => entries.into_iter().map(revision_problem).collect(),
+        Err(e) => vec![revision_problem(e.to_string())],
+    };
+    let ids = match ids.map_or_else(|| store.project_ids_at(revision), Ok) {
+        Ok(ids) => ids,
+        Err(e) => {
+            problems.push(revision_problem(format!("can't list projects: {e}")));
+            return (0, problems);
+        }
+    };
+
+    let mut checked = 0;
+    for id in ids {
+        let messages = match store.read_project(&id, revision) {
+            // Removed in this commit.
+            Ok(None) => continue,
+            Ok(Some(raw)) => check_project(raw),
+            Err(e) => vec![format!("can't read project: {e}")],
+        };
+        checked += 1;
+        problems.extend(messages.into_iter().map(|message| Problem {
+            id: Some(id.clone()),
+            message,
+        }));
+    }
+    (checked, problems)
+}
+
+/// Describes what's wrong with the project, if anything.
+pub fn check_project(raw: RawProject) -> Vec<String> {
+    if raw.archive.get_file_content("manifest.json").is_none() {
+        return vec!["no manifest.json".to_string()];
+    }
+    let proj = match raw.into_project() {
+        Ok(proj) => proj,
+        Err(e) => return vec![format!("invalid manifest.json: {e}")],
+    };
+    match &proj {
+        Project::Python(p) => {
+            if proj
+                .raw()
+                .archive
+                .get_file_content("projectbody.json")
+                .is_none()
+            {
+                return vec!["no projectbody.json".to_string()];
+            }
+            match p.get_source() {
+                Ok(_) => Vec::new(),
+                Err(e) => vec![format!("invalid projectbody.json: {e}")],
+            }
+        }
+        Project::WordBlocks(p) | Project::IconBlocks(p) => {
+            let Some(sb3) = proj.raw().archive.get_archive("scratch.sb3") else {
+                return vec!["no scratch.sb3".to_string()];
+            };
+            if sb3.get_file_content("project.json").is_none() {
+                return vec!["no project.json in scratch.sb3".to_string()];
+            }
+            match p.get_scratch() {
+                Ok(scratch) => missing_assets(&scratch, sb3),
+                Err(e) => vec![format!("invalid project.json: {e}")],
+            }
+        }
+    }
+}
+
+/// Describes the costumes and sounds whose files aren't in scratch.sb3.
+fn missing_assets(scratch: &ScratchProject, sb3: &RawArchive) -> Vec<String> {
+    let mut res = Vec::new();
+    for target in &scratch.targets {
+        for (kind, assets) in [("costume", &target.costumes), ("sound", &target.sounds)] {
+            for asset in assets {
+                let file = match &asset.md5ext {
+                    Some(md5ext) => md5ext.clone(),
+                    None => format!("{}.{}", asset.asset_id, asset.data_format),
+                };
+                if sb3.get_file_content(&file).is_none() {
+                    res.push(format!(
+                        "{}: {kind} \"{}\" is missing {file}",
+                        target.name, asset.name
+                    ));
+                }
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{ArchiveEntry, ArchiveEntryContents};
+
+    fn archive(files: Vec<(&str, ArchiveEntryContents)>) -> RawArchive {
+        let entries = files
+            .into_iter()
+            .map(|(name, contents)| ArchiveEntry {
+                name: name.to_string(),
+                contents,
+            })
+            .collect();
+        RawArchive { entries }
+    }
+
+    fn data(s: &str) -> ArchiveEntryContents {
+        ArchiveEntryContents::Data(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_check_project() {
+        let project_json = r#"{"targets": [{"isStage": false, "name": "Robot",
+            "costumes": [{"name": "hub", "assetId": "abc", "md5ext": "abc.svg", "dataFormat": "svg"}],
+            "sounds": [{"name": "beep", "assetId": "def", "dataFormat": "wav"}]}]}"#;
+        let blocks = |files: Vec<(&str, ArchiveEntryContents)>| RawProject {
+            archive: archive(vec![
+                ("manifest.json", data(r#"{"type": "word-blocks"}"#)),
+                ("scratch.sb3", ArchiveEntryContents::Archive(archive(files))),
+            ]),
+        };
+        assert!(check_project(blocks(vec![
+            ("project.json", data(project_json)),
+            ("abc.svg", data("<svg/>")),
+            ("def.wav", data("")),
+        ]))
+        .is_empty());
+        assert_eq!(
+            check_project(blocks(vec![("project.json", data(project_json))])),
+            [
+                "Robot: costume \"hub\" is missing abc.svg",
+                "Robot: sound \"beep\" is missing def.wav"
+            ]
+        );
+        assert_eq!(
+            check_project(blocks(vec![])),
+            ["no project.json in scratch.sb3"]
+        );
+
+        let python = |files| RawProject {
+            archive: archive(files),
+        };
+        assert_eq!(
+            check_project(python(vec![(
+                "manifest.json",
+                data(r#"{"type": "python"}"#)
+            )])),
+            ["no projectbody.json"]
+        );
+        assert_eq!(
+            check_project(python(vec![
+                ("manifest.json", data(r#"{"type": "python"}"#)),
+                ("projectbody.json", data(r#"{"main": "import hub"}"#)),
+            ])),
+            Vec::<String>::new()
+        );
+        assert_eq!(check_project(python(vec![])), ["no manifest.json"]);
+    }
+}
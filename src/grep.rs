@@ -0,0 +1,251 @@
+//! Searches the text of projects: Python source, and for block projects the blocks (by label,
+//! opcode, and argument values), variable, list, and broadcast names, and comments.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::time::SystemTime;
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::dirs::Dirs;
+use crate::project::scratch::{BlockView, ScratchProject};
+use crate::project::{self, Program, Project, ProjectID};
+use crate::store::{self, Store};
+
+/// A line of a project that matched.
+pub struct Match {
+    /// The line number for Python, or the sprite (or stage) for blocks.
+    pub location: String,
+    pub text: String,
+}
+
+impl Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.text)
+    }
+}
+
+/// The matches in one version of a project.
+pub struct ProjectMatches {
+    pub id: ProjectID,
+    /// The commit hash and date, or None for the local copy.
+    pub revision: Option<(String, SystemTime)>,
+    pub matches: Result<Vec<Match>, Box<dyn Error>>,
+}
+
+/// Searches the local copy of every tracked project.
+pub fn grep_local(
+    cfg: &Config,
+    pattern: &Regex,
+    program: Option<Program>,
+) -> Result<Vec<ProjectMatches>, Box<dyn Error>> {
+    let dirs = Dirs::new(cfg)?;
+    let (stores, store_errs) = store::open_all(&cfg.stores);
+    let (proj_ids, id_errs) = store::all_project_ids(&stores);
+    if let Some((st, e)) = store_errs.into_iter().chain(id_errs).next() {
+        return Err(format!("{st}: {e}").into());
+    }
+    let mut proj_ids: Vec<ProjectID> = proj_ids
+        .into_iter()
+        .filter(|id| program.is_none_or(|p| p == id.program))
+        .collect();
+    proj_ids.sort_by_key(|id| id.to_string());
+
+    let mut res = Vec::new();
+    for id in proj_ids {
+        let matches = match project::read(&id, &dirs) {
+            Ok(None) => continue,
+            Ok(Some(raw)) => raw
+                .into_project()
+                .and_then(|proj| grep_project(&proj, pattern)),
+            Err(e) => Err(e),
+        };
+        res.push(ProjectMatches {
+            id,
+            revision: None,
+            matches,
+        });
+    }
+    res.retain(has_results);
+    Ok(res)
+}
+
+/// Searches every version of every project in the store, newest first. Each commit only
+/// contributes the projects that it changed, so every version is searched once.
+pub fn grep_history(
+    store: &Store,
+    pattern: &Regex,
+    program: Option<Program>,
+) -> Result<Vec<ProjectMatches>, Box<dyn Error>> {
+    let mut res = Vec::new();
+    for commit in store.history()? {
+        for id in commit.changed_projects {
+            if program.is_some_and(|p| p != id.program) {
+                continue;
+            }
+            let matches = match store.read_project(&id, &commit.revision) {
+                // Removed in this commit.
+                Ok(None) => continue,
+                Ok(Some(raw)) => raw
+                    .into_project()
+                    .and_then(|proj| grep_project(&proj, pattern)),
+                Err(e) => Err(e),
+            };
+            res.push(ProjectMatches {
+                id,
+                revision: Some((commit.hash.clone(), commit.date)),
+                matches,
+            });
+        }
+    }
+    res.retain(has_results);
+    Ok(res)
+}
+
+fn has_results(m: &ProjectMatches) -> bool {
+    m.matches.as_ref().map_or(true, |m| !m.is_empty())
+}
+
+pub fn grep_project(proj: &Project, pattern: &Regex) -> Result<Vec<Match>, Box<dyn Error>> {
+    let lines = match proj {
+        Project::Python(p) => python_lines(&p.get_source()?),
+        Project::WordBlocks(p) | Project::IconBlocks(p) => blocks_lines(&p.get_scratch()?),
+    };
+    Ok(search(lines, pattern))
+}
+
+fn search(lines: Vec<Line>, pattern: &Regex) -> Vec<Match> {
+    lines
+        .into_iter()
+        .filter(|l| {
+            l.hidden
+                .iter()
+                .chain([&l.text])
+                .any(|s| pattern.is_match(s))
+        })
+        .map(|l| Match {
+            text: match l.hidden.iter().find(|s| pattern.is_match(s)) {
+                // Show what matched, since it isn't part of the text.
+                Some(hidden) if !pattern.is_match(&l.text) => format!("{} ({hidden})", l.text),
+                _ => l.text,
+            },
+            location: l.location,
+        })
+        .collect()
+}
+
+/// A line of searchable text.
+struct Line {
+    location: String,
+    text: String,
+    /// Also searched, but not shown unless it's what matched (e.g. a block's opcode).
+    hidden: Option<String>,
+}
+
+fn python_lines(src: &str) -> Vec<Line> {
+    src.lines()
+        .enumerate()
+        .map(|(i, line)| Line {
+            location: (i + 1).to_string(),
+            text: line.trim().to_string(),
+            hidden: None,
+        })
+        .collect()
+}
+
+fn blocks_lines(proj: &ScratchProject) -> Vec<Line> {
+    let mut res = Vec::new();
+    for target in &proj.targets {
+        let mut push = |text: String, hidden: Option<String>| {
+            res.push(Line {
+                location: target.name.clone(),
+                text,
+                hidden,
+            })
+        };
+        let name_of = |v: &serde_json::Value| {
+            v.as_array()
+                .and_then(|v| v.first())
+                .and_then(|n| n.as_str())
+                .map(str::to_string)
+        };
+        for name in target.variables.values().filter_map(name_of) {
+            push(format!("variable {name}"), None);
+        }
+        for name in target.lists.values().filter_map(name_of) {
+            push(format!("list {name}"), None);
+        }
+        for name in target.broadcasts.values() {
+            push(format!("broadcast {name}"), None);
+        }
+        for script in target.scripts() {
+            stack_lines(&script, &mut push);
+        }
+        for comment in target.comments.values() {
+            for line in comment.text.lines().filter(|l| !l.trim().is_empty()) {
+                push(format!("comment: {}", line.trim()), None);
+            }
+        }
+    }
+    res
+}
+
+fn stack_lines(stack: &[BlockView], push: &mut impl FnMut(String, Option<String>)) {
+    for view in stack {
+        push(view.to_string(), Some(view.block.opcode.clone()));
+        for substack in &view.substacks {
+            stack_lines(substack, push);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search_text(lines: Vec<Line>, pattern: &str) -> Vec<String> {
+        let matches = search(lines, &Regex::new(pattern).unwrap());
+        matches.into_iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn test_python() {
+        let lines = python_lines("import motor_pair\n\n    motor_pair.move_tank(1, 2)\n");
+        assert_eq!(
+            search_text(lines, "move_tank"),
+            ["3: motor_pair.move_tank(1, 2)"]
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let proj: ScratchProject = serde_json::from_str(
+            r#"{"targets": [
+                {"isStage": true, "name": "Stage", "blocks": {},
+                 "broadcasts": {"b1": "wall follower"}, "variables": {"v1": ["speed", 0]}},
+                {"isStage": false, "name": "Robot", "blocks": {
+                    "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b",
+                          "topLevel": true, "inputs": {}, "fields": {}},
+                    "b": {"opcode": "flippermove_move", "parent": "a", "topLevel": false,
+                          "inputs": {"VALUE": [1, [4, "10"]]}, "fields": {"UNIT": ["cm", null]}}
+                 },
+                 "comments": {"c1": {"blockId": "b", "text": "Follow the wall\nuntil the end"}}}
+            ]}"#,
+        )
+        .unwrap();
+        let search = |pattern| search_text(blocks_lines(&proj), pattern);
+        assert_eq!(
+            search("(?i)wall"),
+            [
+                "Stage: broadcast wall follower",
+                "Robot: comment: Follow the wall"
+            ]
+        );
+        assert_eq!(
+            search("flippermove_"),
+            ["Robot: move [10] [cm] (flippermove_move)"]
+        );
+        assert_eq!(search("speed"), ["Stage: variable speed"]);
+    }
+}
@@ -0,0 +1,454 @@
+//! Imports project files from outside the apps' directories (e.g. old backups) into stores.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::{Config, StoreConfig};
+use crate::pending::{self, Queue};
+use crate::project::{self, Program, ProjectID, RawProject};
+use crate::store::{self, Revision};
+
+/// Where the date of an imported version comes from.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DateSource {
+    /// When the project was last saved, according to its manifest (or the file's modification
+    /// time if the manifest doesn't say)
+    Manifest,
+    /// The file's modification time
+    Mtime,
+    /// The time of the import
+    Now,
+}
+
+/// A file to import.
+pub struct ImportFile {
+    pub path: PathBuf,
+    pub id: ProjectID,
+    /// None if the version should be dated at the time of the import.
+    pub date: Option<SystemTime>,
+}
+
+/// Finds the project files in paths, searching directories recursively, and returns them oldest
+/// first. Unless program is given, it's based on each file's extension.
+///
+/// If group is set, files are treated as backups: dates, version numbers, and markers like
+/// "copy" are removed from their names to find which project they're a version of, and a date in
+/// the name takes precedence over the date source.
+pub fn scan(
+    paths: &[PathBuf],
+    program: Option<Program>,
+    dates: DateSource,
+    group: bool,
+) -> Result<Vec<ImportFile>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        match fs::metadata(path)?.is_dir() {
+            true => find_projects(path, &mut files)?,
+            false => files.push(path.clone()),
+        }
+    }
+
+    let mut res = Vec::new();
+    for path in files {
+        let name = path
+            .file_name()
+            .ok_or_else(|| format!("not a file: {}", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        let program = program
+            .or_else(|| Program::from_file_name(&name))
+            .ok_or_else(|| {
+                format!(
+                    "can't tell which app {} is from, use --program",
+                    path.display()
+                )
+            })?;
+        let (name, name_date, version) = match group {
+            true => {
+                let backup = parse_backup_name(&name);
+                let name = format!("{}.{}", backup.name, program.extension());
+                (name, backup.date, backup.version)
+            }
+            false => (name, None, None),
+        };
+        let date = match (name_date, dates) {
+            (Some(date), _) => Some(date),
+            (None, DateSource::Now) => None,
+            (None, DateSource::Mtime) => Some(fs::metadata(&path)?.modified()?),
+            (None, DateSource::Manifest) => match manifest_date(&project::read_file(&path)?) {
+                Some(date) => Some(date),
+                None => Some(fs::metadata(&path)?.modified()?),
+            },
+        };
+        let file = ImportFile {
+            path,
+            id: ProjectID { program, name },
+            date,
+        };
+        res.push((file, version));
+    }
+    // Files without dates are committed now, so they go last. Sorting is stable, so they stay in
+    // the order they were given.
+    res.sort_by_key(|(f, version)| (f.date.is_none(), f.date, *version));
+    Ok(res.into_iter().map(|(f, _)| f).collect())
+}
+
+static DATE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(\d{4})[-_.]?(\d{2})[-_.]?(\d{2})(?:[T _-]?(\d{2})[-_.:h]?(\d{2})(?:[-_.:m]?(\d{2}))?)?",
+    )
+    .expect("valid date pattern")
+});
+
+static VERSION_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\bv(?:ersion)?[ ._-]?(\d+)\b").expect("valid version pattern")
+});
+
+/// A marker at the end of a name, like " - copy", " (2)", "_backup 3", or ".bak".
+static MARKER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?i)(?:\(\s*(?:(?:copy|backup|bak|old)\s*)?\d*\s*\)",
+        r"|(?:^|[\s_.-])(?:copy|backup|bak|old)(?:\s*\d+)?)[\s_.-]*$",
+    ))
+    .expect("valid marker pattern")
+});
+
+/// What a backup's file name says about it.
+#[derive(Debug, PartialEq)]
+struct BackupName {
+    /// The name of the project, without an extension.
+    name: String,
+    date: Option<SystemTime>,
+    version: Option<u32>,
+}
+
+/// Parses names like "Robot v3 2023-10-02.llsp3", "Robot_20231002_1530.llsp3", or
+/// "Robot (copy 2).llsp3". Dates in the name are in local time.
+fn parse_backup_name(file_name: &str) -> BackupName {
+    let stem = Path::new(file_name).file_stem().unwrap_or_default();
+    let mut stem = stem.to_string_lossy().to_string();
+
+    let mut date = None;
+    if let Some(caps) = DATE_RE.captures(&stem) {
+        let num = |i: usize| caps.get(i).map_or(0, |m| m.as_str().parse().unwrap_or(0));
+        let datetime = NaiveDate::from_ymd_opt(num(1) as i32, num(2), num(3))
+            .and_then(|d| d.and_hms_opt(num(4), num(5), num(6)));
+        if let Some(datetime) = datetime {
+            date = Local
+                .from_local_datetime(&datetime)
+                .earliest()
+                .map(SystemTime::from);
+            stem.replace_range(caps.get(0).expect("whole match").range(), " ");
+        }
+    }
+
+    let mut version = None;
+    if let Some(caps) = VERSION_RE.captures(&stem) {
+        version = caps[1].parse().ok();
+        stem.replace_range(caps.get(0).expect("whole match").range(), " ");
+    }
+
+    // Markers are only taken off the end, so that names like "Old Robot" are left alone. A name
+    // that's nothing but a marker (e.g. "Backup") is kept too.
+    let mut stem = stem.trim_end().to_string();
+    while let Some(m) = MARKER_RE.find(&stem)
+        && m.start() > 0
+    {
+        stem.truncate(m.start());
+    }
+    let name = stem
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '(' || c == ')')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let name = name.trim_matches(|c: char| c == '-' || c == '.' || c == ' ');
+    BackupName {
+        name: name.to_string(),
+        date,
+        version,
+    }
+}
+
+/// Adds the files under dir that look like projects, in name order.
+fn find_projects(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_projects(&path, files)?;
+        } else if Program::from_file_name(&path.to_string_lossy()).is_some() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ManifestDates {
+    lastsaved: Option<String>,
+    created: Option<String>,
+}
+
+/// Returns when the project was last saved (or created), according to its manifest.
+pub fn manifest_date(raw: &RawProject) -> Option<SystemTime> {
+    let manifest = raw.archive.get_file_content("manifest.json")?;
+    let dates: ManifestDates = serde_json::from_slice(manifest).ok()?;
+    let date = dates.lastsaved.or(dates.created)?;
+    Some(DateTime::parse_from_rfc3339(&date).ok()?.into())
+}
+
+pub struct ImportResult {
+    pub file: ImportFile,
+    /// The result of committing the file to each store, or an error if it couldn't be read.
+    pub store_results: Result<Vec<(StoreConfig, store::CommitResult)>, Box<dyn Error>>,
+    /// Stores that the file was queued for because they couldn't be opened, a commit to them
+    /// failed, or earlier commits to them are still waiting.
+    pub queued: Result<Vec<StoreConfig>, Box<dyn Error>>,
+}
+
+pub struct ImportResults {
+    pub files: Vec<ImportResult>,
+    /// Stores that couldn't be opened. Files are queued for them.
+    pub open_errors: Vec<(StoreConfig, Box<dyn Error>)>,
+    /// Projects whose latest version in a store was committed again because the imported
+    /// versions are older, with the result.
+    pub restored: Vec<(StoreConfig, Vec<ProjectID>, store::CommitResult)>,
+}
+
+/// Commits each file to every store, one commit per file. Stores that don't get a file have it
+/// queued, like mm commit does.
+///
+/// Imported versions go on top of each store's history even when they're dated earlier, so
+/// afterwards the versions that were latest before are committed again if every version of the
+/// project that was imported is older than the store's newest commit. Stores that the files were
+/// queued for get the latest versions from the next mm commit, which follows the queued commits.
+pub fn import(cfg: &Config, files: Vec<ImportFile>) -> Result<ImportResults, Box<dyn Error>> {
+    let (stores, open_errors) = store::open_all(&cfg.stores);
+    let mut queue = pending::load(cfg);
+    let blocked = queue.as_ref().map(Queue::blocked).unwrap_or_default();
+    let mut to_queue: Vec<StoreConfig> = open_errors.iter().map(|(st, _)| st.clone()).collect();
+    to_queue.extend(
+        stores
+            .iter()
+            .filter(|(st, _)| blocked.contains(&st.path))
+            .map(|(st, _)| st.clone()),
+    );
+
+    // The newest imported date of each project, or None if one of its versions is dated now.
+    let mut newest: HashMap<ProjectID, Option<SystemTime>> = HashMap::new();
+    for file in &files {
+        let date = newest.entry(file.id.clone()).or_insert(file.date);
+        *date = date.zip(file.date).map(|(a, b)| a.max(b));
+    }
+    let mut before = Vec::new();
+    for (st, store) in &stores {
+        let head = store.head()?.map(|c| c.date);
+        let mut latest = Vec::new();
+        for (id, date) in &newest {
+            let older = date.zip(head).is_some_and(|(date, head)| date < head);
+            if let Some(raw) = store.read_project(id, &Revision::Latest)?
+                && older
+            {
+                latest.push((id.clone(), raw));
+            }
+        }
+        before.push((st, store, latest));
+    }
+
+    let mut res = Vec::new();
+    for file in files {
+        let mut queued = Ok(Vec::new());
+        let store_results = project::read_file(&file.path).map(|raw| {
+            let message = format!("Import {} from {}", file.id, file.path.display());
+            let update = vec![(file.id.clone(), raw)];
+            let mut store_results = Vec::new();
+            for (st, store) in &stores {
+                if to_queue.iter().any(|q| q.path == st.path) {
+                    continue;
+                }
+                let res = match file.date {
+                    None => store.commit(&update, &message),
+                    Some(date) => store.commit_at(&update, &message, date),
+                };
+                // Later files have to wait too, to keep the history in order.
+                if res.is_err() {
+                    to_queue.push(st.clone());
+                }
+                store_results.push((st.clone(), res));
+            }
+            if !to_queue.is_empty() {
+                let paths = to_queue.iter().map(|st| st.path.clone()).collect();
+                let date = file.date.unwrap_or_else(SystemTime::now);
+                queued = match &mut queue {
+                    Ok(queue) => queue
+                        .add(paths, &update, &message, date)
+                        .map(|_| to_queue.clone()),
+                    Err(e) => Err(e.to_string().into()),
+                };
+            }
+            store_results
+        });
+        res.push(ImportResult {
+            file,
+            store_results,
+            queued,
+        });
+    }
+
+    let mut restored = Vec::new();
+    for (st, store, mut latest) in before {
+        if to_queue.iter().any(|q| q.path == st.path) {
+            continue;
+        }
+        latest.retain(|(id, raw)| {
+            let current = store.read_project(id, &Revision::Latest).ok().flatten();
+            current.is_none_or(|c| c.hash() != raw.hash())
+        });
+        if latest.is_empty() {
+            continue;
+        }
+        latest.sort_by_key(|(id, _)| id.to_string());
+        let ids = latest.iter().map(|(id, _)| id.clone()).collect();
+        let message = "Restore latest versions after importing older ones";
+        restored.push((st.clone(), ids, store.commit(&latest, message)));
+    }
+    Ok(ImportResults {
+        files: res,
+        open_errors,
+        restored,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::test_util::{id, python};
+    use crate::project::{ArchiveEntry, ArchiveEntryContents, RawArchive};
+    use std::fs::File;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn with_manifest(manifest: &str) -> RawProject {
+        RawProject {
+            archive: RawArchive {
+                entries: vec![ArchiveEntry {
+                    name: "manifest.json".to_string(),
+                    contents: ArchiveEntryContents::Data(manifest.as_bytes().to_vec()),
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn test_manifest_date() {
+        let raw = with_manifest(
+            r#"{"created": "2023-10-01T10:00:00.000Z", "lastsaved": "2023-10-02T12:30:00.000Z"}"#,
+        );
+        let date = DateTime::parse_from_rfc3339("2023-10-02T12:30:00Z").unwrap();
+        assert_eq!(manifest_date(&raw), Some(date.into()));
+
+        let raw = with_manifest(r#"{"created": "2023-10-01T10:00:00.000Z"}"#);
+        let date = DateTime::parse_from_rfc3339("2023-10-01T10:00:00Z").unwrap();
+        assert_eq!(manifest_date(&raw), Some(date.into()));
+
+        assert_eq!(manifest_date(&with_manifest("{}")), None);
+    }
+
+    fn local(s: &str) -> Option<SystemTime> {
+        let datetime = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        Some(Local.from_local_datetime(&datetime).unwrap().into())
+    }
+
+    #[test]
+    fn test_parse_backup_name() {
+        let backup = |name: &str, date: Option<&str>, version| BackupName {
+            name: name.to_string(),
+            date: date.and_then(local),
+            version,
+        };
+        assert_eq!(
+            parse_backup_name("Robot v3 2023-10-02.llsp3"),
+            backup("Robot", Some("2023-10-02 00:00:00"), Some(3))
+        );
+        assert_eq!(
+            parse_backup_name("Line follower_20231002_1530.llsp3"),
+            backup("Line follower", Some("2023-10-02 15:30:00"), None)
+        );
+        assert_eq!(
+            parse_backup_name("Robot (copy 2).lms"),
+            backup("Robot", None, None)
+        );
+        assert_eq!(
+            parse_backup_name("Robot - backup (1).llsp3"),
+            backup("Robot", None, None)
+        );
+        assert_eq!(
+            parse_backup_name("Robot.llsp3"),
+            backup("Robot", None, None)
+        );
+        assert_eq!(
+            parse_backup_name("Old Robot v2 (copy).llsp3"),
+            backup("Old Robot", None, Some(2))
+        );
+        assert_eq!(
+            parse_backup_name("Copy of arm_bak.lms"),
+            backup("Copy of arm", None, None)
+        );
+        assert_eq!(
+            parse_backup_name("Backup.llsp3"),
+            backup("Backup", None, None)
+        );
+    }
+
+    #[test]
+    fn test_import_older_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store_path, missing) = (dir.path().join("a.sqlite"), dir.path().join("missing"));
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "[[stores]]\npath = {store_path:?}\ntype = \"sqlite\"\n\n\
+                 [[stores]]\npath = {missing:?}\ntype = \"git\"\n"
+            ),
+        )
+        .unwrap();
+        let cfg = Config::load(config_path.to_str()).unwrap();
+        let at = |secs: u64| UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs);
+        let store = store::create(&cfg.stores[0]).unwrap();
+        store
+            .commit_at(&[(id("Robot.llsp3"), python("new"))], "new", at(100))
+            .unwrap();
+
+        let backup = dir.path().join("Robot.llsp3");
+        python("old").write(File::create(&backup).unwrap()).unwrap();
+        let files = vec![ImportFile {
+            path: backup,
+            id: id("Robot.llsp3"),
+            date: Some(at(0)),
+        }];
+        let res = import(&cfg, files).unwrap();
+
+        assert_eq!(res.open_errors.len(), 1);
+        let store_results = res.files[0].store_results.as_ref().unwrap();
+        assert!(store_results[0].1.is_ok());
+        let queued = res.files[0].queued.as_ref().unwrap();
+        assert_eq!(queued[0].path, missing);
+        assert_eq!(pending::load(&cfg).unwrap().counts(), [(missing, 1)]);
+        let (_, restored, result) = &res.restored[0];
+        assert_eq!(restored, &[id("Robot.llsp3")]);
+        assert!(result.is_ok());
+        let latest = store.read_project(&id("Robot.llsp3"), &Revision::Latest);
+        assert_eq!(latest.unwrap().unwrap().hash(), python("new").hash());
+    }
+}
@@ -0,0 +1,146 @@
+//! Static checks for projects, to catch mistakes before running them on the hub.
+
+mod blocks;
+mod python;
+
+use std::error::Error;
+use std::fmt::Display;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::dirs::Dirs;
+use crate::project::{self, Project, ProjectID};
+use crate::store::{self, Revision, Store};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Where a problem was found.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Location {
+    /// A position in Python source. The line is 1-based, the column is 0-based and counts
+    /// characters.
+    Source { line: usize, col: usize },
+    /// A sprite (or the stage), for problems that aren't about a single block.
+    Target { target: String },
+    /// A block in a sprite (or the stage).
+    Block { target: String, block: String },
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Source { line, col } => write!(f, "{line}:{}", col + 1),
+            // Block ids don't mean anything to people, the message names the block instead.
+            Location::Target { target } | Location::Block { target, .. } => write!(f, "{target}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Short name of the check, e.g. "undefined-name".
+    pub code: &'static str,
+    pub location: Location,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}: {} [{}]",
+            self.location, self.severity, self.message, self.code
+        )
+    }
+}
+
+pub type ProjectLint = (ProjectID, Result<Vec<Diagnostic>, Box<dyn Error>>);
+
+pub fn lint_project(id: &ProjectID, proj: &Project) -> Result<Vec<Diagnostic>, Box<dyn Error>> {
+    match proj {
+        Project::Python(p) => Ok(python::lint(&p.get_source()?, id.program)),
+        Project::WordBlocks(p) | Project::IconBlocks(p) => Ok(blocks::lint(&p.get_scratch()?)),
+    }
+}
+
+/// Lints the local copy of every tracked project. Projects whose local copy has been deleted are
+/// skipped.
+pub fn lint_local(cfg: &Config) -> Result<Vec<ProjectLint>, Box<dyn Error>> {
+    let dirs = Dirs::new(cfg)?;
+    let (stores, store_errs) = store::open_all(&cfg.stores);
+    let (proj_ids, id_errs) = store::all_project_ids(&stores);
+    if let Some((st, e)) = store_errs.into_iter().chain(id_errs).next() {
+        return Err(format!("{st}: {e}").into());
+    }
+    let mut proj_ids: Vec<ProjectID> = proj_ids.into_iter().collect();
+    proj_ids.sort_by_key(|id| id.to_string());
+
+    let mut res = Vec::new();
+    for id in proj_ids {
+        let diags = match project::read(&id, &dirs) {
+            Ok(None) => continue,
+            Ok(Some(raw)) => raw.into_project().and_then(|proj| lint_project(&id, &proj)),
+            Err(e) => Err(e),
+        };
+        res.push((id, diags));
+    }
+    Ok(res)
+}
+
+/// Lints every project in the store at revision.
+pub fn lint_revision(
+    store: &Store,
+    revision: &Revision,
+) -> Result<Vec<ProjectLint>, Box<dyn Error>> {
+    let mut proj_ids = store.project_ids_at(revision)?;
+    proj_ids.sort_by_key(|id| id.to_string());
+
+    let mut res = Vec::new();
+    for id in proj_ids {
+        let diags = match store.read_project(&id, revision) {
+            Ok(None) => continue,
+            Ok(Some(raw)) => raw.into_project().and_then(|proj| lint_project(&id, &proj)),
+            Err(e) => Err(e),
+        };
+        res.push((id, diags));
+    }
+    Ok(res)
+}
+
+/// Describes the results as JSON, for other tools.
+pub fn to_json(projects: &[ProjectLint]) -> Value {
+    let projects: Vec<Value> = projects
+        .iter()
+        .map(|(id, diags)| {
+            let (diags, error) = match diags {
+                Ok(diags) => (serde_json::to_value(diags).unwrap_or_default(), None),
+                Err(e) => (json!([]), Some(e.to_string())),
+            };
+            json!({
+                "program": id.program.to_string(),
+                "name": id.name,
+                "error": error,
+                "diagnostics": diags,
+            })
+        })
+        .collect();
+    json!({ "projects": projects })
+}
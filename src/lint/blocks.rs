@@ -0,0 +1,472 @@
+//! Checks for word and icon block projects.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::project::scratch::{label, opcode_prefix, Block, InputValue, ScratchProject, Target};
+
+use super::{Diagnostic, Location, Severity};
+
+/// Blocks that read the variable in their VARIABLE field.
+const VARIABLE_READS: &[&str] = &["data_variable", "data_showvariable"];
+
+/// Blocks that read the list in their LIST field.
+const LIST_READS: &[&str] = &[
+    "data_listcontents",
+    "data_itemoflist",
+    "data_itemnumoflist",
+    "data_lengthoflist",
+    "data_listcontainsitem",
+    "data_showlist",
+];
+
+const SET_MOVEMENT_MOTORS: &str = "flippermove_setMovementPair";
+
+/// Blocks that set up the motors on the ports in their PORT input.
+const MOTOR_SETUP: &[&str] = &[
+    "flippermotor_motorSetSpeed",
+    "flippermoremotor_motorSetStopMethod",
+];
+
+pub fn lint(proj: &ScratchProject) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    check_dead_code(proj, &mut diags);
+    check_unused_data(proj, &mut diags);
+    check_broadcasts(proj, &mut diags);
+    check_empty_loops(proj, &mut diags);
+    check_movement_motors(proj, &mut diags);
+    check_motor_ports(proj, &mut diags);
+    diags
+}
+
+fn at_block(target: &Target, id: &str) -> Location {
+    Location::Block {
+        target: target.name.clone(),
+        block: id.to_string(),
+    }
+}
+
+fn check_dead_code(proj: &ScratchProject, diags: &mut Vec<Diagnostic>) {
+    for target in &proj.targets {
+        for id in target.top_level_ids() {
+            let Some(block) = target.block(id) else {
+                continue;
+            };
+            if !block.is_hat() {
+                diags.push(Diagnostic {
+                    severity: Severity::Warning,
+                    code: "no-hat",
+                    location: at_block(target, id),
+                    message: format!(
+                        "the script starting with '{}' has no hat block, so it never runs",
+                        label(&block.opcode)
+                    ),
+                });
+            }
+        }
+    }
+}
+
+/// How a variable or list is used.
+#[derive(Default)]
+struct Uses {
+    read: bool,
+    written: bool,
+}
+
+fn check_unused_data(proj: &ScratchProject, diags: &mut Vec<Diagnostic>) {
+    // Stage variables can be used from any sprite, so collect uses across the whole project.
+    let mut uses: HashMap<String, Uses> = HashMap::new();
+    for target in &proj.targets {
+        for entry in target.blocks.values() {
+            if let Some(InputValue::Variable { id, .. } | InputValue::List { id, .. }) =
+                entry.primitive()
+            {
+                uses.entry(id).or_default().read = true;
+            }
+        }
+        for (_, block) in target.each_block() {
+            for (_, value) in block.input_values() {
+                if let InputValue::Variable { id, .. } | InputValue::List { id, .. } = value {
+                    uses.entry(id).or_default().read = true;
+                }
+            }
+            for (field, reads) in [("VARIABLE", VARIABLE_READS), ("LIST", LIST_READS)] {
+                if let Some(id) = block.field_id(field) {
+                    let u = uses.entry(id.to_string()).or_default();
+                    match reads.contains(&block.opcode.as_str()) {
+                        true => u.read = true,
+                        false => u.written = true,
+                    };
+                }
+            }
+        }
+    }
+
+    for target in &proj.targets {
+        for (kind, declared) in [("variable", &target.variables), ("list", &target.lists)] {
+            for (id, decl) in declared {
+                let name = decl.get(0).and_then(|n| n.as_str()).unwrap_or(id);
+                let message = match uses.get(id.as_str()) {
+                    Some(Uses { read: true, .. }) => continue,
+                    Some(Uses { written: true, .. }) => {
+                        format!("{kind} '{name}' is set but never used")
+                    }
+                    _ => format!("{kind} '{name}' is never used"),
+                };
+                diags.push(Diagnostic {
+                    severity: Severity::Warning,
+                    code: "unused-variable",
+                    location: Location::Target {
+                        target: target.name.clone(),
+                    },
+                    message,
+                });
+            }
+        }
+    }
+}
+
+fn check_broadcasts(proj: &ScratchProject, diags: &mut Vec<Diagnostic>) {
+    // Broadcasts are matched by name, ignoring case, like the editor does.
+    let mut sent: Vec<(&Target, &str, String)> = Vec::new();
+    let mut received: Vec<(&Target, &str, String)> = Vec::new();
+    // A message computed by a reporter could be anything.
+    let mut dynamic = false;
+    for target in &proj.targets {
+        for (id, block) in target.each_block() {
+            match block.opcode.as_str() {
+                "event_broadcast" | "event_broadcastandwait" => {
+                    match broadcast_input(target, block) {
+                        Some(name) => sent.push((target, id, name)),
+                        None => dynamic = true,
+                    }
+                }
+                "event_whenbroadcastreceived" => {
+                    if let Some(name) = block.field("BROADCAST_OPTION") {
+                        received.push((target, id, name.to_string()));
+                    }
+                }
+                _ => {}
+            };
+        }
+    }
+
+    let names = |list: &[(&Target, &str, String)]| -> HashSet<String> {
+        list.iter().map(|(_, _, n)| n.to_lowercase()).collect()
+    };
+    let (sent_names, received_names) = (names(&sent), names(&received));
+    let mut reported = HashSet::new();
+    for (target, id, name) in &sent {
+        let key = name.to_lowercase();
+        if !received_names.contains(&key) && reported.insert(key) {
+            diags.push(Diagnostic {
+                severity: Severity::Warning,
+                code: "unreceived-broadcast",
+                location: at_block(target, id),
+                message: format!("message '{name}' is broadcast but nothing receives it"),
+            });
+        }
+    }
+    if dynamic {
+        return;
+    }
+    for (target, id, name) in &received {
+        if !sent_names.contains(&name.to_lowercase()) {
+            diags.push(Diagnostic {
+                severity: Severity::Warning,
+                code: "unsent-broadcast",
+                location: at_block(target, id),
+                message: format!("nothing broadcasts message '{name}', so this script never runs"),
+            });
+        }
+    }
+}
+
+/// Returns the message a broadcast block sends, if it's chosen from the menu.
+fn broadcast_input(target: &Target, block: &Block) -> Option<String> {
+    match block.input("BROADCAST_INPUT")? {
+        InputValue::Broadcast { name, .. } => Some(name),
+        InputValue::Block(menu) => {
+            let menu = target.block(menu)?;
+            match menu.shadow {
+                true => menu.field("BROADCAST_OPTION").map(str::to_string),
+                false => None,
+            }
+        }
+        InputValue::Literal(name) => Some(name),
+        _ => None,
+    }
+}
+
+fn check_empty_loops(proj: &ScratchProject, diags: &mut Vec<Diagnostic>) {
+    for target in &proj.targets {
+        for (id, block) in target.each_block() {
+            if block.opcode == "control_forever" && block.input("SUBSTACK").is_none() {
+                diags.push(Diagnostic {
+                    severity: Severity::Warning,
+                    code: "empty-loop",
+                    location: at_block(target, id),
+                    message: "'forever' loop is empty, so it keeps the script busy without doing anything"
+                        .to_string(),
+                });
+            }
+        }
+    }
+}
+
+fn check_movement_motors(proj: &ScratchProject, diags: &mut Vec<Diagnostic>) {
+    let mut first_use = None;
+    for target in &proj.targets {
+        for (id, block) in target.each_block() {
+            if block.opcode == SET_MOVEMENT_MOTORS {
+                return;
+            }
+            let is_movement = opcode_prefix(&block.opcode) == "flippermove" && !block.shadow;
+            if is_movement && first_use.is_none() {
+                first_use = Some((target, id, block));
+            }
+        }
+    }
+    if let Some((target, id, block)) = first_use {
+        diags.push(Diagnostic {
+            severity: Severity::Warning,
+            code: "unconfigured-port",
+            location: at_block(target, id),
+            message: format!(
+                "'{}' drives the movement motors, but they're never set with 'set movement motors', so it depends on the default ports",
+                label(&block.opcode)
+            ),
+        });
+    }
+}
+
+fn check_motor_ports(proj: &ScratchProject, diags: &mut Vec<Diagnostic>) {
+    let mut configured = HashSet::new();
+    let mut used: Vec<(&Target, &str, &Block, char)> = Vec::new();
+    for target in &proj.targets {
+        for (id, block) in target.each_block() {
+            if block.shadow {
+                continue;
+            }
+            if block.opcode == SET_MOVEMENT_MOTORS {
+                configured.extend(ports(target, block, "PAIR"));
+            } else if MOTOR_SETUP.contains(&block.opcode.as_str()) {
+                configured.extend(ports(target, block, "PORT"));
+            } else if matches!(
+                opcode_prefix(&block.opcode),
+                "flippermotor" | "flippermoremotor"
+            ) {
+                for port in ports(target, block, "PORT") {
+                    used.push((target, id, block, port));
+                }
+            }
+        }
+    }
+    // Report each port once, where it's first used.
+    let mut reported = HashSet::new();
+    for (target, id, block, port) in used {
+        if configured.contains(&port) || !reported.insert(port) {
+            continue;
+        }
+        diags.push(Diagnostic {
+            severity: Severity::Warning,
+            code: "unconfigured-port",
+            location: at_block(target, id),
+            message: format!(
+                "'{}' uses the motor on port {port}, but no block sets up that port",
+                label(&block.opcode)
+            ),
+        });
+    }
+}
+
+/// Returns the hub ports chosen in a port menu, e.g. ['A', 'C'] for "AC". Ports computed by a
+/// reporter can't be known, and none are returned for them.
+fn ports(target: &Target, block: &Block, input: &str) -> Vec<char> {
+    let value = match block.input(input) {
+        Some(InputValue::Block(menu)) => match target.block(menu) {
+            Some(menu) if menu.shadow => menu.fields.keys().next().and_then(|f| menu.field(f)),
+            _ => None,
+        },
+        _ => None,
+    };
+    let ports = value.unwrap_or_default().chars();
+    ports.filter(|c| ('A'..='F').contains(c)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(blocks: &str) -> ScratchProject {
+        let json = format!(
+            r#"{{"targets": [
+                {{"isStage": true, "name": "Stage",
+                  "variables": {{"v1": ["speed", 0], "v2": ["unused", 0], "v3": ["written", 0]}},
+                  "lists": {{"l1": ["items", []]}}, "blocks": {{}}}},
+                {{"isStage": false, "name": "Sprite", "blocks": {blocks}}}
+            ]}}"#
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn codes(proj: &ScratchProject) -> Vec<(&'static str, String)> {
+        lint(proj)
+            .into_iter()
+            .map(|d| (d.code, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_problems() {
+        let proj = project(
+            r#"{
+            "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b", "parent": null,
+                  "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 0, "y": 0},
+            "b": {"opcode": "control_forever", "next": null, "parent": "a",
+                  "inputs": {}, "fields": {}, "shadow": false, "topLevel": false},
+            "c": {"opcode": "data_setvariableto", "next": "d", "parent": null,
+                  "inputs": {"VALUE": [1, [10, "5"]]}, "fields": {"VARIABLE": ["written", "v3"]},
+                  "shadow": false, "topLevel": true, "x": 0, "y": 100},
+            "d": {"opcode": "event_broadcast", "next": "e", "parent": "c",
+                  "inputs": {"BROADCAST_INPUT": [1, [11, "go", "b1"]]}, "fields": {},
+                  "shadow": false, "topLevel": false},
+            "e": {"opcode": "flippermove_move", "next": null, "parent": "d",
+                  "inputs": {"STEERING": [1, [4, "0"]]}, "fields": {},
+                  "shadow": false, "topLevel": false},
+            "f": {"opcode": "event_whenbroadcastreceived", "next": null, "parent": null,
+                  "inputs": {}, "fields": {"BROADCAST_OPTION": ["stop", "b2"]},
+                  "shadow": false, "topLevel": true, "x": 0, "y": 200},
+            "g": [12, "speed", "v1", 10, 300],
+            "h": {"opcode": "data_lengthoflist", "next": null, "parent": null,
+                  "inputs": {}, "fields": {"LIST": ["items", "l1"]},
+                  "shadow": false, "topLevel": true, "x": 0, "y": 400}
+        }"#,
+        );
+        assert_eq!(
+            codes(&proj),
+            vec![
+                (
+                    "no-hat",
+                    "the script starting with 'setvariableto' has no hat block, so it never runs"
+                        .to_string()
+                ),
+                (
+                    "no-hat",
+                    "the script starting with 'lengthoflist' has no hat block, so it never runs"
+                        .to_string()
+                ),
+                ("unused-variable", "variable 'unused' is never used".to_string()),
+                (
+                    "unused-variable",
+                    "variable 'written' is set but never used".to_string()
+                ),
+                (
+                    "unreceived-broadcast",
+                    "message 'go' is broadcast but nothing receives it".to_string()
+                ),
+                (
+                    "unsent-broadcast",
+                    "nothing broadcasts message 'stop', so this script never runs".to_string()
+                ),
+                (
+                    "empty-loop",
+                    "'forever' loop is empty, so it keeps the script busy without doing anything".to_string()
+                ),
+                (
+                    "unconfigured-port",
+                    "'move' drives the movement motors, but they're never set with 'set movement motors', so it depends on the default ports".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clean() {
+        let proj = project(
+            r#"{
+            "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b", "parent": null,
+                  "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 0, "y": 0},
+            "b": {"opcode": "flippermove_setMovementPair", "next": "c", "parent": "a",
+                  "inputs": {}, "fields": {}, "shadow": false, "topLevel": false},
+            "c": {"opcode": "event_broadcast", "next": "d", "parent": "b",
+                  "inputs": {"BROADCAST_INPUT": [1, "m"]}, "fields": {},
+                  "shadow": false, "topLevel": false},
+            "m": {"opcode": "event_broadcast_menu", "next": null, "parent": "c", "inputs": {},
+                  "fields": {"BROADCAST_OPTION": ["Go", "b1"]}, "shadow": true, "topLevel": false},
+            "d": {"opcode": "flippermove_move", "next": null, "parent": "c",
+                  "inputs": {"STEERING": [3, [12, "speed", "v1"], [4, "0"]]}, "fields": {},
+                  "shadow": false, "topLevel": false},
+            "e": {"opcode": "event_whenbroadcastreceived", "next": "f", "parent": null,
+                  "inputs": {}, "fields": {"BROADCAST_OPTION": ["go", "b1"]},
+                  "shadow": false, "topLevel": true, "x": 0, "y": 200},
+            "f": {"opcode": "control_forever", "next": null, "parent": "e",
+                  "inputs": {"SUBSTACK": [2, "g"]}, "fields": {}, "shadow": false, "topLevel": false},
+            "g": {"opcode": "data_showvariable", "next": "h", "parent": "f",
+                  "inputs": {}, "fields": {"VARIABLE": ["unused", "v2"]},
+                  "shadow": false, "topLevel": false},
+            "h": {"opcode": "data_changevariableby", "next": "i", "parent": "g",
+                  "inputs": {"VALUE": [1, [4, "1"]]}, "fields": {"VARIABLE": ["written", "v3"]},
+                  "shadow": false, "topLevel": false},
+            "i": {"opcode": "data_showlist", "next": null, "parent": "h",
+                  "inputs": {}, "fields": {"LIST": ["items", "l1"]},
+                  "shadow": false, "topLevel": false},
+            "j": {"opcode": "data_showvariable", "next": null, "parent": null,
+                  "inputs": {}, "fields": {"VARIABLE": ["written", "v3"]},
+                  "shadow": false, "topLevel": false}
+        }"#,
+        );
+        assert_eq!(codes(&proj), vec![]);
+    }
+
+    #[test]
+    fn test_unconfigured_motor_port() {
+        let proj = project(
+            r#"{
+            "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b", "parent": null,
+                  "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 0, "y": 0},
+            "b": {"opcode": "flippermove_setMovementPair", "next": "c", "parent": "a",
+                  "inputs": {"PAIR": [1, "bm"]}, "fields": {}, "shadow": false, "topLevel": false},
+            "bm": {"opcode": "flippermove_custom-set-move-pair", "next": null, "parent": "b",
+                   "inputs": {}, "fields": {"field_flippermove_custom-set-move-pair": ["AB", null]},
+                   "shadow": true, "topLevel": false},
+            "c": {"opcode": "flippermotor_motorSetSpeed", "next": "d", "parent": "b",
+                  "inputs": {"PORT": [1, "cm"], "SPEED": [1, [4, "50"]]}, "fields": {},
+                  "shadow": false, "topLevel": false},
+            "cm": {"opcode": "flippermotor_multiple-port-selector", "next": null, "parent": "c",
+                   "inputs": {},
+                   "fields": {"field_flippermotor_multiple-port-selector": ["D", null]},
+                   "shadow": true, "topLevel": false},
+            "d": {"opcode": "flippermotor_motorStartDirection", "next": "e", "parent": "c",
+                  "inputs": {"PORT": [1, "dm"]}, "fields": {"DIRECTION": ["clockwise", null]},
+                  "shadow": false, "topLevel": false},
+            "dm": {"opcode": "flippermotor_multiple-port-selector", "next": null, "parent": "d",
+                   "inputs": {},
+                   "fields": {"field_flippermotor_multiple-port-selector": ["AD", null]},
+                   "shadow": true, "topLevel": false},
+            "e": {"opcode": "flippermotor_motorStop", "next": null, "parent": "d",
+                  "inputs": {"PORT": [1, "em"]}, "fields": {}, "shadow": false, "topLevel": false},
+            "em": {"opcode": "flippermotor_multiple-port-selector", "next": null, "parent": "e",
+                   "inputs": {},
+                   "fields": {"field_flippermotor_multiple-port-selector": ["C", null]},
+                   "shadow": true, "topLevel": false}
+        }"#,
+        );
+        let diags = lint(&proj);
+        let ports: Vec<_> = diags
+            .iter()
+            .filter(|d| d.code == "unconfigured-port")
+            .map(|d| (&d.location, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            ports,
+            [(
+                &Location::Block {
+                    target: "Sprite".to_string(),
+                    block: "e".to_string()
+                },
+                "'motor stop' uses the motor on port C, but no block sets up that port"
+            )]
+        );
+    }
+}
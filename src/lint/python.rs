@@ -0,0 +1,756 @@
+//! Checks for Python projects. They work on the token stream rather than a full parse, and
+//! treat the whole file as a single scope for names, which is enough for hub programs and keeps
+//! false positives down.
+
+use std::collections::HashSet;
+
+use crate::project::python::{tokenize, Token, TokenKind};
+use crate::project::Program;
+
+use super::{Diagnostic, Location, Severity};
+
+/// Modules that only exist in the SPIKE (3.x) runtime.
+const SPIKE_MODULES: &[&str] = &[
+    "app",
+    "color",
+    "color_matrix",
+    "color_sensor",
+    "device",
+    "distance_sensor",
+    "force_sensor",
+    "motor",
+    "motor_pair",
+    "orientation",
+    "runloop",
+];
+
+/// Modules that only exist in the Mindstorms runtime.
+const MINDSTORMS_MODULES: &[&str] = &["mindstorms"];
+
+/// Parts of the `hub` module that only exist in one of the runtimes.
+const SPIKE_HUB: &[&str] = &["light", "light_matrix", "motion_sensor"];
+const MINDSTORMS_HUB: &[&str] = &["battery", "display", "led", "motion", "supervision"];
+
+/// Statements that must have a `:` and are followed by an indented block.
+const COMPOUND: &[&str] = &[
+    "async", "class", "def", "elif", "else", "except", "finally", "for", "if", "try", "while",
+    "with",
+];
+
+pub fn lint(src: &str, program: Program) -> Vec<Diagnostic> {
+    let tokens = tokenize(src);
+    let mut diags = Vec::new();
+    let lines = logical_lines(&tokens, &mut diags);
+    check_indentation(&lines, &mut diags);
+    let scopes = scopes(&lines);
+    check_await(&lines, &scopes, &mut diags);
+    // The other checks would mostly repeat syntax errors in a confusing way.
+    if diags.is_empty() {
+        let imports = imports(&lines);
+        check_names(&lines, &imports, &mut diags);
+        check_runtime(&lines, &imports, program, &mut diags);
+        check_async_loops(&lines, &scopes, &mut diags);
+    }
+    diags.sort_by(|a, b| a.location.cmp(&b.location));
+    diags
+}
+
+fn diagnostic(severity: Severity, code: &'static str, at: &Token, message: String) -> Diagnostic {
+    Diagnostic {
+        severity,
+        code,
+        location: Location::Source {
+            line: at.line,
+            col: at.col,
+        },
+        message,
+    }
+}
+
+/// A statement, possibly spanning several physical lines. Whitespace and comments are dropped.
+struct Line<'a> {
+    indent: usize,
+    tokens: Vec<Token<'a>>,
+}
+
+impl Line<'_> {
+    fn first(&self) -> &str {
+        self.tokens[0].text
+    }
+
+    fn is_import(&self) -> bool {
+        matches!(self.first(), "import" | "from")
+    }
+
+    fn is_compound(&self) -> bool {
+        COMPOUND.contains(&self.first())
+    }
+
+    fn is_def(&self) -> bool {
+        self.first() == "def" || (self.first() == "async" && self.text(1) == Some("def"))
+    }
+
+    fn text(&self, i: usize) -> Option<&str> {
+        self.tokens.get(i).map(|t| t.text)
+    }
+
+    /// Returns how deeply nested in brackets each token is. Brackets count as outside themselves.
+    fn depths(&self) -> Vec<usize> {
+        let mut depth: usize = 0;
+        self.tokens
+            .iter()
+            .map(|t| match t.text {
+                "(" | "[" | "{" if t.kind == TokenKind::Bracket => {
+                    depth += 1;
+                    depth - 1
+                }
+                ")" | "]" | "}" if t.kind == TokenKind::Bracket => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                }
+                _ => depth,
+            })
+            .collect()
+    }
+
+    fn has_top_level_colon(&self) -> bool {
+        let depths = self.depths();
+        // Colons in a lambda's parameter list don't count.
+        let mut lambdas = 0;
+        for (t, depth) in self.tokens.iter().zip(depths) {
+            match (t.text, depth) {
+                ("lambda", 0) => lambdas += 1,
+                (":", 0) if lambdas > 0 => lambdas -= 1,
+                (":", 0) => return true,
+                _ => {}
+            };
+        }
+        false
+    }
+}
+
+/// Groups tokens into statements, reporting bad tokens and mismatched brackets along the way.
+fn logical_lines<'a>(tokens: &[Token<'a>], diags: &mut Vec<Diagnostic>) -> Vec<Line<'a>> {
+    let mut lines = Vec::new();
+    let mut current: Vec<Token<'a>> = Vec::new();
+    let mut open: Vec<&Token> = Vec::new();
+    for tok in tokens {
+        match tok.kind {
+            TokenKind::Whitespace | TokenKind::Comment => continue,
+            TokenKind::Newline => {
+                if current.last().is_some_and(|t| t.text == "\\") {
+                    current.pop();
+                } else if open.is_empty() && !current.is_empty() {
+                    let indent = current[0].col;
+                    lines.push(Line {
+                        indent,
+                        tokens: std::mem::take(&mut current),
+                    });
+                }
+                continue;
+            }
+            TokenKind::UnterminatedString => diags.push(diagnostic(
+                Severity::Error,
+                "syntax",
+                tok,
+                "unterminated string".to_string(),
+            )),
+            TokenKind::Unknown => diags.push(diagnostic(
+                Severity::Error,
+                "syntax",
+                tok,
+                format!("invalid character {:?}", tok.text),
+            )),
+            TokenKind::Bracket => match tok.text {
+                "(" | "[" | "{" => open.push(tok),
+                close => match open.pop() {
+                    None => diags.push(diagnostic(
+                        Severity::Error,
+                        "syntax",
+                        tok,
+                        format!("unmatched '{close}'"),
+                    )),
+                    Some(o) if closing(o.text) != close => diags.push(diagnostic(
+                        Severity::Error,
+                        "syntax",
+                        tok,
+                        format!(
+                            "closing '{close}' does not match '{}' on line {}",
+                            o.text, o.line
+                        ),
+                    )),
+                    Some(_) => {}
+                },
+            },
+            _ => {}
+        };
+        current.push(tok.clone());
+    }
+    for o in open {
+        diags.push(diagnostic(
+            Severity::Error,
+            "syntax",
+            o,
+            format!("'{}' was never closed", o.text),
+        ));
+    }
+    if !current.is_empty() {
+        let indent = current[0].col;
+        lines.push(Line {
+            indent,
+            tokens: current,
+        });
+    }
+    lines
+}
+
+fn closing(open: &str) -> &'static str {
+    match open {
+        "(" => ")",
+        "[" => "]",
+        _ => "}",
+    }
+}
+
+fn check_indentation(lines: &[Line], diags: &mut Vec<Diagnostic>) {
+    let mut indents = vec![0];
+    let mut expect_indent = false;
+    for line in lines {
+        let top = *indents.last().unwrap();
+        let at = &line.tokens[0];
+        if line.indent > top {
+            if !expect_indent {
+                diags.push(diagnostic(
+                    Severity::Error,
+                    "syntax",
+                    at,
+                    "unexpected indent".to_string(),
+                ));
+            }
+            indents.push(line.indent);
+        } else {
+            if expect_indent {
+                diags.push(diagnostic(
+                    Severity::Error,
+                    "syntax",
+                    at,
+                    "expected an indented block".to_string(),
+                ));
+            }
+            while line.indent < *indents.last().unwrap() {
+                indents.pop();
+            }
+            if line.indent != *indents.last().unwrap() {
+                diags.push(diagnostic(
+                    Severity::Error,
+                    "syntax",
+                    at,
+                    "unindent does not match any outer indentation level".to_string(),
+                ));
+                indents.push(line.indent);
+            }
+        }
+
+        if line.is_compound() && !line.has_top_level_colon() {
+            diags.push(diagnostic(
+                Severity::Error,
+                "syntax",
+                at,
+                format!("expected ':' after '{}' statement", line.first()),
+            ));
+        }
+        expect_indent = line.tokens.last().is_some_and(|t| t.text == ":");
+    }
+    if let (true, Some(line)) = (expect_indent, lines.last()) {
+        diags.push(diagnostic(
+            Severity::Error,
+            "syntax",
+            line.tokens.last().unwrap(),
+            "expected an indented block".to_string(),
+        ));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Module,
+    Class,
+    Function { is_async: bool },
+}
+
+/// Returns the innermost scope of each line.
+fn scopes(lines: &[Line]) -> Vec<Scope> {
+    let mut stack: Vec<(usize, Scope)> = Vec::new();
+    let mut res = Vec::with_capacity(lines.len());
+    for line in lines {
+        while stack
+            .last()
+            .is_some_and(|(indent, _)| *indent >= line.indent)
+        {
+            stack.pop();
+        }
+        res.push(stack.last().map_or(Scope::Module, |(_, s)| *s));
+        if line.is_def() {
+            let is_async = line.first() == "async";
+            stack.push((line.indent, Scope::Function { is_async }));
+        } else if line.first() == "class" {
+            stack.push((line.indent, Scope::Class));
+        }
+    }
+    res
+}
+
+fn check_await(lines: &[Line], scopes: &[Scope], diags: &mut Vec<Diagnostic>) {
+    for (line, scope) in lines.iter().zip(scopes) {
+        if *scope == (Scope::Function { is_async: true }) {
+            continue;
+        }
+        // A one-line async def has its body on the same line.
+        if line.is_def() && line.first() == "async" {
+            continue;
+        }
+        if let Some(at) = line.tokens.iter().find(|t| t.text == "await") {
+            diags.push(diagnostic(
+                Severity::Error,
+                "syntax",
+                at,
+                "'await' outside async function".to_string(),
+            ));
+        }
+    }
+}
+
+/// A name bound by an import statement.
+struct Import<'a> {
+    /// Full module name, like "mindstorms.control".
+    module: String,
+    /// The imported name, for `from` imports.
+    name: Option<&'a str>,
+    /// The name bound in the program.
+    bound: &'a str,
+    at: Token<'a>,
+}
+
+fn imports<'a>(lines: &[Line<'a>]) -> Vec<Import<'a>> {
+    let mut res = Vec::new();
+    for line in lines.iter().filter(|l| l.is_import()) {
+        let t: Vec<&Token<'a>> = line
+            .tokens
+            .iter()
+            .filter(|t| t.kind != TokenKind::Bracket)
+            .collect();
+        match line.first() {
+            "import" => {
+                for item in t[1..].split(|t| t.text == ",") {
+                    let Some(first) = item.first() else { continue };
+                    let as_pos = item.iter().position(|t| t.text == "as");
+                    let module: String = item[..as_pos.unwrap_or(item.len())]
+                        .iter()
+                        .map(|t| t.text)
+                        .collect();
+                    let bound = match as_pos.and_then(|p| item.get(p + 1)) {
+                        Some(alias) => alias,
+                        None => first,
+                    };
+                    res.push(Import {
+                        module,
+                        name: None,
+                        bound: bound.text,
+                        at: (*bound).clone(),
+                    });
+                }
+            }
+            _ => {
+                let Some(import_pos) = t.iter().position(|t| t.text == "import") else {
+                    continue;
+                };
+                let module: String = t[1..import_pos].iter().map(|t| t.text).collect();
+                for item in t[import_pos + 1..].split(|t| t.text == ",") {
+                    let Some(name) = item.first() else { continue };
+                    let bound = match item.get(1).filter(|t| t.text == "as") {
+                        Some(_) => item.get(2).unwrap_or(name),
+                        None => name,
+                    };
+                    res.push(Import {
+                        module: module.clone(),
+                        name: Some(name.text),
+                        bound: bound.text,
+                        at: (*bound).clone(),
+                    });
+                }
+            }
+        };
+    }
+    res
+}
+
+/// Collects every name the program binds, anywhere.
+fn bound_names<'a>(lines: &[Line<'a>], imports: &[Import<'a>]) -> HashSet<&'a str> {
+    let mut bound: HashSet<&str> = imports.iter().map(|i| i.bound).collect();
+    for line in lines.iter().filter(|l| !l.is_import()) {
+        let t = &line.tokens;
+        let name_at = |i: usize| t.get(i).is_some_and(is_name);
+
+        match line.first() {
+            "def" | "class" if name_at(1) => {
+                bound.insert(t[1].text);
+            }
+            "async" if name_at(2) => {
+                bound.insert(t[2].text);
+            }
+            "global" | "nonlocal" => bound.extend(t.iter().filter(|t| is_name(t)).map(|t| t.text)),
+            _ => {}
+        };
+
+        if line.is_def() {
+            // Parameters are the first name after '(', ',', '*', or '**' in the parameter list.
+            let depths = line.depths();
+            for i in 1..t.len() {
+                let starts_param = matches!(t[i - 1].text, "(" | "," | "*" | "**");
+                if depths[i] == 1 && starts_param && name_at(i) {
+                    bound.insert(t[i].text);
+                }
+            }
+        }
+
+        for i in 0..t.len() {
+            match t[i].text {
+                // Loop variables, in statements and comprehensions.
+                "for" => {
+                    let end = t[i..].iter().position(|t| t.text == "in").unwrap_or(1);
+                    bound.extend(
+                        t[i + 1..i + end]
+                            .iter()
+                            .filter(|t| is_name(t))
+                            .map(|t| t.text),
+                    );
+                }
+                // `with ... as x` and `except ... as x`.
+                "as" if name_at(i + 1) => {
+                    bound.insert(t[i + 1].text);
+                }
+                "lambda" => {
+                    let end = t[i..].iter().position(|t| t.text == ":").unwrap_or(1);
+                    bound.extend(
+                        t[i + 1..i + end]
+                            .iter()
+                            .filter(|t| is_name(t))
+                            .map(|t| t.text),
+                    );
+                }
+                ":=" if i > 0 && name_at(i - 1) => {
+                    bound.insert(t[i - 1].text);
+                }
+                _ => {}
+            };
+        }
+
+        // Annotated assignment, like `x: int = 1`.
+        if name_at(0) && line.text(1) == Some(":") {
+            bound.insert(t[0].text);
+        }
+
+        // Assignment targets are everything before the last top-level '='.
+        let depths = line.depths();
+        let Some(last_eq) = (0..t.len())
+            .rev()
+            .find(|i| t[*i].text == "=" && depths[*i] == 0)
+        else {
+            continue;
+        };
+        // Names inside subscripts or calls (like `a[i] = 1`) are reads, not targets.
+        let mut reading: Vec<bool> = Vec::new();
+        for i in 0..last_eq {
+            match t[i].text {
+                "(" | "[" | "{" if t[i].kind == TokenKind::Bracket => {
+                    let after_value =
+                        i > 0 && (name_at(i - 1) || matches!(t[i - 1].text, ")" | "]"));
+                    reading.push(after_value || reading.last() == Some(&true));
+                }
+                ")" | "]" | "}" if t[i].kind == TokenKind::Bracket => {
+                    reading.pop();
+                }
+                _ if name_at(i) && reading.last() != Some(&true) => {
+                    let attr = i > 0 && t[i - 1].text == ".";
+                    let followed = matches!(line.text(i + 1), Some("." | "[" | "("));
+                    if !attr && !followed {
+                        bound.insert(t[i].text);
+                    }
+                }
+                _ => {}
+            };
+        }
+    }
+    bound
+}
+
+fn is_name(t: &Token) -> bool {
+    t.kind == TokenKind::Name
+}
+
+/// Returns the names the program reads, as tokens. Attributes and keyword arguments aren't
+/// included.
+fn used_names<'a, 'b>(lines: &'b [Line<'a>]) -> impl Iterator<Item = &'b Token<'a>> {
+    lines.iter().filter(|l| !l.is_import()).flat_map(|line| {
+        let depths = line.depths();
+        let t = &line.tokens;
+        (0..t.len()).filter_map(move |i| {
+            let attr = i > 0 && t[i - 1].text == ".";
+            let keyword_arg = depths[i] > 0 && line.text(i + 1) == Some("=");
+            match matches!(t[i].kind, TokenKind::Name | TokenKind::Builtin) && !attr && !keyword_arg
+            {
+                true => Some(&t[i]),
+                false => None,
+            }
+        })
+    })
+}
+
+fn check_names(lines: &[Line], imports: &[Import], diags: &mut Vec<Diagnostic>) {
+    let star_import = imports.iter().any(|i| i.bound == "*");
+    let bound = bound_names(lines, imports);
+
+    let mut used = HashSet::new();
+    for t in used_names(lines) {
+        let fresh = used.insert(t.text);
+        if fresh && t.kind == TokenKind::Name && !star_import && !bound.contains(t.text) {
+            diags.push(diagnostic(
+                Severity::Error,
+                "undefined-name",
+                t,
+                format!("undefined name '{}'", t.text),
+            ));
+        }
+    }
+
+    // Names used inside f-strings don't show up as tokens.
+    let fstrings: Vec<&str> = lines
+        .iter()
+        .flat_map(|l| &l.tokens)
+        .filter(|t| t.kind == TokenKind::String && t.text.starts_with(['f', 'F']))
+        .map(|t| t.text)
+        .collect();
+    for import in imports.iter().filter(|i| i.bound != "*") {
+        let in_fstring = fstrings.iter().any(|s| contains_word(s, import.bound));
+        if !used.contains(import.bound) && !in_fstring {
+            let what = match import.name {
+                Some(name) => format!("{}.{name}", import.module),
+                None => import.module.clone(),
+            };
+            diags.push(diagnostic(
+                Severity::Warning,
+                "unused-import",
+                &import.at,
+                format!("'{what}' imported but unused"),
+            ));
+        }
+    }
+}
+
+fn contains_word(s: &str, word: &str) -> bool {
+    s.match_indices(word).any(|(i, _)| {
+        let is_ident = |c: char| c == '_' || c.is_alphanumeric();
+        let before = s[..i].chars().next_back().is_some_and(is_ident);
+        let after = s[i + word.len()..].chars().next().is_some_and(is_ident);
+        !before && !after
+    })
+}
+
+fn check_runtime(
+    lines: &[Line],
+    imports: &[Import],
+    program: Program,
+    diags: &mut Vec<Diagnostic>,
+) {
+    let (missing_modules, missing_hub, other) = match program {
+        Program::Spike => (MINDSTORMS_MODULES, MINDSTORMS_HUB, "Mindstorms"),
+        Program::Mindstorms => (SPIKE_MODULES, SPIKE_HUB, "SPIKE"),
+    };
+    for import in imports {
+        let top = import.module.split('.').next().unwrap_or_default();
+        if missing_modules.contains(&top) {
+            diags.push(diagnostic(
+                Severity::Error,
+                "runtime-api",
+                &import.at,
+                format!("the {top} module is only available in the {other} app"),
+            ));
+        }
+        if top == "hub"
+            && let Some(name) = import.name
+            && missing_hub.contains(&name)
+        {
+            diags.push(diagnostic(
+                Severity::Warning,
+                "runtime-api",
+                &import.at,
+                format!("hub.{name} is only available in the {other} app"),
+            ));
+        }
+    }
+
+    // Uses like `hub.display`, when hub itself was imported.
+    let hub_imported = imports.iter().any(|i| i.name.is_none() && i.bound == "hub");
+    if !hub_imported {
+        return;
+    }
+    for line in lines.iter().filter(|l| !l.is_import()) {
+        for w in line.tokens.windows(3) {
+            let attr = (w[0].text, w[1].text, w[2].text);
+            if let ("hub", ".", name) = attr
+                && missing_hub.contains(&name)
+            {
+                diags.push(diagnostic(
+                    Severity::Warning,
+                    "runtime-api",
+                    &w[2],
+                    format!("hub.{name} is only available in the {other} app"),
+                ));
+            }
+        }
+    }
+}
+
+fn check_async_loops(lines: &[Line], scopes: &[Scope], diags: &mut Vec<Diagnostic>) {
+    for (i, line) in lines.iter().enumerate() {
+        if line.first() != "while" || scopes[i] != (Scope::Function { is_async: true }) {
+            continue;
+        }
+        let body = lines[i + 1..].iter().take_while(|l| l.indent > line.indent);
+        let awaits = std::iter::once(line)
+            .chain(body)
+            .flat_map(|l| &l.tokens)
+            .any(|t| t.text == "await");
+        if !awaits {
+            diags.push(diagnostic(
+                Severity::Warning,
+                "blocking-loop",
+                &line.tokens[0],
+                "loop in an async function never awaits, so nothing else can run while it does"
+                    .to_string(),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(src: &str, program: Program) -> Vec<(usize, &'static str, String)> {
+        lint(src, program)
+            .into_iter()
+            .map(|d| match d.location {
+                Location::Source { line, .. } => (line, d.code, d.message),
+                _ => panic!("unexpected location {:?}", d.location),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        let src = r#"
+from hub import port, light_matrix
+import motor, runloop
+
+async def main(speed=720, *args):
+    count = 0
+    for i in range(3):
+        await motor.run_for_degrees(port.A, 360, speed)
+        count += i
+    while True:
+        light_matrix.write(f"{count}")
+        await runloop.sleep_ms(100)
+    values = [x * 2 for x in args if x]
+    with open('f') as f:
+        print(f, values, lambda a, b: a + b)
+
+runloop.run(main())
+"#;
+        assert_eq!(codes(src, Program::Spike), vec![]);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let src = "x = (1,\ny = 'abc\n";
+        assert_eq!(
+            codes(src, Program::Spike),
+            vec![
+                (1, "syntax", "'(' was never closed".to_string()),
+                (2, "syntax", "unterminated string".to_string()),
+            ]
+        );
+        let src = "def f():\n    x = 1\n  y = 2\nif x\n    pass\nawait f()\n";
+        assert_eq!(
+            codes(src, Program::Spike),
+            vec![
+                (
+                    3,
+                    "syntax",
+                    "unindent does not match any outer indentation level".to_string()
+                ),
+                (4, "syntax", "expected ':' after 'if' statement".to_string()),
+                (5, "syntax", "unexpected indent".to_string()),
+                (6, "syntax", "'await' outside async function".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        let src = "import math\nfrom hub import port as p\nx = 1\nx.y = z\nprint(x, key=x)\n";
+        assert_eq!(
+            codes(src, Program::Spike),
+            vec![
+                (1, "unused-import", "'math' imported but unused".to_string()),
+                (
+                    2,
+                    "unused-import",
+                    "'hub.port' imported but unused".to_string()
+                ),
+                (4, "undefined-name", "undefined name 'z'".to_string()),
+            ]
+        );
+        assert_eq!(codes("a[i] = 1\n", Program::Spike).len(), 2);
+        assert_eq!(codes("from x import *\nprint(a)\n", Program::Spike), vec![]);
+    }
+
+    #[test]
+    fn test_runtime() {
+        let src = "from mindstorms import MSHub\nimport hub\nMSHub()\nhub.display.show(1)\n";
+        assert_eq!(
+            codes(src, Program::Spike),
+            vec![
+                (
+                    1,
+                    "runtime-api",
+                    "the mindstorms module is only available in the Mindstorms app".to_string()
+                ),
+                (
+                    4,
+                    "runtime-api",
+                    "hub.display is only available in the Mindstorms app".to_string()
+                ),
+            ]
+        );
+        assert_eq!(codes(src, Program::Mindstorms), vec![]);
+
+        let src =
+            "import runloop\nfrom hub import light_matrix\nrunloop.run(light_matrix.show())\n";
+        assert_eq!(codes(src, Program::Mindstorms).len(), 2);
+    }
+
+    #[test]
+    fn test_blocking_loop() {
+        let src = "import runloop\n\nasync def main():\n    while True:\n        pass\n    while True:\n        await runloop.sleep_ms(1)\n\nwhile True:\n    pass\n";
+        assert_eq!(
+            codes(src, Program::Spike),
+            vec![(
+                4,
+                "blocking-loop",
+                "loop in an async function never awaits, so nothing else can run while it does"
+                    .to_string()
+            )]
+        );
+    }
+}
@@ -1,19 +1,33 @@
 mod app;
+mod cache;
+mod catch_up;
 mod cli;
 mod commit;
+mod compact;
 mod config;
+mod convert;
 mod dirs;
+mod export;
+mod fsck;
+mod grep;
+mod import;
+mod lint;
+mod pending;
 mod project;
 mod render;
+mod restore;
+mod serve;
+mod stats;
 mod status;
 mod store;
 mod track;
+mod ui;
 mod untrack;
 
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::process::exit;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 use clap::Parser;
@@ -21,30 +35,62 @@ use config::{Config, StoreConfig};
 use notify_debouncer_full::notify::{Error, RecursiveMode};
 use notify_debouncer_full::{new_debouncer, DebounceEventResult, DebouncedEvent};
 use project::ProjectID;
+use rayon::prelude::*;
 use std::sync::mpsc::channel;
 use store::{Revision, Store};
 
 fn main() {
     let cli = cli::Cli::parse();
     let config = Config::load(cli.config.as_deref()).unwrap();
+    let no_cache = cli.no_cache;
     match cli.command {
-        None => cmd_status(Default::default(), config),
-        Some(cli::Commands::Status(status_cmd)) => cmd_status(status_cmd, config),
+        None => cmd_status(Default::default(), config, no_cache),
+        Some(cli::Commands::Status(status_cmd)) => cmd_status(status_cmd, config, no_cache),
         Some(cli::Commands::Store(store_cmd)) => cmd_store(store_cmd, config),
         Some(cli::Commands::Track(track_cmd)) => cmd_track(track_cmd, config),
         Some(cli::Commands::Untrack(untrack_cmd)) => cmd_untrack(untrack_cmd, config),
-        Some(cli::Commands::Commit) => cmd_commit(config),
+        Some(cli::Commands::Commit) => cmd_commit(config, no_cache),
         Some(cli::Commands::AutoCommit(auto_commit_cmd)) => {
-            cmd_auto_commit(auto_commit_cmd, config);
+            cmd_auto_commit(auto_commit_cmd, config, no_cache);
         }
         Some(cli::Commands::Log(log_cmd)) => cmd_log(log_cmd, config),
         Some(cli::Commands::Render(render_cmd)) => cmd_render(render_cmd, config),
+        Some(cli::Commands::SyncRenderedBranch(sync_cmd)) => {
+            cmd_sync_rendered_branch(sync_cmd, config)
+        }
+        Some(cli::Commands::Serve(serve_cmd)) => cmd_serve(serve_cmd, config),
+        Some(cli::Commands::Ui) => cmd_ui(config, no_cache),
+        Some(cli::Commands::Lint(lint_cmd)) => cmd_lint(lint_cmd, config),
+        Some(cli::Commands::Stats(stats_cmd)) => cmd_stats(stats_cmd, config),
+        Some(cli::Commands::Grep(grep_cmd)) => cmd_grep(grep_cmd, config),
+        Some(cli::Commands::Export(export_cmd)) => cmd_export(export_cmd, config),
+        Some(cli::Commands::Convert(convert_cmd)) => cmd_convert(convert_cmd, config),
+        Some(cli::Commands::Import(import_cmd)) => cmd_import(import_cmd, config),
+        Some(cli::Commands::Fsck(fsck_cmd)) => cmd_fsck(fsck_cmd, config),
+        Some(cli::Commands::Branch(branch_cmd)) => cmd_branch(branch_cmd, config),
+        Some(cli::Commands::Tag(tag_cmd)) => cmd_tag(tag_cmd, config),
+        Some(cli::Commands::Tags(tags_cmd)) => cmd_tags(tags_cmd, config),
+        Some(cli::Commands::Compact(compact_cmd)) => cmd_compact(compact_cmd, config),
+    }
+}
+
+fn load_cache(cfg: &Config, no_cache: bool) -> cache::Cache {
+    match no_cache {
+        true => cache::disabled(),
+        false => cache::load(cfg),
     }
 }
 
-fn cmd_status(cmd: cli::StatusCommand, cfg: Config) {
+fn save_cache(cache: &cache::Cache) {
+    if let Err(e) = cache.save() {
+        eprintln!("error saving cache: {e}");
+    }
+}
+
+fn cmd_status(cmd: cli::StatusCommand, cfg: Config, no_cache: bool) {
     let cli::StatusCommand { show_untracked } = cmd;
     let dirs = dirs::Dirs::new(&cfg).unwrap();
+    let cache = load_cache(&cfg, no_cache);
 
     let mut any_overrides = false;
     if let Some(p) = &cfg.mindstorms_path {
@@ -70,8 +116,8 @@ fn cmd_status(cmd: cli::StatusCommand, cfg: Config) {
     for (st, e) in err_stores {
         println!("  {st}: error opening store: {e}");
     }
-    let all_stores: Vec<Rc<Store>> = all_stores.into_iter().map(|(_, s)| Rc::new(s)).collect();
-    let mut projects: HashMap<ProjectID, Vec<Rc<Store>>> = HashMap::new();
+    let all_stores: Vec<Arc<Store>> = all_stores.into_iter().map(|(_, s)| Arc::new(s)).collect();
+    let mut projects: HashMap<ProjectID, Vec<Arc<Store>>> = HashMap::new();
     for store in &all_stores {
         println!("  {store}");
         match store.project_ids() {
@@ -86,6 +132,16 @@ fn cmd_status(cmd: cli::StatusCommand, cfg: Config) {
             }
         };
     }
+    match pending::load(&cfg) {
+        Err(e) => println!("  error reading queued commits: {e}"),
+        Ok(queue) => {
+            for (path, n) in queue.counts() {
+                let st = cfg.stores.iter().find(|st| st.path == path);
+                let st = st.map_or(path.display().to_string(), |st| st.to_string());
+                println!("  {st}: {n} queued commits to catch up on (retried on the next commit)");
+            }
+        }
+    }
     println!();
 
     for proj in app::all_projects(&dirs).expect("unexpected error") {
@@ -100,32 +156,42 @@ fn cmd_status(cmd: cli::StatusCommand, cfg: Config) {
 
     println!("Projects:");
     let all_stores_count = all_stores.len();
-    let mut untracked = Vec::new();
-    for (proj, proj_stores) in projects {
-        match proj_stores.is_empty() {
-            true => untracked.push(proj),
-            false => match status::get_status(&proj, &all_stores, &dirs) {
-                Err(e) => println!("  {proj}! error: {e}"),
-                Ok(status::Status::NoDifferences) => println!("  {proj}: up to date"),
-                Ok(status::Status::LocalMissing) => {
-                    println!("  {proj}: local copy has been deleted");
-                    println!("    To stop tracking it, run:");
-                    println!("      {} untrack --{} {:?}", exe(), proj.program, proj.name);
-                }
-                Ok(status::Status::Differences(out_of_date_stores)) => {
-                    let store_list_count = out_of_date_stores.len();
-                    let store_list: Vec<String> = out_of_date_stores
-                        .iter()
-                        .map(|st| format!("{st}"))
-                        .collect();
-                    let store_list = store_list.join("; ");
-                    println!(
-                        "  {proj}: {store_list_count}/{all_stores_count} stores need sync: {store_list}"
-                    );
-                }
-            },
+    // Sorted, so that projects are listed in the same order every time.
+    let mut projects: Vec<(ProjectID, Vec<Arc<Store>>)> = projects.into_iter().collect();
+    projects.sort_by_key(|(proj, _)| proj.to_string());
+    let (tracked, untracked): (Vec<_>, Vec<_>) =
+        projects.into_iter().partition(|(_, st)| !st.is_empty());
+    let untracked: Vec<ProjectID> = untracked.into_iter().map(|(proj, _)| proj).collect();
+    // Checked in parallel. Errors can't be sent between threads, so only their messages are kept.
+    let statuses: Vec<Result<status::Status, String>> = tracked
+        .par_iter()
+        .map(|(proj, _)| {
+            status::get_status(proj, &all_stores, &dirs, &cache).map_err(|e| e.to_string())
+        })
+        .collect();
+    for ((proj, _), status) in tracked.iter().zip(statuses) {
+        match status {
+            Err(e) => println!("  {proj}! error: {e}"),
+            Ok(status::Status::NoDifferences) => println!("  {proj}: up to date"),
+            Ok(status::Status::LocalMissing) => {
+                println!("  {proj}: local copy has been deleted");
+                println!("    To stop tracking it, run:");
+                println!("      {} untrack --{} {:?}", exe(), proj.program, proj.name);
+            }
+            Ok(status::Status::Differences(out_of_date_stores)) => {
+                let store_list_count = out_of_date_stores.len();
+                let store_list: Vec<String> = out_of_date_stores
+                    .iter()
+                    .map(|st| format!("{st}"))
+                    .collect();
+                let store_list = store_list.join("; ");
+                println!(
+                    "  {proj}: {store_list_count}/{all_stores_count} stores need sync: {store_list}"
+                );
+            }
         };
     }
+    save_cache(&cache);
 
     if !untracked.is_empty() {
         println!();
@@ -153,20 +219,50 @@ fn cmd_store(cmd: cli::StoreCommand, config: Config) {
     match cmd.subcommand {
         cli::StoreSubcommand::Create(args) => cmd_store_create(args, config),
         cli::StoreSubcommand::Remove(args) => cmd_store_remove(args, config),
+        cli::StoreSubcommand::List => cmd_store_list(config),
+        cli::StoreSubcommand::Info(args) => cmd_store_info(args, config),
+        cli::StoreSubcommand::Move(args) => cmd_store_move(args, config),
+        cli::StoreSubcommand::CatchUp(args) => cmd_store_catch_up(args, config),
     }
 }
 
 fn cmd_store_create(args: cli::CreateStoreArgs, mut config: Config) {
-    let cli::CreateStoreArgs { store_type, path } = args;
+    let cli::CreateStoreArgs {
+        store_type,
+        path,
+        passphrase,
+        key_file,
+    } = args;
     for st in &config.stores {
         if store::paths_match(&st.path, &path) {
             println!("Already using {st}");
             return;
         }
     }
-    let store = store::create(&store_type, path).unwrap().into();
-    println!("Started using {}", store);
-    config.stores.push(store);
+    let (path, key_file) = match (
+        std::path::absolute(&path),
+        key_file.map(std::path::absolute).transpose(),
+    ) {
+        (Ok(path), Ok(key_file)) => (path, key_file),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let st = StoreConfig {
+        path,
+        store_type,
+        rendered_branch: None,
+        branch: None,
+        passphrase,
+        key_file,
+    };
+    if let Err(e) = store::create(&st) {
+        eprintln!("Couldn't create {st}: {e}");
+        exit(1);
+    }
+    println!("Started using {st}");
+    config.stores.push(st);
     config.store().unwrap();
 }
 
@@ -182,11 +278,193 @@ fn cmd_store_remove(args: cli::RemoveStoreArgs, mut config: Config) {
             new_stores.push(st);
         }
     }
+    if removed == 0 {
+        println!("Not using a store at {}", path.display());
+        exit(1);
+    }
     config.stores = new_stores;
     config.store().unwrap();
     println!("Stores removed: {removed}");
 }
 
+fn cmd_store_list(cfg: Config) {
+    if cfg.stores.is_empty() {
+        println!("No stores yet!");
+        println!("Get started by running '{} store create'.", exe());
+        return;
+    }
+    for st in &cfg.stores {
+        println!("{st}");
+        println!("  path: {}", st.path.display());
+        let store = match store::open(st) {
+            Ok(store) => store,
+            Err(e) => {
+                println!("  unreachable: {e}");
+                continue;
+            }
+        };
+        match store.head() {
+            Ok(None) => println!("  head: (no commits)"),
+            Ok(Some(c)) => println!("  head: {} ({})", c.hash, format_datetime(c.date)),
+            Err(e) => println!("  head! error: {e}"),
+        }
+        match store.project_ids() {
+            Ok(ids) => println!("  projects: {}", ids.len()),
+            Err(e) => println!("  projects! error: {e}"),
+        }
+    }
+}
+
+fn cmd_store_info(args: cli::StoreInfoArgs, cfg: Config) {
+    let cli::StoreInfoArgs { path } = args;
+    let Some(st) = get_single_store(&cfg, Some(path)) else {
+        exit(1);
+    };
+    let info = store::open(st).and_then(|store| store.info());
+    let store::StoreInfo {
+        size,
+        commits,
+        first_commit,
+        last_commit,
+        projects,
+    } = match info {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("{st}: {e}");
+            exit(1);
+        }
+    };
+    println!("{st}");
+    println!("  path: {}", st.path.display());
+    match size {
+        0..1_000_000 => println!("  size: {:.1} KB", size as f64 / 1_000.0),
+        _ => println!("  size: {:.1} MB", size as f64 / 1_000_000.0),
+    }
+    println!("  commits: {commits}");
+    if let (Some(first), Some(last)) = (first_commit, last_commit) {
+        println!("  first commit: {}", format_datetime(first));
+        println!("  last commit: {}", format_datetime(last));
+    }
+    println!("  tracked projects: {}", projects.len());
+    for id in projects {
+        println!("    {id}");
+    }
+}
+
+fn cmd_store_move(args: cli::MoveStoreArgs, mut cfg: Config) {
+    let cli::MoveStoreArgs { old, new } = args;
+    let Some(index) = cfg
+        .stores
+        .iter()
+        .position(|st| store::paths_match(&st.path, &old))
+    else {
+        eprintln!("Not using a store at {}", old.display());
+        exit(1);
+    };
+    let new = match std::path::absolute(&new) {
+        Ok(new) => new,
+        Err(e) => {
+            eprintln!("{}: {e}", new.display());
+            exit(1);
+        }
+    };
+    let old = cfg.stores[index].path.clone();
+    if let Err(e) = store::relocate(&old, &new) {
+        eprintln!("Couldn't move {} to {}: {e}", old.display(), new.display());
+        exit(1);
+    }
+
+    let moved = StoreConfig {
+        path: new.clone(),
+        ..cfg.stores[index].clone()
+    };
+    if let Err(e) = store::open(&moved) {
+        eprintln!("Couldn't open the store after moving it: {e}");
+        match store::relocate(&new, &old) {
+            Ok(()) => eprintln!("Moved it back to {}", old.display()),
+            Err(e) => eprintln!("Couldn't move it back to {}: {e}", old.display()),
+        }
+        exit(1);
+    }
+    cfg.stores[index] = moved;
+    cfg.store().unwrap();
+    if let Err(e) = pending::load(&cfg).and_then(|mut queue| queue.rename_store(&old, &new)) {
+        eprintln!("Couldn't update queued commits: {e}");
+    }
+    println!("Moved {} to {}", old.display(), new.display());
+}
+
+fn cmd_store_catch_up(args: cli::CatchUpStoreArgs, cfg: Config) {
+    let cli::CatchUpStoreArgs { from, to } = args;
+    let (sources, targets) = (select_stores(&cfg, from), select_stores(&cfg, to));
+    if cfg.stores.len() < 2 {
+        println!("Catching up needs at least two stores.");
+        exit(1);
+    }
+
+    let mut error_count = 0;
+    for to_st in &targets {
+        for from_st in &sources {
+            if store::paths_match(&from_st.path, &to_st.path) {
+                continue;
+            }
+            println!("Catching up {to_st} from {from_st}:");
+            let res = store::open(from_st).and_then(|from| {
+                let to = store::open(to_st)?;
+                catch_up::catch_up(&from, &to)
+            });
+            let catch_up::CatchUpResult {
+                replayed,
+                restored,
+                untracked,
+            } = match res {
+                Ok(res) => res,
+                Err(e) => {
+                    error_count += 1;
+                    println!("  error: {e}");
+                    continue;
+                }
+            };
+            if replayed.is_empty() {
+                println!("  already up to date");
+            }
+            for r in replayed {
+                let projects: Vec<String> = r.projects.iter().map(|id| id.to_string()).collect();
+                let date = format_datetime(r.date);
+                match r.result {
+                    Ok(msg) => println!("  {date} {}: {}: {msg}", r.message, projects.join(", ")),
+                    Err(e) => {
+                        error_count += 1;
+                        println!("  {date} {}! error: {e}", r.message);
+                    }
+                }
+            }
+            if let Some((projects, res)) = restored {
+                let projects: Vec<String> = projects.iter().map(|id| id.to_string()).collect();
+                match res {
+                    Ok(msg) => println!("  restored latest {}: {msg}", projects.join(", ")),
+                    Err(e) => {
+                        error_count += 1;
+                        println!("  error restoring latest {}: {e}", projects.join(", "));
+                    }
+                }
+            }
+            for (id, res) in untracked {
+                match res {
+                    Ok(msg) => println!("  untracked {id}, as in {from_st}: {msg}"),
+                    Err(e) => {
+                        error_count += 1;
+                        println!("  error untracking {id}: {e}");
+                    }
+                }
+            }
+        }
+    }
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
 fn cmd_track(cmd: cli::TrackCommand, cfg: Config) {
     let cli::TrackCommand {
         spike,
@@ -207,25 +485,30 @@ fn cmd_track(cmd: cli::TrackCommand, cfg: Config) {
             exit(1);
         }
         Ok(res) => {
-            let track::TrackResult { id, store_results } = res;
-            println!("Now tracking {id}");
-            let mut error_count = 0;
-            for (st, st_res) in store_results {
-                match st_res {
-                    Ok(msg) => println!("  {st}: {msg}"),
-                    Err(e) => {
-                        error_count += 1;
-                        println!("  {st}! error: {e}")
-                    }
-                };
-            }
-            if error_count > 0 {
+            if print_track_result(res) > 0 {
                 exit(1);
             }
         }
     };
 }
 
+/// Prints the result of tracking a project in each store. Returns the number of errors.
+fn print_track_result(res: track::TrackResult) -> usize {
+    let track::TrackResult { id, store_results } = res;
+    println!("Now tracking {id}");
+    let mut error_count = 0;
+    for (st, st_res) in store_results {
+        match st_res {
+            Ok(msg) => println!("  {st}: {msg}"),
+            Err(e) => {
+                error_count += 1;
+                println!("  {st}! error: {e}")
+            }
+        };
+    }
+    error_count
+}
+
 fn cmd_untrack(cmd: cli::UntrackCommand, cfg: Config) {
     let cli::UntrackCommand {
         spike,
@@ -265,8 +548,9 @@ fn cmd_untrack(cmd: cli::UntrackCommand, cfg: Config) {
     };
 }
 
-fn cmd_commit(cfg: Config) {
+fn cmd_commit(cfg: Config, no_cache: bool) {
     let dirs = dirs::Dirs::new(&cfg).unwrap();
+    let cache = load_cache(&cfg, no_cache);
 
     if cfg.stores.is_empty() {
         println!("No stores yet!");
@@ -305,8 +589,18 @@ fn cmd_commit(cfg: Config) {
     let commit::CommitResult {
         missing_projects,
         project_read_errors,
+        retried,
         store_results,
-    } = commit::commit(&stores, &dirs, &tracked_projects, "Update tracked projects");
+        queued,
+    } = commit::commit(
+        &cfg,
+        &stores,
+        &dirs,
+        &tracked_projects,
+        "Update tracked projects",
+        &cache,
+    );
+    save_cache(&cache);
 
     for proj_id in missing_projects {
         println!("Project {proj_id} does not exist on this computer.");
@@ -322,17 +616,22 @@ fn cmd_commit(cfg: Config) {
     for (proj_id, e) in project_read_errors {
         println!("{proj_id}: error reading project: {e}");
     }
+    print_retried(retried);
     for (st, res) in store_results {
         match res {
             Ok(msg) => println!("{st}: {msg}"),
             Err(e) => println!("{st}! {e}"),
         };
     }
+    print_queued(queued);
+
+    sync_rendered_branches(&stores);
 }
 
-fn cmd_auto_commit(opts: cli::AutoCommitCommand, cfg: Config) {
+fn cmd_auto_commit(opts: cli::AutoCommitCommand, mut cfg: Config, no_cache: bool) {
     let cli::AutoCommitCommand { interval } = opts;
     let dirs = dirs::Dirs::new(&cfg).unwrap();
+    let cache = load_cache(&cfg, no_cache);
 
     if cfg.stores.is_empty() {
         println!("No stores yet!");
@@ -341,7 +640,7 @@ fn cmd_auto_commit(opts: cli::AutoCommitCommand, cfg: Config) {
     }
 
     // Find all tracked projects
-    let (stores, err_stores) = store::open_all(&cfg.stores);
+    let (mut stores, err_stores) = store::open_all(&cfg.stores);
     for (st, e) in err_stores {
         println!("{st}: error opening store: {e}");
     }
@@ -433,12 +732,21 @@ fn cmd_auto_commit(opts: cli::AutoCommitCommand, cfg: Config) {
                     }
                 }
 
-                let (stores, err_stores) = store::open_all(&cfg.stores);
-                for (st, e) in err_stores {
-                    println!("{st}! error opening store: {e}")
+                // Pick up branch switches made since auto-commit started. The stores are only
+                // opened again if the config changed, so that they keep what they've already
+                // loaded (e.g. an encrypted store's index).
+                if let Ok(reloaded) = cfg.reload()
+                    && reloaded != cfg
+                {
+                    cfg = reloaded;
+                    let err_stores;
+                    (stores, err_stores) = store::open_all(&cfg.stores);
+                    for (st, e) in err_stores {
+                        println!("{st}! error opening store: {e}")
+                    }
                 }
 
-                do_auto_commit(&stores, &dirs, &proj_ids);
+                do_auto_commit(&cfg, &stores, &dirs, &proj_ids, &cache);
                 println!();
             }
             AutoCommitEvent::WatchError(e) => println!("watch error: {e}"),
@@ -447,7 +755,7 @@ fn cmd_auto_commit(opts: cli::AutoCommitCommand, cfg: Config) {
                     "[{}] Auto-committing changed projects on shutdown:",
                     chrono::Local::now()
                 );
-                do_auto_commit(&stores, &dirs, &tracked_projects);
+                do_auto_commit(&cfg, &stores, &dirs, &tracked_projects, &cache);
                 return;
             }
         }
@@ -455,20 +763,27 @@ fn cmd_auto_commit(opts: cli::AutoCommitCommand, cfg: Config) {
 }
 
 fn do_auto_commit(
+    cfg: &Config,
     stores: &[(StoreConfig, Store)],
     dirs: &dirs::Dirs,
     proj_ids: &HashSet<ProjectID>,
+    cache: &cache::Cache,
 ) {
     let commit::CommitResult {
         missing_projects,
         project_read_errors,
+        retried,
         store_results,
+        queued,
     } = commit::commit(
+        cfg,
         stores,
         dirs,
         proj_ids,
         "Update tracked projects via auto-commit",
+        cache,
     );
+    save_cache(cache);
 
     for proj_id in missing_projects {
         println!("Project {proj_id} is now missing.");
@@ -483,16 +798,77 @@ fn do_auto_commit(
     for (proj_id, e) in project_read_errors {
         println!("{proj_id}: error reading project: {e}");
     }
+    print_retried(retried);
     for (st, res) in store_results {
         match res {
             Ok(msg) => println!("{st}: {msg}"),
             Err(e) => println!("{st}! {e}"),
         };
     }
+    print_queued(queued);
+
+    sync_rendered_branches(stores);
+}
+
+fn print_retried(retried: Vec<(StoreConfig, String, store::CommitResult)>) {
+    for (st, message, res) in retried {
+        match res {
+            Ok(msg) => println!("{st}: caught up on {message:?}: {msg}"),
+            Err(e) => println!("{st}! still can't commit {message:?}: {e}"),
+        };
+    }
+}
+
+fn print_queued(queued: Result<Vec<StoreConfig>, Box<dyn std::error::Error>>) {
+    match queued {
+        Ok(stores) => {
+            for st in stores {
+                println!("{st}: queued, will retry on the next commit");
+            }
+        }
+        Err(e) => println!("error queueing commits to retry later: {e}"),
+    }
+}
+
+/// Brings the rendered branch up to date in each store that has one configured.
+fn sync_rendered_branches(stores: &[(StoreConfig, Store)]) {
+    for (st, store) in stores {
+        if let Some(branch) = &st.rendered_branch {
+            print_sync_result(st, branch, render::branch::sync(store, branch));
+        }
+    }
+}
+
+fn print_sync_result(
+    st: &StoreConfig,
+    branch: &str,
+    res: Result<render::branch::SyncResult, Box<dyn std::error::Error>>,
+) {
+    match res {
+        Err(e) => println!("{st}! error rendering to {branch}: {e}"),
+        Ok(render::branch::SyncResult {
+            rendered,
+            project_errors,
+        }) => {
+            for (source, render) in &rendered {
+                println!("{st}: rendered {source} to {branch} as {render}");
+            }
+            for (source, proj_id, e) in project_errors {
+                println!("{st}: {source} {proj_id}! {e}");
+            }
+            if rendered.is_empty() {
+                println!("{st}: {branch} is up to date");
+            }
+        }
+    };
 }
 
 fn cmd_log(cmd: cli::LogCommand, cfg: Config) {
-    let cli::LogCommand { since, store } = cmd;
+    let cli::LogCommand {
+        since,
+        until,
+        store,
+    } = cmd;
 
     let target_store = match get_single_store(&cfg, store) {
         None => exit(1),
@@ -508,7 +884,24 @@ fn cmd_log(cmd: cli::LogCommand, cfg: Config) {
         }
     };
 
-    match store.log(SystemTime::now() - since) {
+    let now = SystemTime::now();
+    if let Some(until) = until {
+        match store.commits_between(now - since, Some(now - until)) {
+            Err(e) => {
+                eprintln!("Failed to get log: {}", e);
+                exit(1);
+            }
+            Ok(commits) if commits.is_empty() => println!(
+                "No commits between {} and {} ago.",
+                format_duration_ago(since),
+                format_duration_ago(until)
+            ),
+            Ok(commits) => print_commits(commits),
+        }
+        return;
+    }
+
+    match store.log(now - since) {
         Err(e) => {
             eprintln!("Failed to get log: {}", e);
             exit(1);
@@ -519,23 +912,25 @@ fn cmd_log(cmd: cli::LogCommand, cfg: Config) {
             format_duration_ago(since),
             format_time_ago(newest_commit.date)
         ),
-        Ok(store::LogResult::Some(commits)) => {
-            for commit in commits {
-                println!(
-                    "{} {} ({}) {}",
-                    commit.hash,
-                    format_time_ago(commit.date),
-                    format_datetime(commit.date),
-                    commit.message
-                );
-                for proj_id in commit.changed_projects {
-                    println!("  +/- {proj_id}");
-                }
-            }
-        }
+        Ok(store::LogResult::Some(commits)) => print_commits(commits),
     };
 }
 
+fn print_commits(commits: Vec<store::CommitInfo>) {
+    for commit in commits {
+        println!(
+            "{} {} ({}) {}",
+            commit.hash,
+            format_time_ago(commit.date),
+            format_datetime(commit.date),
+            commit.message
+        );
+        for proj_id in commit.changed_projects {
+            println!("  +/- {proj_id}");
+        }
+    }
+}
+
 fn cmd_render(opts: cli::RenderCommand, cfg: config::Config) {
     let cli::RenderCommand {
         dest,
@@ -545,6 +940,8 @@ fn cmd_render(opts: cli::RenderCommand, cfg: config::Config) {
     let cli::RenderDest {
         out_dir,
         to_store: tree,
+        output,
+        html,
     } = dest;
 
     let target_store = match get_single_store(&cfg, store) {
@@ -569,19 +966,36 @@ fn cmd_render(opts: cli::RenderCommand, cfg: config::Config) {
         },
     };
 
-    let res = match (out_dir, tree) {
-        (Some(out_dir), false) => render::render_all_projects(
+    let res = match (out_dir, tree, output, html) {
+        (Some(out_dir), false, None, None) => render::render_all_projects(
             render::fs::out_dir(out_dir),
             render::txt::TextFormatter,
             &store,
             revision,
         ),
-        (None, true) => render::render_all_projects(
+        (None, true, None, None) => render::render_all_projects(
             render::store::tree(&store),
             render::txt::TextFormatter,
             &store,
             revision,
         ),
+        (None, false, Some(output), None) => render::file::output(output).and_then(|dest| {
+            render::render_all_projects(dest, render::txt::TextFormatter, &store, revision)
+        }),
+        (None, false, None, Some(html)) => {
+            // The site is organized by revision, so it needs a specific one.
+            let revision = match revision {
+                Revision::Latest => store
+                    .latest()
+                    .and_then(|r| r.ok_or_else(|| "the store has no commits yet".into())),
+                r => Ok(r),
+            };
+            revision
+                .and_then(|revision| Ok((render::html::site(html, &store, &revision)?, revision)))
+                .and_then(|(site, revision)| {
+                    render::render_all_projects(site, render::html::HtmlFormatter, &store, revision)
+                })
+        }
         _ => unreachable!(),
     };
 
@@ -591,6 +1005,58 @@ fn cmd_render(opts: cli::RenderCommand, cfg: config::Config) {
     };
 }
 
+fn cmd_sync_rendered_branch(opts: cli::SyncRenderedBranchCommand, mut cfg: config::Config) {
+    let cli::SyncRenderedBranchCommand {
+        branch,
+        store,
+        auto,
+        no_auto,
+    } = opts;
+    let branch = branch.unwrap_or_else(|| render::branch::DEFAULT_BRANCH.to_string());
+
+    let target_store = match get_single_store(&cfg, store) {
+        None => exit(1),
+        Some(s) => s.clone(),
+    };
+    let store = match store::open(&target_store) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("{target_store}: error opening store: {e}");
+            exit(1);
+        }
+    };
+
+    print_sync_result(
+        &target_store,
+        &branch,
+        render::branch::sync(&store, &branch),
+    );
+
+    if auto || no_auto {
+        for st in &mut cfg.stores {
+            if store::paths_match(&st.path, &target_store.path) {
+                st.rendered_branch = auto.then(|| branch.clone());
+            }
+        }
+        cfg.store().unwrap();
+        match auto {
+            true => println!("{target_store}: {branch} will be rendered after every commit"),
+            false => println!("{target_store}: no longer rendering after commits"),
+        };
+    }
+}
+
+/// Returns the store at path, or every store if it's None. Exits if there's no store at path.
+fn select_stores(cfg: &config::Config, path: Option<PathBuf>) -> Vec<&StoreConfig> {
+    match path {
+        None => cfg.stores.iter().collect(),
+        Some(path) => match get_single_store(cfg, Some(path)) {
+            None => exit(1),
+            Some(st) => vec![st],
+        },
+    }
+}
+
 fn get_single_store(cfg: &config::Config, store: Option<PathBuf>) -> Option<&StoreConfig> {
     if cfg.stores.is_empty() {
         println!("No stores yet!");
@@ -675,3 +1141,708 @@ fn exe() -> String {
         .next()
         .unwrap_or_else(|| "mind-meld".to_string())
 }
+
+fn cmd_serve(cmd: cli::ServeCommand, cfg: Config) {
+    let cli::ServeCommand { port } = cmd;
+    if let Err(e) = serve::serve(&cfg, port) {
+        eprintln!("Error running server: {e}");
+        exit(1);
+    }
+}
+
+fn cmd_ui(cfg: Config, no_cache: bool) {
+    let cache = load_cache(&cfg, no_cache);
+    if let Err(e) = ui::run(cfg, cache) {
+        eprintln!("{e}");
+        exit(1);
+    }
+}
+
+fn cmd_lint(cmd: cli::LintCommand, cfg: Config) {
+    let cli::LintCommand {
+        revision,
+        store,
+        json,
+    } = cmd;
+    let res = match revision {
+        None => lint::lint_local(&cfg),
+        Some(expr) => {
+            let target_store = match get_single_store(&cfg, store) {
+                None => exit(1),
+                Some(s) => s,
+            };
+            store::open(target_store).and_then(|store| {
+                let revision = store.resolve(&expr)?;
+                lint::lint_revision(&store, &revision)
+            })
+        }
+    };
+    let projects = match res {
+        Ok(projects) => projects,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    for (proj_id, diags) in &projects {
+        match diags {
+            Err(e) => {
+                errors += 1;
+                if !json {
+                    println!("{proj_id}! error: {e}");
+                }
+            }
+            Ok(diags) => {
+                if !json && !diags.is_empty() {
+                    println!("{proj_id}:");
+                }
+                for diag in diags {
+                    match diag.severity {
+                        lint::Severity::Error => errors += 1,
+                        lint::Severity::Warning => warnings += 1,
+                    };
+                    if !json {
+                        println!("  {diag}");
+                    }
+                }
+            }
+        };
+    }
+    match json {
+        true => println!("{:#}", lint::to_json(&projects)),
+        false => println!(
+            "Checked {} projects: {errors} errors, {warnings} warnings",
+            projects.len()
+        ),
+    };
+    if errors > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_stats(cmd: cli::StatsCommand, cfg: Config) {
+    let cli::StatsCommand {
+        since,
+        store,
+        format,
+    } = cmd;
+    let target_store = match get_single_store(&cfg, store) {
+        None => exit(1),
+        Some(s) => s,
+    };
+    let since = since.map_or(SystemTime::UNIX_EPOCH, |d| SystemTime::now() - d);
+    let res = store::open(target_store).and_then(|store| stats::stats(&store, since));
+    let projects = match res {
+        Ok(projects) => projects,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+
+    match format {
+        cli::StatsFormat::Table if projects.is_empty() => println!("No changes"),
+        cli::StatsFormat::Table => print!("{}", stats::table(&projects)),
+        cli::StatsFormat::Csv => print!("{}", stats::csv(&projects)),
+        cli::StatsFormat::Json => println!("{:#}", stats::to_json(&projects)),
+    }
+    for s in &projects {
+        for (hash, e) in &s.errors {
+            eprintln!("{} at {hash}: {e}", s.id);
+        }
+    }
+}
+
+fn cmd_grep(cmd: cli::GrepCommand, cfg: Config) {
+    let cli::GrepCommand {
+        pattern,
+        ignore_case,
+        program,
+        all_revisions,
+        store,
+    } = cmd;
+    let pattern = match regex::RegexBuilder::new(&pattern)
+        .case_insensitive(ignore_case)
+        .build()
+    {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(2);
+        }
+    };
+
+    let mut res = grep::grep_local(&cfg, &pattern, program);
+    if all_revisions {
+        let target_store = match get_single_store(&cfg, store) {
+            None => exit(2),
+            Some(s) => s,
+        };
+        res = res.and_then(|mut res| {
+            let store = store::open(target_store)?;
+            res.extend(grep::grep_history(&store, &pattern, program)?);
+            Ok(res)
+        });
+    }
+    let projects = match res {
+        Ok(projects) => projects,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(2);
+        }
+    };
+
+    let mut found = false;
+    for proj in &projects {
+        let revision = match &proj.revision {
+            None => String::new(),
+            Some((hash, date)) => format!(" @ {hash} ({})", format_datetime(*date)),
+        };
+        match &proj.matches {
+            Err(e) => eprintln!("{}{revision}! error: {e}", proj.id),
+            Ok(matches) => {
+                found = true;
+                println!("{}{revision}:", proj.id);
+                for m in matches {
+                    println!("  {m}");
+                }
+            }
+        }
+    }
+    if !found {
+        exit(1);
+    }
+}
+
+fn cmd_export(cmd: cli::ExportCommand, cfg: Config) {
+    let cli::ExportCommand {
+        sb3: _,
+        project,
+        program,
+        revision,
+        store,
+        stub_extensions,
+        output,
+        force,
+    } = cmd;
+    let Some(program) = program.or_else(|| project::Program::from_file_name(&project)) else {
+        eprintln!("Can't tell which app {project} is from, use --program");
+        exit(1);
+    };
+    let id = ProjectID {
+        program,
+        name: project,
+    };
+    let raw = match revision {
+        None => dirs::Dirs::new(&cfg)
+            .map_err(Into::into)
+            .and_then(|dirs| project::read(&id, &dirs)),
+        Some(expr) => {
+            let target_store = match get_single_store(&cfg, store) {
+                None => exit(1),
+                Some(s) => s,
+            };
+            store::open(target_store).and_then(|store| {
+                let revision = store.resolve(&expr)?;
+                store.read_project(&id, &revision)
+            })
+        }
+    };
+    let raw = match raw {
+        Ok(Some(raw)) => raw,
+        Ok(None) => {
+            eprintln!("Project not found: {id}");
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+
+    let output = output.unwrap_or_else(|| PathBuf::from(&id.name).with_extension("sb3"));
+    match export::export_sb3(raw, stub_extensions, &output, force) {
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+        Ok(stubbed) => {
+            println!("Exported {id} to {}", output.display());
+            if stub_extensions {
+                println!("Replaced {stubbed} LEGO blocks with stubs");
+            }
+        }
+    }
+}
+
+fn cmd_convert(cmd: cli::ConvertCommand, cfg: Config) {
+    let cli::ConvertCommand {
+        project,
+        program,
+        to,
+        track,
+        force,
+    } = cmd;
+    let Some(program) = program.or_else(|| project::Program::from_file_name(&project)) else {
+        eprintln!("Can't tell which app {project} is from, use --program");
+        exit(1);
+    };
+    let id = ProjectID {
+        program,
+        name: project,
+    };
+    let res = match convert::convert(&cfg, &id, to, force) {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+
+    println!("Converted {id} to {}", res.path.display());
+    if !res.unsupported.is_empty() {
+        println!("Not supported by the {to} app:");
+        for problem in &res.unsupported {
+            println!("  {problem}");
+        }
+    }
+    if track {
+        let file_name = PathBuf::from(&res.id.name);
+        match track::track(cfg, to, file_name) {
+            Err(e) => {
+                eprintln!("{e}");
+                exit(1);
+            }
+            Ok(res) => {
+                if print_track_result(res) > 0 {
+                    exit(1);
+                }
+            }
+        }
+    }
+}
+
+fn cmd_import(cmd: cli::ImportCommand, cfg: Config) {
+    let cli::ImportCommand {
+        paths,
+        program,
+        date,
+        group,
+        dry_run,
+    } = cmd;
+    if cfg.stores.is_empty() {
+        println!("No stores yet!");
+        println!("Get started by running '{} store create'.", exe());
+        exit(1);
+    }
+    let files = match import::scan(&paths, program, date, group) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let describe = |file: &import::ImportFile| {
+        let date = file.date.map(format_datetime).unwrap_or("now".to_string());
+        format!("{} -> {} ({date})", file.path.display(), file.id)
+    };
+    if dry_run {
+        for file in &files {
+            println!("{}", describe(file));
+        }
+        return;
+    }
+
+    let import::ImportResults {
+        files,
+        open_errors,
+        restored,
+    } = match import::import(&cfg, files) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let mut error_count = 0;
+    for (st, e) in open_errors {
+        println!("{st}! error opening store: {e}");
+    }
+    for import::ImportResult {
+        file,
+        store_results,
+        queued,
+    } in files
+    {
+        println!("{}", describe(&file));
+        match store_results {
+            Err(e) => {
+                error_count += 1;
+                println!("  error: {e}");
+            }
+            Ok(store_results) => {
+                for (st, st_res) in store_results {
+                    match st_res {
+                        Ok(msg) => println!("  {st}: {msg}"),
+                        Err(e) => {
+                            error_count += 1;
+                            println!("  {st}! error: {e}")
+                        }
+                    };
+                }
+            }
+        }
+        match queued {
+            Ok(stores) => {
+                for st in stores {
+                    println!("  {st}: queued, will retry on the next commit");
+                }
+            }
+            Err(e) => {
+                error_count += 1;
+                println!("  error queueing commits to retry later: {e}");
+            }
+        }
+    }
+    for (st, ids, res) in restored {
+        let ids: Vec<String> = ids.iter().map(ToString::to_string).collect();
+        match res {
+            Ok(msg) => println!("{st}: restored the latest {}: {msg}", ids.join(", ")),
+            Err(e) => {
+                error_count += 1;
+                println!("{st}! error restoring the latest {}: {e}", ids.join(", "));
+            }
+        }
+    }
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_fsck(cmd: cli::FsckCommand, cfg: Config) {
+    let cli::FsckCommand {
+        all_revisions,
+        store,
+    } = cmd;
+    let stores: Vec<&StoreConfig> = match store {
+        Some(_) => match get_single_store(&cfg, store) {
+            None => exit(1),
+            Some(s) => vec![s],
+        },
+        None => cfg.stores.iter().collect(),
+    };
+    if stores.is_empty() {
+        println!("No stores yet!");
+        println!("Get started by running '{} store create'.", exe());
+        exit(1);
+    }
+
+    let mut ok = true;
+    for st in stores {
+        let res = store::open(st).and_then(|store| fsck::fsck(&store, all_revisions));
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                ok = false;
+                println!("{st}! error: {e}");
+                continue;
+            }
+        };
+        if res.problems.is_empty() {
+            println!(
+                "{st}: ok ({} projects in {} revisions)",
+                res.projects, res.revisions
+            );
+            continue;
+        }
+        ok = false;
+        for rev in res.problems {
+            match rev.revision {
+                None => println!("{st}:"),
+                Some((hash, date)) => println!("{st} @ {hash} ({}):", format_datetime(date)),
+            }
+            for problem in rev.problems {
+                match problem.id {
+                    None => println!("  {}", problem.message),
+                    Some(id) => println!("  {id}: {}", problem.message),
+                }
+            }
+        }
+    }
+    if !ok {
+        exit(1);
+    }
+}
+
+fn cmd_branch(cmd: cli::BranchCommand, cfg: Config) {
+    if cfg.stores.is_empty() {
+        println!("No stores yet!");
+        println!("Get started by running '{} store create'.", exe());
+        exit(1);
+    }
+    match cmd.subcommand {
+        cli::BranchSubcommand::Create(args) => cmd_branch_create(args, cfg),
+        cli::BranchSubcommand::Switch(args) => cmd_branch_switch(args, cfg),
+        cli::BranchSubcommand::List(args) => cmd_branch_list(args, cfg),
+    }
+}
+
+fn cmd_branch_create(args: cli::CreateBranchArgs, cfg: Config) {
+    let cli::CreateBranchArgs { name, from, store } = args;
+    let mut error_count = 0;
+    for st in select_stores(&cfg, store) {
+        let res = store::open(st).and_then(|store| {
+            let from = from.as_deref().map(|r| store.resolve(r)).transpose()?;
+            store.create_branch(&name, from.as_ref())
+        });
+        match res {
+            Ok(()) => println!("{st}: created {name}"),
+            Err(e) => {
+                error_count += 1;
+                println!("{st}! error: {e}");
+            }
+        }
+    }
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_branch_switch(args: cli::SwitchBranchArgs, mut cfg: Config) {
+    let cli::SwitchBranchArgs { name, store } = args;
+    let paths: Vec<PathBuf> = select_stores(&cfg, store)
+        .into_iter()
+        .map(|st| st.path.clone())
+        .collect();
+    let mut error_count = 0;
+    for st in cfg.stores.iter_mut().filter(|st| paths.contains(&st.path)) {
+        // Projects committed there would be mixed in with the rendered output.
+        if st.rendered_branch.as_ref() == Some(&name) {
+            error_count += 1;
+            println!("{st}! {name} is the rendered branch, projects can't be committed to it");
+            continue;
+        }
+        let branches = store::open(st).and_then(|store| store.branches());
+        match branches {
+            Ok(branches) if branches.contains(&name) => {
+                st.branch = Some(name.clone());
+                println!("{st}: switched to {name}");
+            }
+            Ok(_) => {
+                error_count += 1;
+                println!("{st}! no branch named {name}");
+                println!("  To create it, run:");
+                println!("    {} branch create {name:?}", exe());
+            }
+            Err(e) => {
+                error_count += 1;
+                println!("{st}! error: {e}");
+            }
+        }
+    }
+    cfg.store().unwrap();
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_branch_list(args: cli::ListBranchesArgs, cfg: Config) {
+    let cli::ListBranchesArgs { store } = args;
+    let mut error_count = 0;
+    for st in select_stores(&cfg, store) {
+        let res =
+            store::open(st).and_then(|store| Ok((store.current_branch()?, store.branches()?)));
+        let (current, branches) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                error_count += 1;
+                println!("{st}! error: {e}");
+                continue;
+            }
+        };
+        println!("{st}:");
+        if !branches.contains(&current) {
+            // The branch doesn't exist until the first commit to it.
+            println!("* {current} (no commits yet)");
+        }
+        for branch in branches {
+            let marker = if branch == current { '*' } else { ' ' };
+            let rendered = match st.rendered_branch.as_ref() == Some(&branch) {
+                true => " (rendered)",
+                false => "",
+            };
+            println!("{marker} {branch}{rendered}");
+        }
+    }
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_tag(cmd: cli::TagCommand, cfg: Config) {
+    let cli::TagCommand {
+        name,
+        project,
+        program,
+        revision,
+        message,
+        store,
+    } = cmd;
+    let id = project.map(|project| {
+        let Some(program) = program.or_else(|| project::Program::from_file_name(&project)) else {
+            eprintln!("Can't tell which app {project} is from, use --program");
+            exit(1);
+        };
+        ProjectID {
+            program,
+            name: project,
+        }
+    });
+    let note = match &id {
+        None => message,
+        Some(id) => format!("{message}\n\nProject: {id}").trim().to_string(),
+    };
+    if cfg.stores.is_empty() {
+        println!("No stores yet!");
+        println!("Get started by running '{} store create'.", exe());
+        exit(1);
+    }
+
+    let mut error_count = 0;
+    for st in select_stores(&cfg, store) {
+        let res = store::open(st).and_then(|store| {
+            let rev = match (&revision, &id) {
+                (Some(expr), _) => store.resolve(expr)?,
+                (None, None) => Revision::Latest,
+                (None, Some(id)) => match store.project_history(id)?.into_iter().next() {
+                    None => return Err(format!("{id} isn't in the store").into()),
+                    Some(commit) => commit.revision,
+                },
+            };
+            if let Some(id) = &id
+                && store.read_project(id, &rev)?.is_none()
+            {
+                return Err(format!("{id} isn't in the store at that revision").into());
+            }
+            store.create_tag(&name, &rev, &note)
+        });
+        match res {
+            Ok(hash) => println!("{st}: tagged {hash} as {name}"),
+            Err(e) => {
+                error_count += 1;
+                println!("{st}! error: {e}");
+            }
+        }
+    }
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_tags(cmd: cli::TagsCommand, cfg: Config) {
+    let cli::TagsCommand { store } = cmd;
+    if cfg.stores.is_empty() {
+        println!("No stores yet!");
+        println!("Get started by running '{} store create'.", exe());
+        exit(1);
+    }
+    let mut error_count = 0;
+    for st in select_stores(&cfg, store) {
+        let tags = match store::open(st).and_then(|store| store.tags()) {
+            Ok(tags) => tags,
+            Err(e) => {
+                error_count += 1;
+                println!("{st}! error: {e}");
+                continue;
+            }
+        };
+        println!("{st}:");
+        if tags.is_empty() {
+            println!("  no tags yet");
+        }
+        for tag in tags {
+            let date = format_datetime(tag.date);
+            println!("  {} {} ({date})", tag.name, tag.hash);
+            for line in tag.note.lines() {
+                match line.is_empty() {
+                    true => println!(),
+                    false => println!("    {line}"),
+                }
+            }
+        }
+    }
+    if error_count > 0 {
+        exit(1);
+    }
+}
+
+fn cmd_compact(cmd: cli::CompactCommand, cfg: Config) {
+    let cli::CompactCommand {
+        older_than,
+        keep,
+        dry_run,
+        drop_backups,
+        store,
+    } = cmd;
+    let Some(st) = get_single_store(&cfg, store) else {
+        exit(1);
+    };
+    let opts = compact::CompactOptions {
+        older_than,
+        keep,
+        dry_run,
+        drop_backups,
+        rendered_branch: st.rendered_branch.as_deref(),
+    };
+    let res = match store::open(st).and_then(|store| store.compact(&opts)) {
+        Ok(res) => res,
+        Err(e) => {
+            println!("{st}! error: {e}");
+            exit(1);
+        }
+    };
+
+    println!("{st}:");
+    let verb = if dry_run { "Would squash" } else { "Squashed" };
+    for squash in &res.squashed {
+        println!(
+            "  {verb} {} commits from {} to {}",
+            squash.commits,
+            format_datetime(squash.from),
+            format_datetime(squash.to)
+        );
+        for id in &squash.projects {
+            println!("    {id}");
+        }
+    }
+    match res.squashed.iter().map(|s| s.commits).sum::<usize>() {
+        0 => println!("  nothing to compact"),
+        commits => println!(
+            "  {commits} commits {} into {}, {} kept as they were",
+            if dry_run {
+                "would be squashed"
+            } else {
+                "squashed"
+            },
+            res.squashed.len(),
+            res.kept
+        ),
+    }
+    if let Some(backup) = &res.backup {
+        println!("  the original history is kept at {backup}");
+    }
+    for backup in &res.dropped_backups {
+        match dry_run {
+            true => println!("  would delete backup {backup}"),
+            false => println!("  deleted backup {backup}"),
+        }
+    }
+    match res.pruned {
+        Some(pruned) => println!("  deleted {pruned} unused objects"),
+        None if !dry_run => println!("  not a bare repository, so unused objects weren't deleted"),
+        None => {}
+    }
+}
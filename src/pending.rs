@@ -0,0 +1,296 @@
+//! Commits that couldn't be made to some stores (e.g. a store on a drive that isn't plugged in).
+//!
+//! They're kept in a directory next to the config, with a copy of each project as it was, and
+//! retried oldest first on the next commit so that the stores end up with the same history.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, StoreConfig};
+use crate::project::{self, ProjectID, RawProject};
+use crate::store::{self, Store};
+
+const COMMIT_FILE: &str = "commit.toml";
+
+#[derive(Serialize, Deserialize)]
+struct PendingCommit {
+    message: String,
+    /// Seconds since the Unix epoch.
+    date: u64,
+    /// Stores that don't have the commit yet.
+    stores: Vec<PathBuf>,
+    /// Projects as (program, name). Each is saved in the commit's directory as program/name.
+    projects: Vec<(String, String)>,
+}
+
+pub struct Queue {
+    dir: PathBuf,
+    /// Oldest first, with the name of the directory each is saved in.
+    commits: Vec<(String, PendingCommit)>,
+    /// The number to save the next commit under. It's past every directory that was found,
+    /// including incomplete ones, so that a new commit never lands in one of them.
+    next: u64,
+}
+
+/// Loads the queue for the config.
+pub fn load(cfg: &Config) -> Result<Queue, Box<dyn Error>> {
+    load_dir(cfg.pending_dir()?)
+}
+
+fn load_dir(dir: PathBuf) -> Result<Queue, Box<dyn Error>> {
+    let mut commits = Vec::new();
+    let mut next = 0;
+    if dir.exists() {
+        let mut names: Vec<String> = fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.file_name().to_string_lossy().to_string()))
+            .collect::<Result<_, _>>()?;
+        // Anything else (e.g. a .DS_Store) wasn't put there by mind-meld.
+        names.retain(|name| name.len() == 8 && name.bytes().all(|b| b.is_ascii_digit()));
+        // Names are zero-padded sequence numbers, so this sorts them oldest first.
+        names.sort();
+        for name in names {
+            next = next.max(name.parse::<u64>()? + 1);
+            // A commit that was cut off while it was being saved doesn't have one, and is left
+            // out like it was never queued.
+            let path = dir.join(&name).join(COMMIT_FILE);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(path)?;
+            commits.push((name, toml::from_str(&contents)?));
+        }
+    }
+    Ok(Queue { dir, commits, next })
+}
+
+impl Queue {
+    /// The number of commits waiting for each store.
+    pub fn counts(&self) -> Vec<(PathBuf, usize)> {
+        let mut res: Vec<(PathBuf, usize)> = Vec::new();
+        for (_, commit) in &self.commits {
+            for path in &commit.stores {
+                match res.iter_mut().find(|(p, _)| p == path) {
+                    Some((_, n)) => *n += 1,
+                    None => res.push((path.clone(), 1)),
+                }
+            }
+        }
+        res
+    }
+
+    /// Stores that have commits waiting. New commits to them have to wait too, to keep the
+    /// history in order.
+    pub fn blocked(&self) -> HashSet<PathBuf> {
+        self.counts().into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// Returns the hash of the newest version of the project that's waiting for the store, if
+    /// there is one.
+    pub fn queued_hash(&self, store: &Path, id: &ProjectID) -> Option<Vec<u8>> {
+        let program = id.program.to_string();
+        let (name, _) = self.commits.iter().rev().find(|(_, c)| {
+            c.stores.iter().any(|p| p == store)
+                && c.projects
+                    .iter()
+                    .any(|(p, n)| *p == program && *n == id.name)
+        })?;
+        let path = self.dir.join(name).join(&program).join(&id.name);
+        Some(project::read_file(&path).ok()?.hash())
+    }
+
+    /// Points commits waiting for a store at its new path after it's moved.
+    pub fn rename_store(&mut self, from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+        for (name, commit) in &mut self.commits {
+            if let Some(path) = commit.stores.iter_mut().find(|p| p.as_path() == from) {
+                *path = to.to_path_buf();
+                save(&self.dir.join(&*name), commit)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves a commit to be made to the stores later.
+    pub fn add(
+        &mut self,
+        stores: Vec<PathBuf>,
+        projects: &[(ProjectID, RawProject)],
+        message: &str,
+        date: SystemTime,
+    ) -> Result<(), Box<dyn Error>> {
+        let name = format!("{:08}", self.next);
+        let dir = self.dir.join(&name);
+        for (id, raw) in projects {
+            let program_dir = dir.join(id.program.to_string());
+            fs::create_dir_all(&program_dir)?;
+            raw.write(File::create(program_dir.join(&id.name))?)?;
+        }
+        let commit = PendingCommit {
+            message: message.to_string(),
+            date: date.duration_since(UNIX_EPOCH)?.as_secs(),
+            stores,
+            projects: projects
+                .iter()
+                .map(|(id, _)| (id.program.to_string(), id.name.clone()))
+                .collect(),
+        };
+        // Written last, so that a partly saved commit doesn't look complete.
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(COMMIT_FILE), toml::to_string_pretty(&commit)?)?;
+        self.commits.push((name, commit));
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Makes the waiting commits to whichever of the stores are available, oldest first. If a
+    /// commit fails, later ones to the same store keep waiting. Returns the message of each
+    /// commit tried, with the result.
+    pub fn retry(
+        &mut self,
+        stores: &[(StoreConfig, Store)],
+    ) -> Vec<(StoreConfig, String, store::CommitResult)> {
+        let mut res = Vec::new();
+        let mut failed: HashSet<PathBuf> = HashSet::new();
+        for (name, commit) in &mut self.commits {
+            let dir = self.dir.join(&*name);
+            let date = UNIX_EPOCH + Duration::from_secs(commit.date);
+            let mut projects = None;
+            let mut done = Vec::new();
+            for (st, store) in stores {
+                if !commit.stores.contains(&st.path) || failed.contains(&st.path) {
+                    continue;
+                }
+                let projects = projects.get_or_insert_with(|| read_projects(&dir, commit));
+                let st_res = match projects {
+                    Ok(projects) => store.commit_at(projects, &commit.message, date),
+                    Err(e) => Err(e.to_string().into()),
+                };
+                match st_res {
+                    Ok(_) => done.push(st.path.clone()),
+                    Err(_) => {
+                        failed.insert(st.path.clone());
+                    }
+                }
+                res.push((st.clone(), commit.message.clone(), st_res));
+            }
+            if !done.is_empty() {
+                commit.stores.retain(|p| !done.contains(p));
+                let saved = match commit.stores.is_empty() {
+                    true => fs::remove_dir_all(&dir).map_err(Into::into),
+                    false => save(&dir, commit),
+                };
+                if let Err(e) = saved {
+                    eprintln!("{}: {e}", dir.display());
+                }
+            }
+        }
+        self.commits.retain(|(_, c)| !c.stores.is_empty());
+        res
+    }
+}
+
+fn save(dir: &Path, commit: &PendingCommit) -> Result<(), Box<dyn Error>> {
+    fs::write(dir.join(COMMIT_FILE), toml::to_string_pretty(commit)?)?;
+    Ok(())
+}
+
+fn read_projects(
+    dir: &Path,
+    commit: &PendingCommit,
+) -> Result<Vec<(ProjectID, RawProject)>, Box<dyn Error>> {
+    commit
+        .projects
+        .iter()
+        .map(|(program, name)| {
+            let id = ProjectID {
+                program: program.parse()?,
+                name: name.clone(),
+            };
+            let raw = project::read_file(&dir.join(program).join(name))?;
+            Ok((id, raw))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{ArchiveEntry, ArchiveEntryContents, Program, RawArchive};
+
+    #[test]
+    fn test_add_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = RawProject {
+            archive: RawArchive {
+                entries: vec![ArchiveEntry {
+                    name: "manifest.json".to_string(),
+                    contents: ArchiveEntryContents::Data(br#"{"type": "python"}"#.to_vec()),
+                }],
+            },
+        };
+        let id = ProjectID {
+            program: Program::Spike,
+            name: "Robot.llsp3".to_string(),
+        };
+        let date = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let hash = raw.hash();
+
+        let mut queue = load_dir(dir.path().join("pending")).unwrap();
+        let (a, b) = (PathBuf::from("/a"), PathBuf::from("/b"));
+        queue
+            .add(vec![a.clone()], &[(id.clone(), raw)], "first", date)
+            .unwrap();
+        queue
+            .add(vec![a.clone(), b.clone()], &[], "second", date)
+            .unwrap();
+
+        let queue = load_dir(dir.path().join("pending")).unwrap();
+        assert_eq!(queue.counts(), [(a.clone(), 2), (b.clone(), 1)]);
+        let (name, commit) = &queue.commits[0];
+        assert_eq!(commit.message, "first");
+        assert_eq!(commit.date, 1_700_000_000);
+        let projects = read_projects(&queue.dir.join(name), commit).unwrap();
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].0, id);
+        assert_eq!(
+            projects[0].1.archive.get_file_content("manifest.json"),
+            Some(&br#"{"type": "python"}"#[..])
+        );
+        assert_eq!(queue.commits[1].1.message, "second");
+        assert_eq!(queue.queued_hash(&a, &id), Some(hash));
+        assert_eq!(queue.queued_hash(&b, &id), None);
+    }
+
+    #[test]
+    fn test_load_skips_strays() {
+        let dir = tempfile::tempdir().unwrap();
+        let pending = dir.path().join("pending");
+        let date = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut queue = load_dir(pending.clone()).unwrap();
+        queue
+            .add(vec![PathBuf::from("/a")], &[], "first", date)
+            .unwrap();
+        // A commit that was cut off before its commit.toml was written, and a file from Finder.
+        fs::create_dir_all(pending.join("00000001/spike")).unwrap();
+        fs::write(pending.join(".DS_Store"), "").unwrap();
+
+        let mut queue = load_dir(pending.clone()).unwrap();
+        assert_eq!(queue.counts(), [(PathBuf::from("/a"), 1)]);
+        queue
+            .add(vec![PathBuf::from("/b")], &[], "second", date)
+            .unwrap();
+        assert!(pending.join("00000002").join(COMMIT_FILE).is_file());
+
+        let queue = load_dir(pending).unwrap();
+        let messages: Vec<&str> = queue
+            .commits
+            .iter()
+            .map(|(_, c)| c.message.as_str())
+            .collect();
+        assert_eq!(messages, ["first", "second"]);
+    }
+}
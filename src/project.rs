@@ -1,14 +1,19 @@
+pub mod python;
+pub mod scratch;
+#[cfg(test)]
+pub mod test_util;
 pub mod types;
 
 use std::error::Error;
 use std::fmt::Display;
 use std::fs::File;
-use std::io::{Read, Seek};
-use std::path::PathBuf;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 use sha2::{digest::Update, Digest, Sha256};
-use zip::ZipArchive;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
 
 use crate::dirs::Dirs;
 
@@ -20,18 +25,30 @@ pub enum Program {
 
 pub enum Project {
     Python(PythonProject),
-    IconBlocks,
-    WordBlocks,
+    IconBlocks(BlocksProject),
+    WordBlocks(BlocksProject),
 }
 
 impl Project {
     pub fn project_type(&self) -> types::ProjectType {
         match self {
             Project::Python(_) => types::ProjectType::Python,
-            Project::IconBlocks => types::ProjectType::IconBlocks,
-            Project::WordBlocks => types::ProjectType::WordBlocks,
+            Project::IconBlocks(_) => types::ProjectType::IconBlocks,
+            Project::WordBlocks(_) => types::ProjectType::WordBlocks,
         }
     }
+
+    pub fn raw(&self) -> &RawProject {
+        match self {
+            Project::Python(p) => &p.raw,
+            Project::IconBlocks(p) | Project::WordBlocks(p) => &p.raw,
+        }
+    }
+
+    /// Returns the contents of the project's icon.svg.
+    pub fn icon(&self) -> Option<&[u8]> {
+        self.raw().archive.get_file_content("icon.svg")
+    }
 }
 
 pub struct PythonProject {
@@ -50,6 +67,49 @@ impl PythonProject {
     }
 }
 
+pub struct BlocksProject {
+    raw: RawProject,
+}
+
+impl BlocksProject {
+    /// Parses project.json from the embedded scratch.sb3.
+    pub(crate) fn get_scratch(&self) -> Result<scratch::ScratchProject, Box<dyn Error>> {
+        let sb3 = self
+            .raw
+            .archive
+            .get_archive("scratch.sb3")
+            .ok_or("no scratch.sb3!")?;
+        match sb3.get_file_content("project.json") {
+            None => Err("no project.json in scratch.sb3!".into()),
+            Some(d) => Ok(serde_json::from_slice(d)?),
+        }
+    }
+
+    /// Writes the embedded scratch.sb3 as a standalone file. If project_json is given, it replaces
+    /// the original project.json.
+    pub(crate) fn write_sb3<W: Write + Seek>(
+        self,
+        project_json: Option<Vec<u8>>,
+        w: W,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut sb3 = self
+            .raw
+            .archive
+            .entries
+            .into_iter()
+            .find_map(|e| match e.contents {
+                ArchiveEntryContents::Archive(a) if e.name == "scratch.sb3" => Some(a),
+                _ => None,
+            })
+            .ok_or("no scratch.sb3!")?;
+        if let Some(json) = project_json {
+            sb3.set_file_content("project.json", json);
+        }
+        sb3.write(w)?;
+        Ok(())
+    }
+}
+
 #[derive(Deserialize)]
 struct ProjectBody {
     main: String,
@@ -65,18 +125,31 @@ pub fn all_programs(dirs: &Dirs) -> Vec<(Program, &PathBuf)> {
 pub fn read(id: &ProjectID, dirs: &Dirs) -> Result<Option<RawProject>, Box<dyn Error>> {
     let base_path = dir(id.program, dirs);
     let path = base_path.join(&id.name);
-    match File::open(path) {
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-        Err(e) => Err(e.into()),
-        Ok(f) => {
-            let archive = ZipArchive::new(f)?;
-            Ok(Some(RawProject {
-                archive: RawArchive::read(archive)?,
-            }))
-        }
+    match read_file(&path) {
+        Err(e) => match e.downcast_ref::<std::io::Error>() {
+            Some(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            _ => Err(e),
+        },
+        Ok(raw) => Ok(Some(raw)),
     }
 }
 
+/// Reads a lms or llsp3 file from anywhere.
+pub fn read_file(path: &Path) -> Result<RawProject, Box<dyn Error>> {
+    let archive = ZipArchive::new(File::open(path)?)?;
+    Ok(RawProject {
+        archive: RawArchive::read(archive)?,
+    })
+}
+
+/// Reads a lms or llsp3 file's contents, e.g. as kept by a store.
+pub(crate) fn read_bytes(data: Vec<u8>) -> Result<RawProject, Box<dyn Error>> {
+    let archive = ZipArchive::new(std::io::Cursor::new(data))?;
+    Ok(RawProject {
+        archive: RawArchive::read(archive)?,
+    })
+}
+
 fn dir(prog: Program, dirs: &Dirs) -> &PathBuf {
     match prog {
         Program::Mindstorms => &dirs.mindstorms,
@@ -109,6 +182,39 @@ impl ProjectID {
     }
 }
 
+impl Program {
+    /// Guesses the app from a file's extension: .llsp3 (and the older .llsp) for SPIKE, .lms for
+    /// Mindstorms.
+    pub fn from_file_name(name: &str) -> Option<Program> {
+        let (_, ext) = name.rsplit_once('.')?;
+        match ext.to_lowercase().as_str() {
+            "llsp3" | "llsp" => Some(Program::Spike),
+            "lms" => Some(Program::Mindstorms),
+            _ => None,
+        }
+    }
+
+    /// The file extension that the app uses for projects.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Program::Spike => "llsp3",
+            Program::Mindstorms => "lms",
+        }
+    }
+}
+
+impl std::str::FromStr for Program {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            PROGRAM_MINDSTORMS => Ok(Program::Mindstorms),
+            PROGRAM_SPIKE => Ok(Program::Spike),
+            _ => Err(format!("invalid program {s:?}")),
+        }
+    }
+}
+
 impl Display for Program {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -137,14 +243,24 @@ impl RawProject {
         hasher.finalize().to_vec()
     }
 
+    /// Writes the project as a lms or llsp3 file.
+    pub fn write<W: Write + Seek>(&self, w: W) -> Result<(), Box<dyn Error>> {
+        self.archive.write(w)?;
+        Ok(())
+    }
+
     pub fn into_project(self) -> Result<Project, Box<dyn Error>> {
         match self.archive.get_file_content("manifest.json") {
             None => Err("no manifest".into()),
             Some(d) => {
                 let manifest: types::Manifest = serde_json::from_slice(d)?;
                 Ok(match manifest.project_type {
-                    types::ProjectType::WordBlocks => Project::WordBlocks,
-                    types::ProjectType::IconBlocks => Project::IconBlocks,
+                    types::ProjectType::WordBlocks => {
+                        Project::WordBlocks(BlocksProject { raw: self })
+                    }
+                    types::ProjectType::IconBlocks => {
+                        Project::IconBlocks(BlocksProject { raw: self })
+                    }
                     types::ProjectType::Python => Project::Python(PythonProject { raw: self }),
                 })
             }
@@ -168,6 +284,21 @@ impl RawArchive {
         Ok(Self { entries })
     }
 
+    fn write<W: Write + Seek>(&self, w: W) -> Result<W, Box<dyn Error>> {
+        let mut zip = ZipWriter::new(w);
+        for e in &self.entries {
+            zip.start_file(&e.name, SimpleFileOptions::default())?;
+            match &e.contents {
+                ArchiveEntryContents::Data(data) => zip.write_all(data)?,
+                ArchiveEntryContents::Archive(arch) => {
+                    let inner = arch.write(std::io::Cursor::new(Vec::new()))?;
+                    zip.write_all(&inner.into_inner())?
+                }
+            };
+        }
+        Ok(zip.finish()?)
+    }
+
     fn hash<H: Update>(&self, hasher: &mut H) {
         hasher.update(format!("entries={}\n", self.entries.len()).as_bytes());
         let mut entries: Vec<&ArchiveEntry> = self.entries.iter().collect();
@@ -178,7 +309,7 @@ impl RawArchive {
         }
     }
 
-    fn get_file_content(&self, name: &str) -> Option<&[u8]> {
+    pub(crate) fn get_file_content(&self, name: &str) -> Option<&[u8]> {
         for e in &self.entries {
             if e.name == name {
                 if let ArchiveEntryContents::Data(d) = &e.contents {
@@ -188,6 +319,40 @@ impl RawArchive {
         }
         None
     }
+
+    pub(crate) fn get_archive(&self, name: &str) -> Option<&RawArchive> {
+        for e in &self.entries {
+            if e.name == name
+                && let ArchiveEntryContents::Archive(a) = &e.contents
+            {
+                return Some(a);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn get_archive_mut(&mut self, name: &str) -> Option<&mut RawArchive> {
+        for e in &mut self.entries {
+            if e.name == name
+                && let ArchiveEntryContents::Archive(a) = &mut e.contents
+            {
+                return Some(a);
+            }
+        }
+        None
+    }
+
+    /// Replaces the contents of a file, or adds it if it isn't there.
+    pub(crate) fn set_file_content(&mut self, name: &str, data: Vec<u8>) {
+        let contents = ArchiveEntryContents::Data(data);
+        match self.entries.iter_mut().find(|e| e.name == name) {
+            Some(e) => e.contents = contents,
+            None => self.entries.push(ArchiveEntry {
+                name: name.to_string(),
+                contents,
+            }),
+        }
+    }
 }
 
 pub struct ArchiveEntry {
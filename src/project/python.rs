@@ -0,0 +1,327 @@
+//! A small Python tokenizer. It's lossless: concatenating the text of every token gives back the
+//! original source, which makes it suitable for syntax highlighting as well as simple checks.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    /// A builtin function or constant, like `print` or `True`.
+    Builtin,
+    Name,
+    Number,
+    String,
+    /// A string that's missing its closing quote.
+    UnterminatedString,
+    Comment,
+    Operator,
+    /// One of `( ) [ ] { }`.
+    Bracket,
+    Newline,
+    Whitespace,
+    /// A character that can't start any token.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// 1-based line number of the start of the token.
+    pub line: usize,
+    /// 0-based column (in characters) of the start of the token.
+    pub col: usize,
+}
+
+pub const KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+/// Names that are available without an import (in MicroPython, at least).
+pub const BUILTINS: &[&str] = &[
+    "abs",
+    "all",
+    "any",
+    "bin",
+    "bool",
+    "bytearray",
+    "bytes",
+    "callable",
+    "chr",
+    "classmethod",
+    "dict",
+    "dir",
+    "divmod",
+    "enumerate",
+    "eval",
+    "exec",
+    "filter",
+    "float",
+    "getattr",
+    "globals",
+    "hasattr",
+    "hash",
+    "hex",
+    "id",
+    "input",
+    "int",
+    "isinstance",
+    "issubclass",
+    "iter",
+    "len",
+    "list",
+    "locals",
+    "map",
+    "max",
+    "min",
+    "next",
+    "object",
+    "oct",
+    "open",
+    "ord",
+    "pow",
+    "print",
+    "property",
+    "range",
+    "repr",
+    "reversed",
+    "round",
+    "set",
+    "setattr",
+    "slice",
+    "sorted",
+    "staticmethod",
+    "str",
+    "sum",
+    "super",
+    "tuple",
+    "type",
+    "zip",
+    "__name__",
+    "__file__",
+    "Exception",
+    "BaseException",
+    "ArithmeticError",
+    "AssertionError",
+    "AttributeError",
+    "EOFError",
+    "ImportError",
+    "IndexError",
+    "KeyboardInterrupt",
+    "KeyError",
+    "MemoryError",
+    "NameError",
+    "NotImplementedError",
+    "OSError",
+    "OverflowError",
+    "RuntimeError",
+    "StopIteration",
+    "SyntaxError",
+    "SystemExit",
+    "TypeError",
+    "ValueError",
+    "ZeroDivisionError",
+    "NotImplemented",
+    "Ellipsis",
+];
+
+const OPERATORS: &[&str] = &[
+    "**=", "//=", ">>=", "<<=", "...", "->", ":=", "**", "//", "<<", ">>", "<=", ">=", "==", "!=",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "@=", "+", "-", "*", "/", "%", "@", "&", "|",
+    "^", "~", "<", ">", "=", ".", ",", ":", ";", "\\",
+];
+
+pub fn tokenize(src: &str) -> Vec<Token<'_>> {
+    let mut res = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    let mut col = 0;
+    while pos < src.len() {
+        let rest = &src[pos..];
+        let (kind, len) = next_token(rest);
+        let text = &rest[..len];
+        res.push(Token {
+            kind,
+            text,
+            line,
+            col,
+        });
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    line += 1;
+                    col = 0;
+                }
+                _ => col += 1,
+            };
+        }
+        pos += len;
+    }
+    res
+}
+
+/// Returns the kind and length (in bytes) of the token at the start of s, which isn't empty.
+fn next_token(s: &str) -> (TokenKind, usize) {
+    let c = s.chars().next().unwrap();
+    match c {
+        '\n' => (TokenKind::Newline, 1),
+        '\r' if s.starts_with("\r\n") => (TokenKind::Newline, 2),
+        '#' => (TokenKind::Comment, s.find('\n').unwrap_or(s.len())),
+        c if c.is_whitespace() => (
+            TokenKind::Whitespace,
+            take_while(s, |c| c != '\n' && c.is_whitespace()),
+        ),
+        '(' | ')' | '[' | ']' | '{' | '}' => (TokenKind::Bracket, 1),
+        '"' | '\'' => string_token(s, 0),
+        c if c.is_ascii_digit() => (TokenKind::Number, number_len(s)),
+        '.' if s[1..].starts_with(|c: char| c.is_ascii_digit()) => {
+            (TokenKind::Number, number_len(s))
+        }
+        c if c == '_' || c.is_alphabetic() => {
+            let len = take_while(s, |c| c == '_' || c.is_alphanumeric());
+            let word = &s[..len];
+            // String prefixes like r"..." or f'...'.
+            if len <= 2
+                && word.chars().all(|c| "rRbBfFuU".contains(c))
+                && s[len..].starts_with(['"', '\''])
+            {
+                return string_token(s, len);
+            }
+            let kind = if KEYWORDS.contains(&word) {
+                TokenKind::Keyword
+            } else if BUILTINS.contains(&word) {
+                TokenKind::Builtin
+            } else {
+                TokenKind::Name
+            };
+            (kind, len)
+        }
+        _ => match OPERATORS.iter().find(|op| s.starts_with(*op)) {
+            Some(op) => (TokenKind::Operator, op.len()),
+            None => (TokenKind::Unknown, c.len_utf8()),
+        },
+    }
+}
+
+fn take_while(s: &str, f: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !f(c)).unwrap_or(s.len())
+}
+
+fn number_len(s: &str) -> usize {
+    let mut len = 0;
+    let mut prev = ' ';
+    for c in s.chars() {
+        let ok = c.is_ascii_alphanumeric()
+            || c == '_'
+            || c == '.'
+            || ((c == '+' || c == '-') && (prev == 'e' || prev == 'E'));
+        if !ok {
+            break;
+        }
+        len += c.len_utf8();
+        prev = c;
+    }
+    len
+}
+
+/// Scans a string literal whose opening quote is at s[prefix..].
+fn string_token(s: &str, prefix: usize) -> (TokenKind, usize) {
+    let body = &s[prefix..];
+    let quote = &body[..1];
+    let triple = body.starts_with(&quote.repeat(3));
+    let delim = if triple {
+        quote.repeat(3)
+    } else {
+        quote.to_string()
+    };
+    let mut i = delim.len();
+    let bytes = body.as_bytes();
+    while i < body.len() {
+        if bytes[i] == b'\\' {
+            i += 1;
+            i += body[i..].chars().next().map_or(0, char::len_utf8);
+            continue;
+        }
+        if bytes[i] == b'\n' && !triple {
+            return (TokenKind::UnterminatedString, prefix + i);
+        }
+        if body[i..].starts_with(&delim) {
+            return (TokenKind::String, prefix + i + delim.len());
+        }
+        i += body[i..].chars().next().map_or(1, char::len_utf8);
+    }
+    (TokenKind::UnterminatedString, s.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src)
+            .into_iter()
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_lossless() {
+        let src = "from hub import port\n\nasync def main():\n    x = f'{1}' # hi\n    await runloop.sleep_ms(1_000)\n";
+        let joined: String = tokenize(src).iter().map(|t| t.text).collect();
+        assert_eq!(joined, src);
+    }
+
+    #[test]
+    fn test_kinds() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("if x >= 1.5e-3: print('a\\'b') # done"),
+            vec![
+                (Keyword, "if"),
+                (Name, "x"),
+                (Operator, ">="),
+                (Number, "1.5e-3"),
+                (Operator, ":"),
+                (Builtin, "print"),
+                (Bracket, "("),
+                (String, "'a\\'b'"),
+                (Bracket, ")"),
+                (Comment, "# done"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("s = r\"\\d\" + '''a\nb'''"),
+            vec![
+                (Name, "s"),
+                (Operator, "="),
+                (String, "r\"\\d\""),
+                (Operator, "+"),
+                (String, "'''a\nb'''"),
+            ]
+        );
+        assert_eq!(
+            kinds("s = 'oops\nx"),
+            vec![
+                (Name, "s"),
+                (Operator, "="),
+                (UnterminatedString, "'oops"),
+                (Newline, "\n"),
+                (Name, "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_positions() {
+        let tokens = tokenize("a\n  b");
+        let b = tokens.iter().find(|t| t.text == "b").unwrap();
+        assert_eq!((b.line, b.col), (2, 2));
+    }
+}
@@ -0,0 +1,611 @@
+//! A model of the `project.json` file inside `scratch.sb3`.
+//!
+//! See the [Scratch file format](https://en.scratch-wiki.info/wiki/Scratch_File_Format) docs for
+//! details. Fields that mind-meld doesn't use are kept in `extra` so that a project can be
+//! modified and written back out without losing anything.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ScratchProject {
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    pub name: String,
+    #[serde(default)]
+    pub is_stage: bool,
+    /// Variable id => [name, value].
+    #[serde(default)]
+    pub variables: BTreeMap<String, Value>,
+    /// List id => [name, [values]].
+    #[serde(default)]
+    pub lists: BTreeMap<String, Value>,
+    /// Broadcast id => name.
+    #[serde(default)]
+    pub broadcasts: BTreeMap<String, String>,
+    #[serde(default)]
+    pub blocks: BTreeMap<String, BlockEntry>,
+    #[serde(default)]
+    pub comments: BTreeMap<String, Comment>,
+    #[serde(default)]
+    pub costumes: Vec<Asset>,
+    #[serde(default)]
+    pub sounds: Vec<Asset>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Most entries in a target's blocks are blocks, but top-level variable and list reporters are
+/// stored as bare arrays.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum BlockEntry {
+    Block(Block),
+    Primitive(Vec<Value>),
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Block {
+    pub opcode: String,
+    #[serde(default)]
+    pub next: Option<String>,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub inputs: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub fields: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub shadow: bool,
+    #[serde(default)]
+    pub top_level: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation: Option<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Comment {
+    #[serde(default)]
+    pub block_id: Option<String>,
+    #[serde(default)]
+    pub text: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Asset {
+    pub name: String,
+    #[serde(default)]
+    pub asset_id: String,
+    #[serde(default)]
+    pub md5ext: Option<String>,
+    #[serde(default)]
+    pub data_format: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The value of one of a block's inputs.
+#[derive(Debug, PartialEq)]
+pub enum InputValue<'a> {
+    /// Another block (a reporter, a menu, or the first block of a substack).
+    Block(&'a str),
+    /// A number, text, or color typed into the input.
+    Literal(String),
+    Broadcast {
+        name: String,
+        id: String,
+    },
+    Variable {
+        name: String,
+        id: String,
+    },
+    List {
+        name: String,
+        id: String,
+    },
+}
+
+impl Block {
+    /// Returns the value of the named input. When a reporter has been dropped onto a shadow,
+    /// the reporter is returned.
+    pub fn input(&self, name: &str) -> Option<InputValue<'_>> {
+        let input = self.inputs.get(name)?.as_array()?;
+        input_value(input.get(1)?)
+    }
+
+    /// Returns all inputs in name order.
+    pub fn input_values(&self) -> Vec<(&str, InputValue<'_>)> {
+        self.inputs
+            .keys()
+            .filter_map(|name| Some((name.as_str(), self.input(name)?)))
+            .collect()
+    }
+
+    /// Returns the value of the named field (e.g. the variable name for "set variable").
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)?.as_array()?.first()?.as_str()
+    }
+
+    /// Returns the id stored with the named field's value, for fields that refer to variables,
+    /// lists, and broadcasts.
+    pub fn field_id(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)?.as_array()?.get(1)?.as_str()
+    }
+
+    /// Returns whether this is a hat block, which starts a script when something happens.
+    pub fn is_hat(&self) -> bool {
+        opcode_name(&self.opcode).starts_with("when")
+            || matches!(
+                self.opcode.as_str(),
+                "procedures_definition" | "control_start_as_clone"
+            )
+    }
+
+    /// The category that the block belongs to, based on its opcode.
+    pub fn category(&self) -> Category {
+        Category::of(&self.opcode)
+    }
+}
+
+fn input_value(v: &Value) -> Option<InputValue<'_>> {
+    match v.as_str() {
+        Some(id) => Some(InputValue::Block(id)),
+        None => primitive_value(v.as_array()?),
+    }
+}
+
+/// Decodes a primitive like `[12, "speed", "v1"]`.
+fn primitive_value(prim: &[Value]) -> Option<InputValue<'static>> {
+    let kind = prim.first()?.as_u64()?;
+    let text = |i: usize| -> Option<String> {
+        Some(match prim.get(i)? {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    };
+    Some(match kind {
+        11 => InputValue::Broadcast {
+            name: text(1)?,
+            id: text(2)?,
+        },
+        12 => InputValue::Variable {
+            name: text(1)?,
+            id: text(2)?,
+        },
+        13 => InputValue::List {
+            name: text(1)?,
+            id: text(2)?,
+        },
+        _ => InputValue::Literal(text(1)?),
+    })
+}
+
+impl BlockEntry {
+    /// Returns the variable or list that a top-level reporter refers to.
+    pub fn primitive(&self) -> Option<InputValue<'static>> {
+        match self {
+            BlockEntry::Block(_) => None,
+            BlockEntry::Primitive(p) => primitive_value(p),
+        }
+    }
+}
+
+impl Target {
+    pub fn block(&self, id: &str) -> Option<&Block> {
+        match self.blocks.get(id)? {
+            BlockEntry::Block(b) => Some(b),
+            BlockEntry::Primitive(_) => None,
+        }
+    }
+
+    /// Returns every block in the target along with its id, skipping primitives.
+    pub fn each_block(&self) -> impl Iterator<Item = (&str, &Block)> {
+        self.blocks.iter().filter_map(|(id, e)| match e {
+            BlockEntry::Block(b) => Some((id.as_str(), b)),
+            BlockEntry::Primitive(_) => None,
+        })
+    }
+
+    /// Returns the ids of the blocks that start each top-level stack, ordered by position in the
+    /// editor (top to bottom, then left to right).
+    pub fn top_level_ids(&self) -> Vec<&str> {
+        let mut res: Vec<(i64, i64, &str)> = self
+            .blocks
+            .iter()
+            .filter_map(|(id, e)| match e {
+                BlockEntry::Block(b) if b.top_level => {
+                    let pos = |k: &str| b.extra.get(k).and_then(Value::as_f64).unwrap_or(0.0);
+                    Some((pos("y") as i64, pos("x") as i64, id.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        res.sort();
+        res.into_iter().map(|(_, _, id)| id).collect()
+    }
+
+    /// Returns the ids of the blocks in a stack, starting with id and following `next`.
+    pub fn stack_ids<'a>(&'a self, id: &'a str) -> Vec<&'a str> {
+        let mut res = Vec::new();
+        let mut cur = Some(id);
+        while let Some(id) = cur {
+            // Guard against cycles in a corrupt project.
+            if res.contains(&id) {
+                break;
+            }
+            res.push(id);
+            cur = self.block(id).and_then(|b| b.next.as_deref());
+        }
+        res
+    }
+
+    /// Describes every top-level stack in the target.
+    pub fn scripts(&self) -> Vec<Vec<BlockView<'_>>> {
+        self.top_level_ids()
+            .into_iter()
+            .map(|id| self.view_stack(id, 0))
+            .collect()
+    }
+
+    /// Formats one block on a single line, the way it appears in `scripts`.
+    pub fn describe(&self, id: &str) -> Option<String> {
+        Some(self.view_block(id, 0)?.to_string())
+    }
+
+    fn view_stack<'a>(&'a self, id: &'a str, depth: usize) -> Vec<BlockView<'a>> {
+        self.stack_ids(id)
+            .into_iter()
+            .filter_map(|id| self.view_block(id, depth))
+            .collect()
+    }
+
+    fn view_block<'a>(&'a self, id: &'a str, depth: usize) -> Option<BlockView<'a>> {
+        // Nesting this deep means the project is corrupt.
+        if depth > 64 {
+            return None;
+        }
+        let block = self.block(id)?;
+        let mut args = Vec::new();
+        let mut substacks = Vec::new();
+        for (name, value) in block.input_values() {
+            match value {
+                InputValue::Block(child) if name.starts_with("SUBSTACK") => {
+                    substacks.push(self.view_stack(child, depth + 1));
+                }
+                InputValue::Block(child) => match self.block(child) {
+                    // Menus are shadow blocks with a single field, show the selected value.
+                    Some(c) if c.shadow && c.inputs.is_empty() && c.fields.len() == 1 => {
+                        let value = c.fields.keys().next().and_then(|f| c.field(f));
+                        args.push((name, Arg::Literal(value.unwrap_or_default().to_string())));
+                    }
+                    Some(_) => {
+                        if let Some(view) = self.view_block(child, depth + 1) {
+                            args.push((name, Arg::Block(Box::new(view))));
+                        }
+                    }
+                    None => match self.blocks.get(child).and_then(BlockEntry::primitive) {
+                        Some(InputValue::Variable { name: var, .. }) => {
+                            args.push((name, Arg::Literal(var)))
+                        }
+                        Some(_) => {}
+                        None => args.push((name, Arg::Literal(String::new()))),
+                    },
+                },
+                InputValue::Literal(s) => args.push((name, Arg::Literal(s))),
+                InputValue::Broadcast { name: s, .. }
+                | InputValue::Variable { name: s, .. }
+                | InputValue::List { name: s, .. } => args.push((name, Arg::Literal(s))),
+            };
+        }
+        for name in block.fields.keys() {
+            if let Some(value) = block.field(name) {
+                args.push((name, Arg::Literal(value.to_string())));
+            }
+        }
+        Some(BlockView {
+            block,
+            label: label(&block.opcode),
+            args,
+            substacks,
+        })
+    }
+}
+
+/// A block along with its arguments and nested stacks, for display.
+pub struct BlockView<'a> {
+    pub block: &'a Block,
+    pub label: String,
+    pub args: Vec<(&'a str, Arg<'a>)>,
+    pub substacks: Vec<Vec<BlockView<'a>>>,
+}
+
+pub enum Arg<'a> {
+    Literal(String),
+    Block(Box<BlockView<'a>>),
+}
+
+impl Display for BlockView<'_> {
+    /// Formats the block on a single line, without its substacks.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)?;
+        for (_, arg) in &self.args {
+            match arg {
+                Arg::Literal(s) => write!(f, " [{s}]")?,
+                Arg::Block(b) => write!(f, " ({b})")?,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Returns the part of an opcode after the extension prefix, e.g. "motorTurnForDirection" for
+/// "flippermotor_motorTurnForDirection".
+pub fn opcode_name(opcode: &str) -> &str {
+    match opcode.split_once('_') {
+        Some((_, name)) => name,
+        None => opcode,
+    }
+}
+
+/// Returns the extension prefix of an opcode, e.g. "flippermotor".
+pub fn opcode_prefix(opcode: &str) -> &str {
+    match opcode.split_once('_') {
+        Some((prefix, _)) => prefix,
+        None => "",
+    }
+}
+
+/// Opcode prefixes that Scratch itself understands.
+const SCRATCH_PREFIXES: &[&str] = &[
+    "",
+    "motion",
+    "looks",
+    "sound",
+    "event",
+    "control",
+    "sensing",
+    "operator",
+    "data",
+    "procedures",
+    "argument",
+    "math",
+    "text",
+    "colour",
+    "music",
+    "pen",
+];
+
+/// Returns whether the opcode is from one of the LEGO extensions rather than Scratch itself.
+pub fn is_lego(opcode: &str) -> bool {
+    !SCRATCH_PREFIXES.contains(&opcode_prefix(opcode))
+}
+
+/// Turns an opcode into something readable, e.g. "motor turn for direction".
+pub fn label(opcode: &str) -> String {
+    let mut res = String::new();
+    for c in opcode_name(opcode).chars() {
+        if c == '_' || c == '-' {
+            res.push(' ');
+        } else if c.is_uppercase() {
+            if !res.is_empty() && !res.ends_with(' ') {
+                res.push(' ');
+            }
+            res.extend(c.to_lowercase());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Block categories, as shown in the editor's palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    Motion,
+    Looks,
+    Sound,
+    Events,
+    Control,
+    Sensing,
+    Operators,
+    Variables,
+    Lists,
+    MyBlocks,
+    Extension,
+}
+
+impl Category {
+    pub const ALL: [Category; 11] = [
+        Category::Motion,
+        Category::Looks,
+        Category::Sound,
+        Category::Events,
+        Category::Control,
+        Category::Sensing,
+        Category::Operators,
+        Category::Variables,
+        Category::Lists,
+        Category::MyBlocks,
+        Category::Extension,
+    ];
+
+    /// Maps an opcode to the closest Scratch category. The LEGO apps prefix their opcodes with
+    /// the extension name (e.g. "flippermotor_"), so motors and movement are grouped with
+    /// motion, lights with looks, and so on.
+    pub fn of(opcode: &str) -> Category {
+        let prefix = opcode_prefix(opcode);
+        if prefix == "data" {
+            return match opcode_name(opcode).contains("list")
+                || opcode_name(opcode).contains("item")
+            {
+                true => Category::Lists,
+                false => Category::Variables,
+            };
+        }
+        let prefix = prefix
+            .trim_start_matches("flipper")
+            .trim_start_matches("horizontal")
+            .trim_start_matches("linegraph")
+            .trim_start_matches("more");
+        match prefix {
+            "motion" | "motor" | "move" => Category::Motion,
+            "looks" | "light" | "display" => Category::Looks,
+            "sound" | "music" => Category::Sound,
+            "event" | "events" => Category::Events,
+            "control" => Category::Control,
+            "sensing" | "sensors" | "sensor" => Category::Sensing,
+            "operator" | "operators" => Category::Operators,
+            "procedures" | "argument" => Category::MyBlocks,
+            _ => Category::Extension,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Motion => "motion",
+            Category::Looks => "looks",
+            Category::Sound => "sound",
+            Category::Events => "events",
+            Category::Control => "control",
+            Category::Sensing => "sensing",
+            Category::Operators => "operators",
+            Category::Variables => "variables",
+            Category::Lists => "lists",
+            Category::MyBlocks => "my-blocks",
+            Category::Extension => "extension",
+        }
+    }
+
+    /// The block color used by the Scratch editor.
+    pub fn color(&self) -> &'static str {
+        match self {
+            Category::Motion => "#4C97FF",
+            Category::Looks => "#9966FF",
+            Category::Sound => "#CF63CF",
+            Category::Events => "#FFBF00",
+            Category::Control => "#FFAB19",
+            Category::Sensing => "#5CB1D6",
+            Category::Operators => "#59C059",
+            Category::Variables => "#FF8C1A",
+            Category::Lists => "#FF661A",
+            Category::MyBlocks => "#FF6680",
+            Category::Extension => "#0FBD8C",
+        }
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = r#"{
+        "targets": [
+            {"isStage": true, "name": "Stage", "variables": {"v1": ["speed", 0]}, "blocks": {}},
+            {"isStage": false, "name": "Sprite", "layerOrder": 1, "blocks": {
+                "a": {"opcode": "flipperevents_whenProgramStarts", "next": "b", "parent": null,
+                      "inputs": {}, "fields": {}, "shadow": false, "topLevel": true, "x": 5, "y": 5},
+                "b": {"opcode": "control_forever", "next": null, "parent": "a",
+                      "inputs": {"SUBSTACK": [2, "c"]}, "fields": {}, "shadow": false, "topLevel": false},
+                "c": {"opcode": "flippermotor_motorStartDirection", "next": null, "parent": "b",
+                      "inputs": {"PORT": [1, "p"], "SPEED": [3, "r", [4, "50"]]}, "fields": {},
+                      "shadow": false, "topLevel": false},
+                "p": {"opcode": "flippermotor_multiple-port-selector", "next": null, "parent": "c",
+                      "inputs": {}, "fields": {"field_flippermotor_multiple-port-selector": ["A", null]},
+                      "shadow": true, "topLevel": false},
+                "r": {"opcode": "operator_add", "next": null, "parent": "c",
+                      "inputs": {"NUM1": [1, [4, "1"]], "NUM2": [3, [12, "speed", "v1"], [4, ""]]},
+                      "fields": {}, "shadow": false, "topLevel": false},
+                "v": [12, "speed", "v1", 100, 200]
+            }}
+        ],
+        "meta": {"semver": "3.0.0"}
+    }"#;
+
+    #[test]
+    fn test_parse_and_round_trip() {
+        let proj: ScratchProject = serde_json::from_str(PROJECT).unwrap();
+        assert_eq!(proj.targets.len(), 2);
+        assert!(proj.targets[0].is_stage);
+        assert!(matches!(
+            proj.targets[1].blocks.get("v"),
+            Some(BlockEntry::Primitive(_))
+        ));
+
+        let out = serde_json::to_value(&proj).unwrap();
+        assert_eq!(out["meta"]["semver"], "3.0.0");
+        assert_eq!(out["targets"][1]["layerOrder"], 1);
+        assert_eq!(out["targets"][1]["blocks"]["a"]["x"], 5);
+    }
+
+    #[test]
+    fn test_inputs() {
+        let proj: ScratchProject = serde_json::from_str(PROJECT).unwrap();
+        let r = proj.targets[1].block("r").unwrap();
+        assert_eq!(r.input("NUM1"), Some(InputValue::Literal("1".to_string())));
+        assert_eq!(
+            r.input("NUM2"),
+            Some(InputValue::Variable {
+                name: "speed".to_string(),
+                id: "v1".to_string()
+            })
+        );
+        let c = proj.targets[1].block("c").unwrap();
+        assert_eq!(c.input("SPEED"), Some(InputValue::Block("r")));
+    }
+
+    #[test]
+    fn test_scripts() {
+        let proj: ScratchProject = serde_json::from_str(PROJECT).unwrap();
+        let scripts = proj.targets[1].scripts();
+        assert_eq!(scripts.len(), 1);
+        let script = &scripts[0];
+        assert_eq!(script.len(), 2);
+        assert_eq!(script[0].to_string(), "when program starts");
+        assert_eq!(script[1].label, "forever");
+        assert_eq!(
+            script[1].substacks[0][0].to_string(),
+            "motor start direction [A] (add [1] [speed])"
+        );
+    }
+
+    #[test]
+    fn test_categories() {
+        assert_eq!(
+            Category::of("flippermotor_motorTurnForDirection"),
+            Category::Motion
+        );
+        assert_eq!(
+            Category::of("flipperlight_lightDisplayText"),
+            Category::Looks
+        );
+        assert_eq!(Category::of("event_broadcast"), Category::Events);
+        assert_eq!(Category::of("data_setvariableto"), Category::Variables);
+        assert_eq!(Category::of("data_addtolist"), Category::Lists);
+        assert_eq!(Category::of("procedures_call"), Category::MyBlocks);
+        assert_eq!(Category::of("weather_forecast"), Category::Extension);
+    }
+}
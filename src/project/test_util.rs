@@ -0,0 +1,30 @@
+//! Projects for tests.
+
+use super::{ArchiveEntry, ArchiveEntryContents, Program, ProjectID, RawArchive, RawProject};
+
+/// A Python project whose main program is main.
+pub fn python(main: &str) -> RawProject {
+    let data = |s: String| ArchiveEntryContents::Data(s.into_bytes());
+    RawProject {
+        archive: RawArchive {
+            entries: vec![
+                ArchiveEntry {
+                    name: "manifest.json".to_string(),
+                    contents: data(r#"{"type": "python"}"#.to_string()),
+                },
+                ArchiveEntry {
+                    name: "projectbody.json".to_string(),
+                    contents: data(format!(r#"{{"main": "{main}"}}"#)),
+                },
+            ],
+        },
+    }
+}
+
+/// A SPIKE project's id.
+pub fn id(name: &str) -> ProjectID {
+    ProjectID {
+        program: Program::Spike,
+        name: name.to_string(),
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -25,3 +27,13 @@ impl ProjectType {
         }
     }
 }
+
+impl Display for ProjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectType::WordBlocks => write!(f, "word blocks"),
+            ProjectType::IconBlocks => write!(f, "icon blocks"),
+            ProjectType::Python => write!(f, "python"),
+        }
+    }
+}
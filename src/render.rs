@@ -1,4 +1,7 @@
+pub mod branch;
+pub mod file;
 pub mod fs;
+pub mod html;
 pub mod store;
 pub mod txt;
 
@@ -6,7 +9,7 @@ use std::error::Error;
 
 use crate::project::types::ProjectType;
 use crate::project::{Project, ProjectID, RawProject};
-use crate::store::{Revision, Store};
+use crate::store::{ProjectChanges, Revision, Store};
 
 pub(crate) fn render_all_projects(
     mut dest: impl RenderDest,
@@ -14,17 +17,17 @@ pub(crate) fn render_all_projects(
     store: &Store,
     revision: Revision,
 ) -> Result<(), Box<dyn Error>> {
-    dest.pre_flight(&revision)?;
-    for proj_id in store.project_ids()? {
-        match store.read_project(&proj_id, &revision)? {
-            None => println!("{proj_id}! missing, oddly."),
-            Some(p) => match render_project(&mut dest, &fmt, &proj_id, p) {
-                Ok(Some(msg)) => println!("{proj_id}: {msg}"),
-                Ok(None) => println!("{proj_id}: rendered"),
-                Err(e) => println!("{proj_id}! {e}"),
-            },
-        };
-    }
+    render_revision(
+        &mut dest,
+        &fmt,
+        store,
+        &revision,
+        |proj_id, res| match res {
+            Ok(Some(msg)) => println!("{proj_id}: {msg}"),
+            Ok(None) => println!("{proj_id}: rendered"),
+            Err(e) => println!("{proj_id}! {e}"),
+        },
+    )?;
     match dest.finish() {
         Ok(Some(msg)) => {
             println!("{msg}");
@@ -35,6 +38,58 @@ pub(crate) fn render_all_projects(
     }
 }
 
+type ProjectRenderResult = Result<Option<String>, Box<dyn Error>>;
+
+/// Renders every project in the revision to dest, reporting the outcome for each project. The
+/// caller is responsible for finishing dest.
+fn render_revision(
+    dest: &mut impl RenderDest,
+    fmt: &impl ProjectFormatter,
+    store: &Store,
+    revision: &Revision,
+    mut report: impl FnMut(&ProjectID, ProjectRenderResult),
+) -> Result<(), Box<dyn Error>> {
+    dest.pre_flight(revision)?;
+    for proj_id in store.project_ids_at(revision)? {
+        match store.read_project(&proj_id, revision)? {
+            None => report(&proj_id, Err("missing, oddly.".into())),
+            Some(p) => report(&proj_id, render_project(dest, fmt, &proj_id, p)),
+        };
+    }
+    Ok(())
+}
+
+/// Renders the projects that changed between base and revision to dest. Projects that were
+/// removed, or that can no longer be rendered, are deleted from dest. The caller is responsible
+/// for finishing dest.
+fn render_changed_projects(
+    dest: &mut impl RenderDest,
+    fmt: &impl ProjectFormatter,
+    store: &Store,
+    base: &Revision,
+    revision: &Revision,
+    mut report: impl FnMut(&ProjectID, ProjectRenderResult),
+) -> Result<(), Box<dyn Error>> {
+    dest.pre_flight(revision)?;
+    let ProjectChanges { changed, removed } = store.changed_projects(base, revision)?;
+    for proj_id in removed {
+        let res = dest.delete(&proj_id);
+        report(&proj_id, res);
+    }
+    for proj_id in changed {
+        // Clear out the old rendering first, in case the project type (and so the rendered
+        // file's extension) changed or the project can't be rendered anymore.
+        let res =
+            dest.delete(&proj_id)
+                .and_then(|_| match store.read_project(&proj_id, revision)? {
+                    None => Err("missing, oddly.".into()),
+                    Some(p) => render_project(dest, fmt, &proj_id, p),
+                });
+        report(&proj_id, res);
+    }
+    Ok(())
+}
+
 pub trait RenderDest {
     fn pre_flight(&mut self, revision: &Revision) -> Result<(), Box<dyn Error>>;
 
@@ -45,6 +100,9 @@ pub trait RenderDest {
         content: &[u8],
     ) -> Result<Option<String>, Box<dyn Error>>;
 
+    /// Removes anything previously rendered for the project.
+    fn delete(&mut self, proj_id: &ProjectID) -> Result<Option<String>, Box<dyn Error>>;
+
     fn finish(self) -> Result<Option<String>, Box<dyn Error>>;
 }
 
@@ -57,7 +115,7 @@ fn render_project(
     fmt: &impl ProjectFormatter,
     proj_id: &ProjectID,
     proj_content: RawProject,
-) -> Result<Option<String>, Box<dyn Error>> {
+) -> ProjectRenderResult {
     let proj = proj_content.into_project()?;
     let rendered = fmt.render(&proj)?;
     dest.write(proj_id, proj.project_type(), &rendered)
@@ -0,0 +1,59 @@
+use std::error::Error;
+
+use crate::project::ProjectID;
+use crate::store::{RenderedBranch, Revision, Store};
+
+use super::txt::TextFormatter;
+use super::ProjectRenderResult;
+
+pub const DEFAULT_BRANCH: &str = "rendered";
+
+pub struct SyncResult {
+    /// (source, render) pairs for each newly rendered commit, oldest first.
+    pub rendered: Vec<(Revision, Revision)>,
+    pub project_errors: Vec<(Revision, ProjectID, Box<dyn Error>)>,
+}
+
+/// Adds one rendered commit to branch for each commit in the store that hasn't been rendered yet.
+pub(crate) fn sync(store: &Store, branch: &str) -> Result<SyncResult, Box<dyn Error>> {
+    let RenderedBranch {
+        mut prev_render,
+        mut last_source,
+        pending,
+    } = store.rendered_branch(branch)?;
+
+    let mut rendered = Vec::new();
+    let mut project_errors = Vec::new();
+    for source in pending {
+        let mut report = |proj_id: &ProjectID, res: ProjectRenderResult| {
+            if let Err(e) = res {
+                project_errors.push((source.clone(), proj_id.clone(), e));
+            }
+        };
+        // The first render has nothing to build on, so it includes every project. After that,
+        // only the projects that changed since the last rendered source are rendered again.
+        let incremental = !matches!(prev_render, Revision::Empty);
+        let mut dest = super::store::branch(store, branch, prev_render, incremental);
+        match incremental {
+            true => super::render_changed_projects(
+                &mut dest,
+                &TextFormatter,
+                store,
+                &last_source,
+                &source,
+                &mut report,
+            )?,
+            false => {
+                super::render_revision(&mut dest, &TextFormatter, store, &source, &mut report)?
+            }
+        };
+        prev_render = dest.commit()?;
+        rendered.push((source.clone(), prev_render.clone()));
+        last_source = source;
+    }
+
+    Ok(SyncResult {
+        rendered,
+        project_errors,
+    })
+}
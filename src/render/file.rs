@@ -0,0 +1,224 @@
+//! Renders all projects into a single file: a zip or tar archive, or a Markdown document.
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use zip::write::SimpleFileOptions;
+
+use crate::project::types::ProjectType;
+use crate::project::ProjectID;
+use crate::store::Revision;
+
+use super::RenderDest;
+
+pub enum Format {
+    Zip,
+    Tar,
+    Markdown,
+}
+
+impl Format {
+    fn for_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("zip") => Ok(Format::Zip),
+            Some("tar") => Ok(Format::Tar),
+            Some("md") => Ok(Format::Markdown),
+            _ => Err(format!("{path:?}: unsupported output type, use .zip, .tar, or .md").into()),
+        }
+    }
+}
+
+/// Picks the format from path's extension.
+pub(crate) fn output(path: PathBuf) -> Result<OutFile, Box<dyn Error>> {
+    let format = Format::for_path(&path)?;
+    Ok(OutFile {
+        path,
+        format,
+        revision: Revision::Latest,
+        rendered: Vec::new(),
+    })
+}
+
+struct Rendered {
+    proj_id: ProjectID,
+    proj_type: ProjectType,
+    path: String,
+    content: Vec<u8>,
+}
+
+pub struct OutFile {
+    path: PathBuf,
+    format: Format,
+    revision: Revision,
+    rendered: Vec<Rendered>,
+}
+
+impl RenderDest for OutFile {
+    fn pre_flight(&mut self, revision: &Revision) -> Result<(), Box<dyn Error>> {
+        self.revision = revision.clone();
+        if self.path.exists() {
+            return Err(format!(
+                "{:?}: file already exists, remove it to render there again.",
+                self.path
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        proj_id: &ProjectID,
+        proj_type: ProjectType,
+        content: &[u8],
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let name = Path::new(&proj_id.name).with_extension(proj_type.extension());
+        let path = format!("{}/{}", proj_id.program, name.to_string_lossy());
+        self.rendered.push(Rendered {
+            proj_id: proj_id.clone(),
+            proj_type,
+            path,
+            content: content.to_vec(),
+        });
+        Ok(None)
+    }
+
+    fn delete(&mut self, proj_id: &ProjectID) -> Result<Option<String>, Box<dyn Error>> {
+        let before = self.rendered.len();
+        self.rendered.retain(|r| &r.proj_id != proj_id);
+        match self.rendered.len() == before {
+            true => Ok(None),
+            false => Ok(Some("removed".to_string())),
+        }
+    }
+
+    fn finish(mut self) -> Result<Option<String>, Box<dyn Error>> {
+        self.rendered.sort_by(|a, b| a.path.cmp(&b.path));
+        let f = match File::create_new(&self.path) {
+            Ok(f) => f,
+            Err(e) if matches!(e.kind(), ErrorKind::AlreadyExists) => {
+                return Err(format!(
+                    "{:?}: file already exists, remove it to render there again.",
+                    self.path
+                )
+                .into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match self.format {
+            Format::Zip => write_zip(f, &self.rendered)?,
+            Format::Tar => write_tar(f, &self.rendered)?,
+            Format::Markdown => write_markdown(f, &self.revision, &self.rendered)?,
+        };
+        Ok(Some(format!(
+            "rendered {} projects to {:?}",
+            self.rendered.len(),
+            self.path
+        )))
+    }
+}
+
+fn write_zip(f: File, rendered: &[Rendered]) -> Result<(), Box<dyn Error>> {
+    let mut zip = zip::ZipWriter::new(f);
+    for r in rendered {
+        zip.start_file(&r.path, SimpleFileOptions::default())?;
+        zip.write_all(&r.content)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn write_tar(f: File, rendered: &[Rendered]) -> Result<(), Box<dyn Error>> {
+    let mut tar = tar::Builder::new(f);
+    for r in rendered {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(r.content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+        );
+        tar.append_data(&mut header, &r.path, r.content.as_slice())?;
+    }
+    tar.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn write_markdown(
+    mut f: File,
+    revision: &Revision,
+    rendered: &[Rendered],
+) -> Result<(), Box<dyn Error>> {
+    f.write_all(markdown(revision, rendered).as_bytes())?;
+    Ok(())
+}
+
+fn markdown(revision: &Revision, rendered: &[Rendered]) -> String {
+    let mut md = format!("# Projects at revision {}\n", revision.slug());
+    for r in rendered {
+        let content = String::from_utf8_lossy(&r.content);
+        let lang = match r.proj_type {
+            ProjectType::Python => "python",
+            _ => "",
+        };
+        // Make sure the fence is longer than any run of backticks in the content.
+        let mut fence = "```".to_string();
+        while content.contains(&fence) {
+            fence.push('`');
+        }
+        let _ = write!(
+            md,
+            "\n## {}\n\n_{}_\n\n{fence}{lang}\n{}",
+            r.proj_id, r.proj_type, content
+        );
+        if !content.ends_with('\n') {
+            md.push('\n');
+        }
+        let _ = writeln!(md, "{fence}");
+    }
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::Program;
+
+    #[test]
+    fn test_markdown() {
+        let rendered = vec![Rendered {
+            proj_id: ProjectID {
+                program: Program::Spike,
+                name: "Project 1.llsp3".to_string(),
+            },
+            proj_type: ProjectType::Python,
+            path: "spike/Project 1.py".to_string(),
+            content: b"print('```')".to_vec(),
+        }];
+        assert_eq!(
+            markdown(&Revision::Empty, &rendered),
+            "# Projects at revision empty\n\n## spike: Project 1.llsp3\n\n_python_\n\n````python\nprint('```')\n````\n"
+        );
+    }
+
+    #[test]
+    fn test_format_for_path() {
+        assert!(matches!(
+            Format::for_path(Path::new("out.zip")),
+            Ok(Format::Zip)
+        ));
+        assert!(matches!(
+            Format::for_path(Path::new("a/b.tar")),
+            Ok(Format::Tar)
+        ));
+        assert!(matches!(
+            Format::for_path(Path::new("notes.md")),
+            Ok(Format::Markdown)
+        ));
+        assert!(Format::for_path(Path::new("out.txt")).is_err());
+    }
+}
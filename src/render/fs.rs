@@ -1,6 +1,6 @@
 use std::fs;
 use std::io::ErrorKind;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::project::types::ProjectType;
 use crate::store::Revision;
@@ -42,6 +42,30 @@ impl super::RenderDest for OutDir {
         Ok(Some(format!("rendered to {out_path:?}")))
     }
 
+    fn delete(
+        &mut self,
+        proj_id: &crate::project::ProjectID,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let out_dir = self.path.join(format!("{}", proj_id.program));
+        let stem = Path::new(&proj_id.name).file_stem();
+        let entries = match fs::read_dir(&out_dir) {
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound) => return Ok(None),
+            res => res?,
+        };
+        let mut removed = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.file_stem() == stem {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+        match removed.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(format!("removed {removed:?}"))),
+        }
+    }
+
     fn finish(self) -> Result<Option<String>, Box<dyn std::error::Error>> {
         Ok(None)
     }
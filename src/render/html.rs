@@ -0,0 +1,465 @@
+//! Renders projects as a static HTML site, with one directory per revision.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::project::python::{self, TokenKind};
+use crate::project::scratch::{Arg, BlockView, Category, ScratchProject};
+use crate::project::types::ProjectType;
+use crate::project::{Project, ProjectID};
+use crate::store::{Revision, Store};
+
+use super::RenderDest;
+
+type RenderResult = Result<Vec<u8>, Box<dyn Error>>;
+
+/// Formats a project as the body of an HTML page.
+pub struct HtmlFormatter;
+
+impl super::ProjectFormatter for HtmlFormatter {
+    fn render(&self, proj: &Project) -> RenderResult {
+        let mut out = String::new();
+        if let Some(icon) = proj.icon() {
+            // Use an img tag rather than inlining the svg so that scripts in it can't run.
+            writeln!(
+                out,
+                r#"<img class="icon" alt="" src="data:image/svg+xml;base64,{}">"#,
+                base64(icon)
+            )?;
+        }
+        match proj {
+            Project::Python(p) => out.push_str(&python_html(&p.get_source()?)),
+            Project::WordBlocks(p) | Project::IconBlocks(p) => {
+                out.push_str(&blocks_html(&p.get_scratch()?))
+            }
+        };
+        Ok(out.into_bytes())
+    }
+}
+
+pub fn python_html(src: &str) -> String {
+    let mut out = String::from(r#"<pre class="python"><code>"#);
+    for token in python::tokenize(src) {
+        let class = match token.kind {
+            TokenKind::Keyword => "kw",
+            TokenKind::Builtin => "builtin",
+            TokenKind::Number => "num",
+            TokenKind::String | TokenKind::UnterminatedString => "str",
+            TokenKind::Comment => "comment",
+            TokenKind::Operator | TokenKind::Bracket => "op",
+            _ => {
+                out.push_str(&escape(token.text));
+                continue;
+            }
+        };
+        let _ = write!(
+            out,
+            r#"<span class="{class}">{}</span>"#,
+            escape(token.text)
+        );
+    }
+    out.push_str("</code></pre>\n");
+    out
+}
+
+pub fn blocks_html(proj: &ScratchProject) -> String {
+    let mut out = String::new();
+    for target in &proj.targets {
+        let scripts = target.scripts();
+        if scripts.is_empty() {
+            continue;
+        }
+        let _ = writeln!(
+            out,
+            r#"<section class="target"><h2>{}</h2>"#,
+            escape(&target.name)
+        );
+        for script in scripts {
+            out.push_str(r#"<div class="script">"#);
+            stack_html(&mut out, &script);
+            out.push_str("</div>\n");
+        }
+        for comment in target.comments.values() {
+            let _ = writeln!(
+                out,
+                r#"<aside class="block-comment">{}</aside>"#,
+                escape(&comment.text)
+            );
+        }
+        out.push_str("</section>\n");
+    }
+    if out.is_empty() {
+        out.push_str("<p>This project doesn't have any blocks.</p>\n");
+    }
+    out
+}
+
+fn stack_html(out: &mut String, stack: &[BlockView]) {
+    for view in stack {
+        let _ = write!(
+            out,
+            r#"<div class="block {}" title="{}">"#,
+            category_class(view.block.category()),
+            escape(&view.block.opcode)
+        );
+        inline_html(out, view);
+        for substack in &view.substacks {
+            out.push_str(r#"<div class="substack">"#);
+            stack_html(out, substack);
+            out.push_str("</div>");
+        }
+        out.push_str("</div>");
+    }
+}
+
+fn inline_html(out: &mut String, view: &BlockView) {
+    out.push_str(&escape(&view.label));
+    for (_, arg) in &view.args {
+        match arg {
+            Arg::Literal(s) => {
+                let _ = write!(out, r#" <span class="arg">{}</span>"#, escape(s));
+            }
+            Arg::Block(b) => {
+                let _ = write!(
+                    out,
+                    r#" <span class="reporter {}">"#,
+                    category_class(b.block.category())
+                );
+                inline_html(out, b);
+                out.push_str("</span>");
+            }
+        };
+    }
+}
+
+fn category_class(c: Category) -> String {
+    format!("cat-{}", c.name())
+}
+
+/// Renders into root/<revision>/, with links to the neighboring revisions' directories. The links
+/// are there whether or not the neighbors have been rendered yet, so that rendering revisions in
+/// any order ends up with a site that can be browsed in both directions.
+pub(crate) fn site(
+    root: PathBuf,
+    store: &Store,
+    revision: &Revision,
+) -> Result<HtmlSite, Box<dyn Error>> {
+    let (prev, next) = store.adjacent_revisions(revision)?;
+    let neighbor = |revision: Option<Revision>| -> Result<_, Box<dyn Error>> {
+        let Some(revision) = revision else {
+            return Ok(None);
+        };
+        let pages = store.project_ids_at(&revision)?;
+        Ok(Some(Neighbor {
+            slug: revision.slug(),
+            pages: pages.iter().map(HtmlSite::page_path).collect(),
+        }))
+    };
+    Ok(HtmlSite {
+        dir: root.join(revision.slug()),
+        revision: revision.clone(),
+        prev: neighbor(prev)?,
+        next: neighbor(next)?,
+        pages: BTreeMap::new(),
+    })
+}
+
+pub struct HtmlSite {
+    dir: PathBuf,
+    revision: Revision,
+    prev: Option<Neighbor>,
+    next: Option<Neighbor>,
+    /// Page path (relative to dir) => project.
+    pages: BTreeMap<String, (ProjectID, ProjectType)>,
+}
+
+/// A revision before or after the one being rendered.
+struct Neighbor {
+    slug: String,
+    /// Paths of the pages for the projects in the revision.
+    pages: HashSet<String>,
+}
+
+impl HtmlSite {
+    fn page_path(proj_id: &ProjectID) -> String {
+        let name = Path::new(&proj_id.name).with_extension("html");
+        format!("{}/{}", proj_id.program, name.to_string_lossy())
+    }
+
+    /// Links to the same page in the previous and next revisions.
+    fn nav(&self, up: &str, page: &str) -> String {
+        let mut links = vec![format!(r#"<a href="{up}index.html">All projects</a>"#)];
+        if let Some(href) = neighbor_href(self.prev.as_ref(), page) {
+            links.push(format!(
+                r#"<a href="{up}{}">&larr; Previous revision</a>"#,
+                escape(&href)
+            ));
+        }
+        if let Some(href) = neighbor_href(self.next.as_ref(), page) {
+            links.push(format!(
+                r#"<a href="{up}{}">Next revision &rarr;</a>"#,
+                escape(&href)
+            ));
+        }
+        format!("<nav>{}</nav>\n", links.join(" &middot; "))
+    }
+}
+
+/// The URL of the page in a neighboring revision, relative to this revision's directory. Falls back
+/// to the neighbor's index if the project isn't in it.
+fn neighbor_href(neighbor: Option<&Neighbor>, page: &str) -> Option<String> {
+    let neighbor = neighbor?;
+    let slug = encode(&neighbor.slug);
+    match neighbor.pages.contains(page) {
+        true => Some(format!("../{slug}/{}", page_href(page))),
+        false => Some(format!("../{slug}/index.html")),
+    }
+}
+
+/// Percent-encodes each segment of a page path, so that it can be used in a link.
+fn page_href(page: &str) -> String {
+    page.split('/').map(encode).collect::<Vec<_>>().join("/")
+}
+
+impl RenderDest for HtmlSite {
+    fn pre_flight(&mut self, revision: &Revision) -> Result<(), Box<dyn Error>> {
+        self.revision = revision.clone();
+        if let Some(root) = self.dir.parent() {
+            fs::create_dir_all(root)?;
+        }
+        match fs::create_dir(&self.dir) {
+            Ok(()) => Ok(()),
+            Err(e) if matches!(e.kind(), ErrorKind::AlreadyExists) => Err(format!(
+                "{:?}: directory already exists, remove it to render there again.",
+                self.dir
+            )
+            .into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(
+        &mut self,
+        proj_id: &ProjectID,
+        proj_type: ProjectType,
+        content: &[u8],
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let page = Self::page_path(proj_id);
+        let title = escape(&proj_id.name);
+        let html = format!(
+            "{}{}<h1>{title}</h1>\n<p class=\"meta\">{} &middot; {proj_type} &middot; revision {}</p>\n{}{}",
+            page_header(&title, "../"),
+            self.nav("../", &page),
+            proj_id.program,
+            self.revision.slug(),
+            String::from_utf8_lossy(content),
+            PAGE_FOOTER,
+        );
+
+        let out_path = self.dir.join(&page);
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&out_path, html)?;
+        self.pages.insert(page, (proj_id.clone(), proj_type));
+        Ok(Some(format!("rendered to {out_path:?}")))
+    }
+
+    fn delete(&mut self, proj_id: &ProjectID) -> Result<Option<String>, Box<dyn Error>> {
+        let page = Self::page_path(proj_id);
+        match self.pages.remove(&page) {
+            None => Ok(None),
+            Some(_) => {
+                fs::remove_file(self.dir.join(&page))?;
+                Ok(Some("removed".to_string()))
+            }
+        }
+    }
+
+    fn finish(self) -> Result<Option<String>, Box<dyn Error>> {
+        let mut html = page_header("Projects", "");
+        html.push_str(&self.nav("", "index.html"));
+        let _ = writeln!(
+            html,
+            "<h1>Projects</h1>\n<p class=\"meta\">revision {}</p>",
+            self.revision.slug()
+        );
+        html.push_str("<table>\n<tr><th>Program</th><th>Project</th><th>Type</th></tr>\n");
+        for (page, (proj_id, proj_type)) in &self.pages {
+            let _ = writeln!(
+                html,
+                r#"<tr><td>{}</td><td><a href="{}">{}</a></td><td>{proj_type}</td></tr>"#,
+                proj_id.program,
+                escape(&page_href(page)),
+                escape(&proj_id.name)
+            );
+        }
+        html.push_str("</table>\n");
+        html.push_str(PAGE_FOOTER);
+
+        let index = self.dir.join("index.html");
+        fs::write(&index, html)?;
+        fs::write(self.dir.join("style.css"), stylesheet())?;
+        Ok(Some(format!("rendered site to {index:?}")))
+    }
+}
+
+fn page_header(title: &str, up: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<link rel=\"stylesheet\" href=\"{up}style.css\">\n</head>\n<body>\n"
+    )
+}
+
+const PAGE_FOOTER: &str = "</body>\n</html>\n";
+
+pub(crate) fn stylesheet() -> String {
+    let mut css = String::from(
+        r#"body { font-family: sans-serif; margin: 2em; color: #222; }
+nav a { margin-right: 0.5em; }
+.meta { color: #666; }
+.icon { float: right; width: 96px; height: 96px; }
+table { border-collapse: collapse; }
+td, th { padding: 0.25em 1em; border-bottom: 1px solid #ddd; text-align: left; }
+pre.python { background: #f6f8fa; padding: 1em; overflow-x: auto; }
+.kw { color: #d73a49; font-weight: bold; }
+.builtin { color: #6f42c1; }
+.num { color: #005cc5; }
+.str { color: #032f62; }
+.comment { color: #6a737d; font-style: italic; }
+.op { color: #555; }
+.script { margin: 1em 0; }
+.block { color: white; padding: 0.3em 0.6em; margin: 2px 0; border-radius: 4px; width: fit-content; min-width: 12em; }
+.substack { margin: 0.3em 0 0.3em 1.5em; }
+.arg { background: white; color: #333; border-radius: 1em; padding: 0 0.5em; }
+.reporter { border-radius: 1em; padding: 0 0.5em; border: 1px solid rgba(0, 0, 0, 0.2); }
+.block-comment { background: #fef49c; padding: 0.5em; width: fit-content; }
+"#,
+    );
+    for c in Category::ALL {
+        let _ = writeln!(
+            css,
+            ".{} {{ background: {}; }}",
+            category_class(c),
+            c.color()
+        );
+    }
+    css
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            c => res.push(c),
+        };
+    }
+    res
+}
+
+/// Percent-encodes a URL path segment.
+pub(crate) fn encode(s: &str) -> String {
+    let mut res = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                res.push(b as char)
+            }
+            b => {
+                let _ = write!(res, "%{b:02X}");
+            }
+        };
+    }
+    res
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => res.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => res.push('='),
+            };
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_python_html() {
+        assert_eq!(
+            python_html("if x < 1: # <hi>\n"),
+            concat!(
+                r#"<pre class="python"><code><span class="kw">if</span> x "#,
+                r#"<span class="op">&lt;</span> <span class="num">1</span>"#,
+                r#"<span class="op">:</span> <span class="comment"># &lt;hi&gt;</span>"#,
+                "\n</code></pre>\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_nav() {
+        let neighbor = |slug: &str, pages: &[&str]| Neighbor {
+            slug: slug.to_string(),
+            pages: pages.iter().map(|p| p.to_string()).collect(),
+        };
+        let page = "spike/Robot #1 & \"2\".html";
+        let site = HtmlSite {
+            dir: PathBuf::from("2"),
+            revision: Revision::Sqlite(2),
+            prev: Some(neighbor("1", &[page])),
+            next: Some(neighbor("3", &[])),
+            pages: BTreeMap::new(),
+        };
+        assert_eq!(
+            site.nav("../", page),
+            concat!(
+                r#"<nav><a href="../index.html">All projects</a> &middot; "#,
+                r#"<a href="../../1/spike/Robot%20%231%20%26%20%222%22.html">"#,
+                r#"&larr; Previous revision</a> &middot; "#,
+                r#"<a href="../../3/index.html">Next revision &rarr;</a></nav>"#,
+                "\n"
+            )
+        );
+
+        let site = HtmlSite {
+            prev: None,
+            next: None,
+            ..site
+        };
+        assert_eq!(
+            site.nav("../", page),
+            "<nav><a href=\"../index.html\">All projects</a></nav>\n"
+        );
+    }
+}
@@ -1,19 +1,67 @@
-use crate::store::{Revision, Store};
+use crate::project::ProjectID;
+use crate::store::{RenderedTree, Revision, Store};
 
 use super::RenderDest;
 
-pub fn tree(store: &Store) -> StoreRenderer {
+pub fn tree(store: &Store) -> StoreRenderer<'_> {
     StoreRenderer {
         store,
         revision: Revision::Latest,
-        rendered: Default::default(),
+        rendered: empty_tree(),
+        prev_render: Revision::Empty,
+        branch: None,
+    }
+}
+
+/// Renders on top of prev_render and points branch at the result. If incremental is true, the
+/// files in prev_render are kept unless they're replaced or deleted.
+pub fn branch<'a>(
+    store: &'a Store,
+    branch: &'a str,
+    prev_render: Revision,
+    incremental: bool,
+) -> StoreRenderer<'a> {
+    let mut rendered = empty_tree();
+    if incremental {
+        rendered.base = prev_render.clone();
+    }
+    StoreRenderer {
+        store,
+        revision: Revision::Latest,
+        rendered,
+        prev_render,
+        branch: Some(branch),
+    }
+}
+
+fn empty_tree() -> RenderedTree {
+    RenderedTree {
+        base: Revision::Empty,
+        files: Vec::new(),
+        removed: Vec::new(),
     }
 }
 
 pub struct StoreRenderer<'a> {
     store: &'a Store,
     revision: Revision,
-    rendered: Vec<(String, Vec<u8>)>,
+    rendered: RenderedTree,
+    prev_render: Revision,
+    branch: Option<&'a str>,
+}
+
+impl StoreRenderer<'_> {
+    /// Writes the rendered projects as a commit, returning the new commit.
+    pub fn commit(self) -> Result<Revision, Box<dyn std::error::Error>> {
+        let msg = format!("Rendered {}", self.revision);
+        self.store.store_render(
+            &self.rendered,
+            &msg,
+            self.prev_render,
+            self.revision,
+            self.branch,
+        )
+    }
 }
 
 impl<'a> RenderDest for StoreRenderer<'a> {
@@ -34,18 +82,21 @@ impl<'a> RenderDest for StoreRenderer<'a> {
             drop_extension(&proj_id.name),
             proj_type.extension()
         );
-        self.rendered.push((path, content.to_vec()));
+        self.rendered.files.push((path, content.to_vec()));
         Ok(None)
     }
 
+    fn delete(
+        &mut self,
+        proj_id: &ProjectID,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let path = format!("{}/{}", proj_id.program, drop_extension(&proj_id.name));
+        self.rendered.removed.push(path);
+        Ok(Some("removed".to_string()))
+    }
+
     fn finish(self) -> Result<Option<String>, Box<dyn std::error::Error>> {
-        let msg = format!("Rendered {}", self.revision);
-        let commit_id = self.store.store_render(
-            &self.rendered,
-            &msg,
-            Revision::Empty,
-            self.revision.clone(),
-        )?;
+        let commit_id = self.commit()?;
         Ok(Some(format!("created commit {commit_id}")))
     }
 }
@@ -1,19 +1,55 @@
 pub struct TextFormatter;
 
-use crate::project::{Project, PythonProject};
+use similar::TextDiff;
+
+use crate::project::{Project, ProjectID, PythonProject};
+use crate::store::{Revision, Store};
+
+use super::ProjectFormatter;
 
 type RenderResult = Result<Vec<u8>, Box<dyn std::error::Error>>;
 
-impl super::ProjectFormatter for TextFormatter {
+impl ProjectFormatter for TextFormatter {
     fn render(&self, proj: &Project) -> RenderResult {
         match proj {
             Project::Python(proj) => render_python(proj),
-            Project::WordBlocks => Err("todo: render word-blocks".into()),
-            Project::IconBlocks => Err("todo: render icon-blocks".into()),
+            Project::WordBlocks(_) => Err("todo: render word-blocks".into()),
+            Project::IconBlocks(_) => Err("todo: render icon-blocks".into()),
+        }
+    }
+}
+
+/// Returns the text rendering of the project at revision, or an empty string if it isn't there.
+pub fn project_text(
+    store: &Store,
+    id: &ProjectID,
+    revision: &Revision,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match store.read_project(id, revision)? {
+        None => Ok(String::new()),
+        Some(p) => {
+            let rendered = TextFormatter.render(&p.into_project()?)?;
+            Ok(String::from_utf8_lossy(&rendered).to_string())
         }
     }
 }
 
+/// Returns a unified diff between the text renderings of the project at two revisions. The
+/// labels name the revisions in the diff header.
+pub fn diff(
+    store: &Store,
+    id: &ProjectID,
+    (from_label, from): (&str, &Revision),
+    (to_label, to): (&str, &Revision),
+) -> Result<String, Box<dyn std::error::Error>> {
+    let old = project_text(store, id, from)?;
+    let new = project_text(store, id, to)?;
+    Ok(TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(from_label, to_label)
+        .to_string())
+}
+
 fn render_python(proj: &PythonProject) -> RenderResult {
     Ok(proj.get_source()?.bytes().collect())
 }
@@ -0,0 +1,27 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use crate::dirs::Dirs;
+use crate::project::ProjectID;
+use crate::store::{Revision, Store};
+
+/// Overwrites the local copy of a project with the version from revision. Returns the path that
+/// was written.
+pub fn restore(
+    store: &Store,
+    id: &ProjectID,
+    revision: &Revision,
+    dirs: &Dirs,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let project = match store.read_project(id, revision)? {
+        None => return Err(format!("{id} is not in {store} at {revision}").into()),
+        Some(p) => p,
+    };
+    let path = id.path(dirs);
+    // Write next to the project first so that a failure doesn't leave a partial file behind.
+    let tmp_path = path.with_extension("mind-meld-restore");
+    project.write(File::create(&tmp_path)?)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(path)
+}
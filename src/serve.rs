@@ -0,0 +1,373 @@
+//! A local web UI for browsing stores, project history, and rendered code.
+
+use std::error::Error;
+use std::fmt::Write;
+use std::io::Cursor;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config::{Config, StoreConfig};
+use crate::dirs::Dirs;
+use crate::project::{Program, ProjectID};
+use crate::render::html::{encode, escape, stylesheet, HtmlFormatter};
+use crate::render::txt;
+use crate::render::ProjectFormatter;
+use crate::restore;
+use crate::store::{self, Revision, Store};
+
+/// Serves the UI on the loopback interface until the process is stopped. Use port 0 to pick any
+/// available port.
+pub fn serve(cfg: &Config, port: u16) -> Result<(), Box<dyn Error>> {
+    let dirs = Dirs::new(cfg)?;
+    let server = Server::http(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+    let addr = server
+        .server_addr()
+        .to_ip()
+        .ok_or("not listening on an IP address")?;
+    println!("Listening on http://{addr}/");
+
+    for request in server.incoming_requests() {
+        let page = handle(cfg, &dirs, addr.port(), &request);
+        println!("{} {} {}", request.method(), request.url(), page.status);
+        if let Err(e) = request.respond(page.into_response()) {
+            println!("error sending response: {e}");
+        }
+    }
+    Ok(())
+}
+
+struct Page {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Page {
+    fn html(title: &str, body: String) -> Self {
+        Self::with_status(200, title, body)
+    }
+
+    fn with_status(status: u16, title: &str, body: String) -> Self {
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{} - mind meld</title>\n<link rel=\"stylesheet\" href=\"/style.css\">\n</head>\n<body>\n<nav><a href=\"/\">Stores</a></nav>\n<h1>{}</h1>\n{body}</body>\n</html>\n",
+            escape(title),
+            escape(title)
+        );
+        Self {
+            status,
+            content_type: "text/html; charset=utf-8",
+            body,
+        }
+    }
+
+    fn error(status: u16, msg: &str) -> Self {
+        Self::with_status(status, "Error", format!("<p>{}</p>\n", escape(msg)))
+    }
+
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let content_type = Header::from_bytes("Content-Type", self.content_type).unwrap();
+        Response::from_string(self.body)
+            .with_status_code(self.status)
+            .with_header(content_type)
+    }
+}
+
+type PageResult = Result<Page, Box<dyn Error>>;
+
+fn handle(cfg: &Config, dirs: &Dirs, port: u16, req: &Request) -> Page {
+    // Only answer requests addressed to the loopback interface, so that other web sites can't
+    // reach the server by pointing their own host name at 127.0.0.1.
+    let local = [format!("127.0.0.1:{port}"), format!("localhost:{port}")];
+    match header(req, "Host") {
+        Some(host) if local.iter().any(|l| l == host) => {}
+        _ => return Page::error(403, "unexpected host"),
+    };
+    // Browsers send Origin with form posts, reject posts from other sites.
+    if let Some(origin) = header(req, "Origin")
+        && !local.iter().any(|l| origin == format!("http://{l}"))
+    {
+        return Page::error(403, "unexpected origin");
+    }
+
+    let path = req.url().split('?').next().unwrap_or("/");
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let res = match (req.method(), segments.as_slice()) {
+        (Method::Get, []) => index_page(cfg),
+        (Method::Get, ["style.css"]) => Ok(Page {
+            status: 200,
+            content_type: "text/css; charset=utf-8",
+            body: stylesheet(),
+        }),
+        (Method::Get, ["stores", st]) => store_page(cfg, st),
+        (Method::Get, ["stores", st, "log"]) => log_page(cfg, st),
+        (Method::Get, ["stores", st, "projects", program, name]) => {
+            history_page(cfg, st, program, name)
+        }
+        (Method::Get, ["stores", st, "projects", program, name, "at", rev]) => {
+            project_page(cfg, st, program, name, rev)
+        }
+        (Method::Get, ["stores", st, "projects", program, name, "diff", from, to]) => {
+            diff_page(cfg, st, program, name, from, to)
+        }
+        (Method::Post, ["stores", st, "projects", program, name, "restore", rev]) => {
+            restore_page(cfg, dirs, st, program, name, rev)
+        }
+        _ => Ok(Page::error(404, "not found")),
+    };
+    res.unwrap_or_else(|e| Page::error(500, &e.to_string()))
+}
+
+fn header<'a>(req: &'a Request, name: &'static str) -> Option<&'a str> {
+    req.headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn index_page(cfg: &Config) -> PageResult {
+    let mut body = String::new();
+    if cfg.stores.is_empty() {
+        body.push_str("<p>No stores yet!</p>\n");
+    }
+    body.push_str("<ul>\n");
+    for (i, st) in cfg.stores.iter().enumerate() {
+        let _ = writeln!(
+            body,
+            r#"<li><a href="/stores/{i}">{}</a></li>"#,
+            escape(&st.to_string())
+        );
+    }
+    body.push_str("</ul>\n");
+    Ok(Page::html("Stores", body))
+}
+
+fn store_page(cfg: &Config, st: &str) -> PageResult {
+    let (sc, store) = open_store(cfg, st)?;
+    let mut proj_ids = store.project_ids()?;
+    proj_ids.sort_by_key(|p| p.to_string());
+
+    let mut body = format!(r#"<p><a href="/stores/{st}/log">History</a></p>"#);
+    body.push_str("\n<h2>Tracked projects</h2>\n<ul>\n");
+    for proj_id in proj_ids {
+        let _ = writeln!(
+            body,
+            r#"<li><a href="{}">{}</a></li>"#,
+            project_url(st, &proj_id, ""),
+            escape(&proj_id.to_string())
+        );
+    }
+    body.push_str("</ul>\n");
+    Ok(Page::html(&sc.to_string(), body))
+}
+
+fn log_page(cfg: &Config, st: &str) -> PageResult {
+    let (sc, store) = open_store(cfg, st)?;
+    let mut body = String::from("<table>\n");
+    for commit in store.history()? {
+        let projects: Vec<String> = commit
+            .changed_projects
+            .iter()
+            .map(|p| {
+                format!(
+                    r#"<a href="{}">{}</a>"#,
+                    project_url(st, p, &format!("at/{}", commit.hash)),
+                    escape(&p.to_string())
+                )
+            })
+            .collect();
+        let _ = writeln!(
+            body,
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            commit.hash,
+            crate::format_datetime(commit.date),
+            escape(&commit.message),
+            projects.join("<br>")
+        );
+    }
+    body.push_str("</table>\n");
+    Ok(Page::html(&format!("History of {sc}"), body))
+}
+
+fn history_page(cfg: &Config, st: &str, program: &str, name: &str) -> PageResult {
+    let (_, store) = open_store(cfg, st)?;
+    let proj_id = project_id(program, name)?;
+    let commits = store.project_history(&proj_id)?;
+
+    let mut body = String::from("<table>\n");
+    for (i, commit) in commits.iter().enumerate() {
+        // Compare with the previous commit that changed this project.
+        let prev = commits.get(i + 1).map_or("empty", |c| c.hash.as_str());
+        let _ = writeln!(
+            body,
+            r#"<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td><a href="{}">view</a> &middot; <a href="{}">diff</a></td><td>{}</td></tr>"#,
+            commit.hash,
+            crate::format_datetime(commit.date),
+            escape(&commit.message),
+            project_url(st, &proj_id, &format!("at/{}", commit.hash)),
+            project_url(st, &proj_id, &format!("diff/{prev}/{}", commit.hash)),
+            restore_form(st, &proj_id, &commit.hash),
+        );
+    }
+    body.push_str("</table>\n");
+    Ok(Page::html(&proj_id.to_string(), body))
+}
+
+fn project_page(cfg: &Config, st: &str, program: &str, name: &str, rev: &str) -> PageResult {
+    let (_, store) = open_store(cfg, st)?;
+    let proj_id = project_id(program, name)?;
+    let revision = parse_revision(&store, rev)?;
+    let project = match store.read_project(&proj_id, &revision)? {
+        None => return Ok(Page::error(404, &format!("{proj_id} is not in {rev}"))),
+        Some(p) => p.into_project()?,
+    };
+    let rendered = HtmlFormatter.render(&project)?;
+
+    let mut body = format!(
+        r#"<p class="meta">{} &middot; revision {} &middot; <a href="{}">history</a></p>"#,
+        project.project_type(),
+        escape(rev),
+        project_url(st, &proj_id, "")
+    );
+    body.push('\n');
+    body.push_str(&restore_form(st, &proj_id, rev));
+    body.push_str(&String::from_utf8_lossy(&rendered));
+    Ok(Page::html(&proj_id.to_string(), body))
+}
+
+fn diff_page(
+    cfg: &Config,
+    st: &str,
+    program: &str,
+    name: &str,
+    from: &str,
+    to: &str,
+) -> PageResult {
+    let (_, store) = open_store(cfg, st)?;
+    let proj_id = project_id(program, name)?;
+    let diff = txt::diff(
+        &store,
+        &proj_id,
+        (from, &parse_revision(&store, from)?),
+        (to, &parse_revision(&store, to)?),
+    )?;
+
+    let mut body = format!(
+        r#"<p class="meta"><a href="{}">history</a></p>"#,
+        project_url(st, &proj_id, "")
+    );
+    body.push_str("\n<pre class=\"diff\">");
+    for line in diff.lines() {
+        let class = match line.chars().next() {
+            Some('+') => "add",
+            Some('-') => "del",
+            Some('@') => "hunk",
+            _ => "ctx",
+        };
+        let _ = writeln!(body, r#"<span class="{class}">{}</span>"#, escape(line));
+    }
+    body.push_str("</pre>\n");
+    Ok(Page::html(&format!("{proj_id}: {from}..{to}"), body))
+}
+
+fn restore_page(
+    cfg: &Config,
+    dirs: &Dirs,
+    st: &str,
+    program: &str,
+    name: &str,
+    rev: &str,
+) -> PageResult {
+    let (_, store) = open_store(cfg, st)?;
+    let proj_id = project_id(program, name)?;
+    let revision = parse_revision(&store, rev)?;
+    let path = restore::restore(&store, &proj_id, &revision, dirs)?;
+    let body = format!(
+        r#"<p>Restored {} from {} to <code>{}</code>.</p><p><a href="{}">Back to history</a></p>"#,
+        escape(&proj_id.to_string()),
+        escape(rev),
+        escape(&path.display().to_string()),
+        project_url(st, &proj_id, "")
+    );
+    Ok(Page::html("Restored", body))
+}
+
+fn restore_form(st: &str, proj_id: &ProjectID, rev: &str) -> String {
+    format!(
+        r#"<form method="post" action="{}" onsubmit="return confirm('Replace the local copy with this version?')"><button>Restore this version</button></form>"#,
+        project_url(st, proj_id, &format!("restore/{}", encode(rev)))
+    )
+}
+
+fn open_store(cfg: &Config, st: &str) -> Result<(StoreConfig, Store), Box<dyn Error>> {
+    let sc = st
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| cfg.stores.get(i))
+        .ok_or_else(|| format!("no store {st:?}"))?;
+    Ok((sc.clone(), store::open(sc)?))
+}
+
+fn project_id(program: &str, name: &str) -> Result<ProjectID, Box<dyn Error>> {
+    Ok(ProjectID {
+        program: program.parse::<Program>()?,
+        name: name.to_string(),
+    })
+}
+
+fn parse_revision(store: &Store, rev: &str) -> Result<Revision, Box<dyn Error>> {
+    match rev {
+        "empty" => Ok(Revision::Empty),
+        rev => store.resolve(rev),
+    }
+}
+
+fn project_url(st: &str, proj_id: &ProjectID, rest: &str) -> String {
+    format!(
+        "/stores/{st}/projects/{}/{}/{rest}",
+        proj_id.program,
+        encode(&proj_id.name)
+    )
+}
+
+/// Decodes a percent-encoded URL path segment.
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                res.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                res.push(b);
+                i += 1;
+            }
+        };
+    }
+    String::from_utf8_lossy(&res).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        assert_eq!(encode("Project 1.llsp3"), "Project%201.llsp3");
+        assert_eq!(decode("Project%201.llsp3"), "Project 1.llsp3");
+        assert_eq!(decode(&encode("ü/?#%")), "ü/?#%");
+        assert_eq!(decode("100%"), "100%");
+    }
+}
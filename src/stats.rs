@@ -0,0 +1,301 @@
+//! How projects change over time, from the store's history.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+
+use crate::project::scratch::Category;
+use crate::project::{Project, ProjectID};
+use crate::store::{LogResult, Store};
+
+/// How big a project is at some revision.
+pub enum Size {
+    /// Lines of Python, not counting blank lines and comments.
+    Lines(usize),
+    /// Number of blocks in each category, not counting menus.
+    Blocks(BTreeMap<Category, usize>),
+}
+
+impl Size {
+    fn of(proj: &Project) -> Result<(Size, usize), Box<dyn Error>> {
+        match proj {
+            Project::Python(p) => Ok((Size::Lines(lines_of_code(&p.get_source()?)), 0)),
+            Project::WordBlocks(p) | Project::IconBlocks(p) => {
+                let scratch = p.get_scratch()?;
+                let mut counts = BTreeMap::new();
+                let mut sounds = 0;
+                for target in &scratch.targets {
+                    sounds += target.sounds.len();
+                    for (_, block) in target.each_block().filter(|(_, b)| !b.shadow) {
+                        *counts.entry(block.category()).or_default() += 1;
+                    }
+                }
+                Ok((Size::Blocks(counts), sounds))
+            }
+        }
+    }
+
+    fn total(&self) -> usize {
+        match self {
+            Size::Lines(n) => *n,
+            Size::Blocks(counts) => counts.values().sum(),
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Size::Lines(_) => "lines",
+            Size::Blocks(_) => "blocks",
+        }
+    }
+}
+
+fn lines_of_code(src: &str) -> usize {
+    src.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .count()
+}
+
+/// A project as of one commit that changed it.
+pub struct Snapshot {
+    pub hash: String,
+    pub date: SystemTime,
+    pub size: Size,
+    pub sounds: usize,
+}
+
+pub struct ProjectStats {
+    pub id: ProjectID,
+    pub commits: usize,
+    /// Number of different days (in local time) with commits.
+    pub active_days: usize,
+    pub first_modified: SystemTime,
+    pub last_modified: SystemTime,
+    /// Oldest first. Commits that removed the project don't have a snapshot.
+    pub history: Vec<Snapshot>,
+    /// Commits whose copy of the project couldn't be read, as (revision, error). They don't have
+    /// a snapshot either.
+    pub errors: Vec<(String, String)>,
+}
+
+/// Walks the commits since the given time, oldest first, and collects stats for every project
+/// they changed.
+pub fn stats(store: &Store, since: SystemTime) -> Result<Vec<ProjectStats>, Box<dyn Error>> {
+    let mut commits = match store.log(since)? {
+        LogResult::Unborn | LogResult::None(_) => Vec::new(),
+        LogResult::Some(commits) => commits,
+    };
+    commits.reverse();
+
+    let mut res: HashMap<ProjectID, ProjectStats> = HashMap::new();
+    let mut days: HashMap<ProjectID, BTreeSet<String>> = HashMap::new();
+    for commit in commits {
+        for id in commit.changed_projects {
+            let stats = res.entry(id.clone()).or_insert_with(|| ProjectStats {
+                id: id.clone(),
+                commits: 0,
+                active_days: 0,
+                first_modified: commit.date,
+                last_modified: commit.date,
+                history: Vec::new(),
+                errors: Vec::new(),
+            });
+            stats.commits += 1;
+            stats.last_modified = commit.date;
+            days.entry(id.clone())
+                .or_default()
+                .insert(format_date(commit.date));
+
+            // Old snapshots can be damaged (see mm fsck), which shouldn't hide the rest of the report.
+            let size = store
+                .read_project(&id, &commit.revision)
+                .and_then(|raw| raw.map(|raw| Size::of(&raw.into_project()?)).transpose());
+            match size {
+                Ok(None) => {}
+                Ok(Some((size, sounds))) => stats.history.push(Snapshot {
+                    hash: commit.hash.clone(),
+                    date: commit.date,
+                    size,
+                    sounds,
+                }),
+                Err(e) => stats.errors.push((commit.hash.clone(), e.to_string())),
+            };
+        }
+    }
+
+    let mut res: Vec<ProjectStats> = res.into_values().collect();
+    for stats in &mut res {
+        stats.active_days = days.get(&stats.id).map_or(0, BTreeSet::len);
+    }
+    res.sort_by_key(|s| s.id.to_string());
+    Ok(res)
+}
+
+fn format_date(time: SystemTime) -> String {
+    let datetime: DateTime<Local> = time.into();
+    datetime.format("%Y-%m-%d").to_string()
+}
+
+fn format_rfc3339(time: SystemTime) -> String {
+    let datetime: DateTime<Local> = time.into();
+    datetime.to_rfc3339()
+}
+
+/// One line per project, with how much it grew.
+pub fn table(stats: &[ProjectStats]) -> String {
+    let header = [
+        "Project", "Commits", "Days", "First", "Last", "Sounds", "Size",
+    ];
+    let rows: Vec<[String; 7]> = stats
+        .iter()
+        .map(|s| {
+            let size = match (s.history.first(), s.history.last()) {
+                (Some(first), Some(last)) if first.size.total() != last.size.total() => format!(
+                    "{} -> {} {}",
+                    first.size.total(),
+                    last.size.total(),
+                    last.size.unit()
+                ),
+                (_, Some(last)) => format!("{} {}", last.size.total(), last.size.unit()),
+                _ => "removed".to_string(),
+            };
+            [
+                s.id.to_string(),
+                s.commits.to_string(),
+                s.active_days.to_string(),
+                format_date(s.first_modified),
+                format_date(s.last_modified),
+                s.history.last().map_or(0, |h| h.sounds).to_string(),
+                size,
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| format!("{cell:w$}"))
+            .collect();
+        let _ = writeln!(out, "{}", cells.join("  ").trim_end());
+    }
+    out
+}
+
+/// One line per project per commit, so that growth can be charted in a spreadsheet.
+pub fn csv(stats: &[ProjectStats]) -> String {
+    let mut out = String::from("program,name,revision,date,lines,sounds");
+    for c in Category::ALL {
+        let _ = write!(out, ",{}_blocks", c.name().replace('-', "_"));
+    }
+    out.push('\n');
+    for s in stats {
+        for snap in &s.history {
+            let (lines, blocks) = match &snap.size {
+                Size::Lines(n) => (n.to_string(), None),
+                Size::Blocks(counts) => (String::new(), Some(counts)),
+            };
+            let _ = write!(
+                out,
+                "{},{},{},{},{lines},{}",
+                s.id.program,
+                csv_field(&s.id.name),
+                snap.hash,
+                format_rfc3339(snap.date),
+                snap.sounds
+            );
+            for c in Category::ALL {
+                match blocks {
+                    Some(counts) => write!(out, ",{}", counts.get(&c).unwrap_or(&0)),
+                    None => write!(out, ","),
+                }
+                .unwrap_or_default();
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+pub fn to_json(stats: &[ProjectStats]) -> Value {
+    let projects: Vec<Value> = stats
+        .iter()
+        .map(|s| {
+            let history: Vec<Value> = s
+                .history
+                .iter()
+                .map(|snap| {
+                    let size = match &snap.size {
+                        Size::Lines(n) => json!({ "lines": n }),
+                        Size::Blocks(counts) => {
+                            let counts: BTreeMap<&str, usize> =
+                                counts.iter().map(|(c, n)| (c.name(), *n)).collect();
+                            json!({ "blocks": counts })
+                        }
+                    };
+                    json!({
+                        "revision": snap.hash,
+                        "date": format_rfc3339(snap.date),
+                        "size": size,
+                        "sounds": snap.sounds,
+                    })
+                })
+                .collect();
+            let errors: Vec<Value> = s
+                .errors
+                .iter()
+                .map(|(hash, e)| json!({ "revision": hash, "error": e }))
+                .collect();
+            json!({
+                "program": s.id.program.to_string(),
+                "name": s.id.name,
+                "commits": s.commits,
+                "active_days": s.active_days,
+                "first_modified": format_rfc3339(s.first_modified),
+                "last_modified": format_rfc3339(s.last_modified),
+                "history": history,
+                "errors": errors,
+            })
+        })
+        .collect();
+    json!({ "projects": projects })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_of_code() {
+        assert_eq!(
+            lines_of_code("# hi\n\nimport hub\n  # indented\nprint(1)\n"),
+            2
+        );
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("Project 1.llsp3"), "Project 1.llsp3");
+        assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+}
@@ -1,35 +1,35 @@
 use std::error::Error;
-use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::cache::Cache;
 use crate::dirs::Dirs;
-use crate::project::{self, ProjectID};
-use crate::store::{Revision, Store};
+use crate::project::ProjectID;
+use crate::store::Store;
 
 pub enum Status {
     NoDifferences,
     LocalMissing,
-    Differences(Vec<Rc<Store>>),
+    Differences(Vec<Arc<Store>>),
 }
 
 pub fn get_status(
     proj: &ProjectID,
-    stores: &[Rc<Store>],
+    stores: &[Arc<Store>],
     dirs: &Dirs,
+    cache: &Cache,
 ) -> Result<Status, Box<dyn Error>> {
-    let local = match project::read(proj, dirs) {
-        Ok(Some(project)) => project,
+    let local_hash = match cache.local_hash(proj, dirs) {
+        Ok(Some(hash)) => hash,
         Ok(None) => return Ok(Status::LocalMissing),
         Err(e) => return Err(e),
     };
-    let local_hash = local.hash();
 
     let mut diff = Vec::new();
     for st in stores {
-        match st.read_project(proj, &Revision::Latest) {
+        match cache.store_hash(st, proj) {
             Err(_) => diff.push(st.clone()),
             Ok(None) => diff.push(st.clone()),
-            Ok(Some(st_proj)) => {
-                let st_hash = st_proj.hash();
+            Ok(Some(st_hash)) => {
                 if st_hash != local_hash {
                     diff.push(st.clone());
                 }
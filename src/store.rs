@@ -1,34 +1,52 @@
+mod encrypted;
 mod git;
+mod sqlite;
 
 use std::collections::HashSet;
 use std::error::Error;
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::compact::{CompactOptions, CompactResult};
 use crate::config::StoreConfig;
 use crate::project::{self, ProjectID};
 
+/// A store that can be shared between threads. Calls to the same store wait for each other,
+/// since none of the underlying handles can be used from two threads at once.
 pub struct Store {
     path: PathBuf,
-    inst: StoreInstance,
+    inst: Mutex<StoreInstance>,
 }
 
 const STORE_TYPE_GIT: &str = "git";
+const STORE_TYPE_ENCRYPTED: &str = "encrypted";
+const STORE_TYPE_SQLITE: &str = "sqlite";
+
+const NOT_COMPACTABLE: &str = "only git stores' history can be compacted";
 
 enum StoreType {
     Git,
+    Encrypted,
+    Sqlite,
 }
 
 enum StoreInstance {
-    Git(git::GitStore),
+    Git(Box<git::GitStore>),
+    Encrypted(encrypted::EncryptedStore),
+    Sqlite(sqlite::SqliteStore),
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Revision {
     Empty,
     Latest,
     Git(gix::ObjectId),
+    /// The name of a commit's file in an encrypted store.
+    Encrypted(String),
+    /// The id of a commit in a sqlite store.
+    Sqlite(i64),
 }
 
 impl Display for Revision {
@@ -37,6 +55,21 @@ impl Display for Revision {
             Revision::Empty => write!(f, "(empty)"),
             Revision::Latest => write!(f, "(latest)"),
             Revision::Git(oid) => write!(f, "(git:{oid})"),
+            Revision::Encrypted(name) => write!(f, "(encrypted:{name})"),
+            Revision::Sqlite(id) => write!(f, "(sqlite:{id})"),
+        }
+    }
+}
+
+impl Revision {
+    /// A short name for the revision that can be used in file names.
+    pub fn slug(&self) -> String {
+        match self {
+            Revision::Empty => "empty".to_string(),
+            Revision::Latest => "latest".to_string(),
+            Revision::Git(oid) => oid.to_hex_with_len(12).to_string(),
+            Revision::Encrypted(name) => name[..name.len().min(12)].to_string(),
+            Revision::Sqlite(id) => id.to_string(),
         }
     }
 }
@@ -45,6 +78,35 @@ type StoreErrors = Vec<(StoreConfig, Box<dyn Error>)>;
 
 pub type CommitResult = Result<&'static str, Box<dyn Error>>;
 
+/// Where a rendered branch stands relative to the store's history.
+pub struct RenderedBranch {
+    /// The newest rendered commit on the branch, or `Revision::Empty` if the branch doesn't exist.
+    pub prev_render: Revision,
+    /// The source revision that prev_render was rendered from.
+    pub last_source: Revision,
+    /// Source revisions that haven't been rendered yet, oldest first.
+    pub pending: Vec<Revision>,
+}
+
+/// Rendered files to store as a commit.
+pub struct RenderedTree {
+    /// The revision whose tree is the starting point (`Revision::Empty` to start from scratch).
+    pub base: Revision,
+    /// Paths and contents of files to add or replace.
+    pub files: Vec<(String, Vec<u8>)>,
+    /// Files to remove from base, as paths without an extension. These are removed before files
+    /// are added.
+    pub removed: Vec<String>,
+}
+
+/// The projects that differ between two revisions.
+#[derive(Debug, Default)]
+pub struct ProjectChanges {
+    /// Projects that were added or modified.
+    pub changed: Vec<ProjectID>,
+    pub removed: Vec<ProjectID>,
+}
+
 pub enum LogResult {
     Unborn,
     None(CommitInfo), // returns the newest commit
@@ -54,30 +116,61 @@ pub enum LogResult {
 #[derive(Debug)]
 pub struct CommitInfo {
     pub hash: String,
+    pub revision: Revision,
     pub date: SystemTime,
     pub message: String,
     pub changed_projects: Vec<ProjectID>,
 }
 
+/// A name for a commit, e.g. for a version of a program that's known to work.
+#[derive(Debug)]
+pub struct TagInfo {
+    pub name: String,
+    /// The hash of the tagged commit.
+    pub hash: String,
+    /// When the tag was made.
+    pub date: SystemTime,
+    pub note: String,
+}
+
+/// A summary of a store, for `mm store info`.
+pub struct StoreInfo {
+    /// Total size of the store's files, in bytes.
+    pub size: u64,
+    pub commits: usize,
+    /// Dates of the oldest and newest commits, if there are any.
+    pub first_commit: Option<SystemTime>,
+    pub last_commit: Option<SystemTime>,
+    pub projects: Vec<ProjectID>,
+}
+
 fn store_type(t: &str) -> Result<StoreType, String> {
     match t {
         STORE_TYPE_GIT => Ok(StoreType::Git),
+        STORE_TYPE_ENCRYPTED => Ok(StoreType::Encrypted),
+        STORE_TYPE_SQLITE => Ok(StoreType::Sqlite),
         _ => Err(format!("invalid store type: {t}")),
     }
 }
 
-pub fn create(t: &str, path: PathBuf) -> Result<Store, Box<dyn Error>> {
-    let path = std::path::absolute(path)?;
-    let t = store_type(t)?;
-    let inst = t.create(&path)?;
-    Ok(Store { inst, path })
+pub fn create(st: &StoreConfig) -> Result<Store, Box<dyn Error>> {
+    let path = std::path::absolute(&st.path)?;
+    let t = store_type(&st.store_type)?;
+    let inst = t.create(&path, st)?;
+    Ok(Store {
+        inst: Mutex::new(inst),
+        path,
+    })
 }
 
 pub fn open(st: &StoreConfig) -> Result<Store, Box<dyn Error>> {
     let path = std::path::absolute(&st.path)?;
     let t = store_type(&st.store_type)?;
-    let inst = t.open(&path)?;
-    Ok(Store { inst, path })
+    let inst = t.open(&path, st)?;
+    Ok(Store {
+        inst: Mutex::new(inst),
+        path,
+    })
 }
 
 pub fn open_all(scs: &[StoreConfig]) -> (Vec<(StoreConfig, Store)>, StoreErrors) {
@@ -109,23 +202,49 @@ pub fn all_project_ids(stores: &[(StoreConfig, Store)]) -> (HashSet<ProjectID>,
 }
 
 impl StoreType {
-    fn create<P: AsRef<Path>>(&self, p: P) -> Result<StoreInstance, Box<dyn Error>> {
+    fn create<P: AsRef<Path>>(
+        &self,
+        p: P,
+        st: &StoreConfig,
+    ) -> Result<StoreInstance, Box<dyn Error>> {
         match self {
-            StoreType::Git => Ok(StoreInstance::Git(
-                git::open(&p).or_else(|_| git::create(&p))?,
-            )),
+            StoreType::Git => Ok(StoreInstance::Git(Box::new(
+                git::open(&p, None).or_else(|_| git::create(&p))?,
+            ))),
+            StoreType::Encrypted => Ok(StoreInstance::Encrypted(encrypted::create(
+                p,
+                &encrypted::secret(st)?,
+            )?)),
+            StoreType::Sqlite => Ok(StoreInstance::Sqlite(sqlite::create(p)?)),
         }
     }
 
-    fn open<P: AsRef<Path>>(&self, p: P) -> Result<StoreInstance, Box<dyn Error>> {
+    fn open<P: AsRef<Path>>(
+        &self,
+        p: P,
+        st: &StoreConfig,
+    ) -> Result<StoreInstance, Box<dyn Error>> {
         match self {
-            StoreType::Git => Ok(StoreInstance::Git(git::open(p)?)),
+            StoreType::Git => Ok(StoreInstance::Git(Box::new(git::open(
+                p,
+                st.branch.as_deref(),
+            )?))),
+            StoreType::Encrypted => Ok(StoreInstance::Encrypted(encrypted::open(
+                p,
+                &encrypted::secret(st)?,
+            )?)),
+            StoreType::Sqlite => Ok(StoreInstance::Sqlite(sqlite::open(
+                p,
+                st.branch.as_deref(),
+            )?)),
         }
     }
 
     fn as_str(&self) -> &'static str {
         match self {
             StoreType::Git => STORE_TYPE_GIT,
+            StoreType::Encrypted => STORE_TYPE_ENCRYPTED,
+            StoreType::Sqlite => STORE_TYPE_SQLITE,
         }
     }
 }
@@ -134,18 +253,45 @@ impl StoreInstance {
     fn store_type(&self) -> StoreType {
         match self {
             Self::Git(_) => StoreType::Git,
+            Self::Encrypted(_) => StoreType::Encrypted,
+            Self::Sqlite(_) => StoreType::Sqlite,
+        }
+    }
+
+    fn latest(&self) -> Result<Option<Revision>, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.latest(),
+            Self::Encrypted(s) => s.latest(),
+            Self::Sqlite(s) => s.latest(),
         }
     }
 
     fn project_ids(&self) -> Result<Vec<ProjectID>, Box<dyn Error + 'static>> {
         match self {
             Self::Git(s) => s.project_ids(),
+            Self::Encrypted(s) => s.project_ids(),
+            Self::Sqlite(s) => s.project_ids(),
         }
     }
 
-    fn commit(&self, projects: &[(ProjectID, project::RawProject)], message: &str) -> CommitResult {
+    fn project_ids_at(&self, revision: &Revision) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.project_ids_at(revision),
+            Self::Encrypted(s) => s.project_ids_at(revision),
+            Self::Sqlite(s) => s.project_ids_at(revision),
+        }
+    }
+
+    fn commit(
+        &self,
+        projects: &[(ProjectID, project::RawProject)],
+        message: &str,
+        date: Option<SystemTime>,
+    ) -> CommitResult {
         match self {
-            Self::Git(s) => s.commit(projects, message),
+            Self::Git(s) => s.commit(projects, message, date),
+            Self::Encrypted(s) => s.commit(projects, message, date),
+            Self::Sqlite(s) => s.commit(projects, message, date),
         }
     }
 
@@ -156,43 +302,193 @@ impl StoreInstance {
     ) -> Result<Option<project::RawProject>, Box<dyn Error + 'static>> {
         match self {
             Self::Git(s) => s.read_project(id, revision),
+            Self::Encrypted(s) => s.read_project(id, revision),
+            Self::Sqlite(s) => s.read_project(id, revision),
+        }
+    }
+
+    fn current_branch(&self) -> Result<String, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.current_branch(),
+            Self::Encrypted(_) => Err(encrypted::unsupported("branches")),
+            Self::Sqlite(s) => s.current_branch(),
+        }
+    }
+
+    fn branches(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.branches(),
+            Self::Encrypted(_) => Err(encrypted::unsupported("branches")),
+            Self::Sqlite(s) => s.branches(),
+        }
+    }
+
+    fn create_branch(&self, name: &str, from: Option<&Revision>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.create_branch(name, from),
+            Self::Encrypted(_) => Err(encrypted::unsupported("branches")),
+            Self::Sqlite(s) => s.create_branch(name, from),
+        }
+    }
+
+    fn create_tag(
+        &self,
+        name: &str,
+        revision: &Revision,
+        note: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.create_tag(name, revision, note),
+            Self::Encrypted(_) => Err(encrypted::unsupported("tags")),
+            Self::Sqlite(s) => s.create_tag(name, revision, note),
+        }
+    }
+
+    fn tags(&self) -> Result<Vec<TagInfo>, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.tags(),
+            Self::Encrypted(_) => Err(encrypted::unsupported("tags")),
+            Self::Sqlite(s) => s.tags(),
+        }
+    }
+
+    fn unexpected_entries(&self, revision: &Revision) -> Result<Vec<String>, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.unexpected_entries(revision),
+            Self::Encrypted(s) => s.unexpected_entries(revision),
+            Self::Sqlite(s) => s.unexpected_entries(revision),
         }
     }
 
     fn untrack(&self, id: &ProjectID, message: &str) -> CommitResult {
         match self {
             Self::Git(s) => s.untrack(id, message),
+            Self::Encrypted(s) => s.untrack(id, message),
+            Self::Sqlite(s) => s.untrack(id, message),
         }
     }
 
     fn log(&self, since: SystemTime) -> Result<LogResult, Box<dyn Error + 'static>> {
         match self {
             Self::Git(s) => s.log(since),
+            Self::Encrypted(s) => s.log(since),
+            Self::Sqlite(s) => s.log(since),
+        }
+    }
+
+    /// Returns the commits that changed the project, newest first, or None if the store has no
+    /// quicker way to find them than going through the whole history.
+    fn project_history(&self, id: &ProjectID) -> Result<Option<Vec<CommitInfo>>, Box<dyn Error>> {
+        match self {
+            Self::Git(_) | Self::Encrypted(_) => Ok(None),
+            Self::Sqlite(s) => s.project_history(id).map(Some),
+        }
+    }
+
+    /// Returns the commits dated from `from` up to `to`, newest first, or None if the store has
+    /// no quicker way to find them than going through the whole history.
+    fn commits_between(
+        &self,
+        from: SystemTime,
+        to: Option<SystemTime>,
+    ) -> Result<Option<Vec<CommitInfo>>, Box<dyn Error>> {
+        match self {
+            Self::Git(_) | Self::Encrypted(_) => Ok(None),
+            Self::Sqlite(s) => s.commits_between(from, to).map(Some),
         }
     }
 
     fn resolve(&self, expr: &str) -> Result<Revision, Box<dyn Error>> {
         match self {
             Self::Git(s) => s.resolve(expr),
+            Self::Encrypted(s) => s.resolve(expr),
+            Self::Sqlite(s) => s.resolve(expr),
+        }
+    }
+
+    fn changed_projects(
+        &self,
+        from: &Revision,
+        to: &Revision,
+    ) -> Result<ProjectChanges, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.changed_projects(from, to),
+            Self::Encrypted(s) => s.changed_projects(from, to),
+            Self::Sqlite(s) => s.changed_projects(from, to),
+        }
+    }
+
+    fn adjacent_revisions(
+        &self,
+        revision: &Revision,
+    ) -> Result<(Option<Revision>, Option<Revision>), Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.adjacent_revisions(revision),
+            Self::Encrypted(s) => s.adjacent_revisions(revision),
+            Self::Sqlite(s) => s.adjacent_revisions(revision),
         }
     }
 
     fn store_render(
         &self,
-        rendered: &[(String, Vec<u8>)],
+        rendered: &RenderedTree,
         msg: &str,
         prev_render: Revision,
         source: Revision,
+        branch: Option<&str>,
     ) -> Result<Revision, Box<dyn Error>> {
         match self {
-            Self::Git(s) => s.store_render(rendered, msg, prev_render, source),
+            Self::Git(s) => s.store_render(rendered, msg, prev_render, source, branch),
+            Self::Encrypted(_) => Err(encrypted::unsupported("rendered branches")),
+            Self::Sqlite(s) => s.store_render(rendered, msg, prev_render, source, branch),
+        }
+    }
+
+    fn rendered_branch(&self, branch: &str) -> Result<RenderedBranch, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.rendered_branch(branch),
+            Self::Encrypted(_) => Err(encrypted::unsupported("rendered branches")),
+            Self::Sqlite(s) => s.rendered_branch(branch),
+        }
+    }
+
+    fn compact(&self, opts: &CompactOptions) -> Result<CompactResult, Box<dyn Error>> {
+        match self {
+            Self::Git(s) => s.compact(opts),
+            Self::Encrypted(_) | Self::Sqlite(_) => Err(NOT_COMPACTABLE.into()),
         }
     }
 }
 
 impl Store {
+    fn inst(&self) -> MutexGuard<'_, StoreInstance> {
+        // The instance holds no state that a panic elsewhere could leave half-updated.
+        self.inst.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the newest commit that commits go on top of, or None if there aren't any. Unlike
+    /// Revision::Latest, it stays the same revision after new commits are made.
+    pub fn latest(&self) -> Result<Option<Revision>, Box<dyn Error>> {
+        self.inst().latest()
+    }
+
+    /// Returns true if a revision always names the same projects. An encrypted store's don't: a
+    /// commit synced in from another computer can come before the newest ones and change what
+    /// they have.
+    pub fn has_stable_revisions(&self) -> bool {
+        !matches!(*self.inst(), StoreInstance::Encrypted(_))
+    }
+
     pub fn project_ids(&self) -> Result<Vec<ProjectID>, Box<dyn Error>> {
-        self.inst.project_ids()
+        self.inst().project_ids()
+    }
+
+    pub fn project_ids_at(&self, revision: &Revision) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        self.inst().project_ids_at(revision)
     }
 
     pub fn read_project(
@@ -200,7 +496,7 @@ impl Store {
         id: &ProjectID,
         revision: &Revision,
     ) -> Result<Option<project::RawProject>, Box<dyn Error>> {
-        self.inst.read_project(id, revision)
+        self.inst().read_project(id, revision)
     }
 
     pub(crate) fn commit(
@@ -208,29 +504,159 @@ impl Store {
         projects: &[(ProjectID, project::RawProject)],
         message: &str,
     ) -> CommitResult {
-        self.inst.commit(projects, message)
+        self.inst().commit(projects, message, None)
+    }
+
+    /// Like `commit`, but with the given author date instead of the current time, e.g. for
+    /// importing old versions of projects.
+    pub(crate) fn commit_at(
+        &self,
+        projects: &[(ProjectID, project::RawProject)],
+        message: &str,
+        date: SystemTime,
+    ) -> CommitResult {
+        self.inst().commit(projects, message, Some(date))
+    }
+
+    /// Returns the name of the branch that commits go to.
+    pub fn current_branch(&self) -> Result<String, Box<dyn Error>> {
+        self.inst().current_branch()
+    }
+
+    pub fn branches(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.inst().branches()
+    }
+
+    /// Creates a branch starting at from, or at the latest commit on the current branch.
+    pub fn create_branch(&self, name: &str, from: Option<&Revision>) -> Result<(), Box<dyn Error>> {
+        self.inst().create_branch(name, from)
+    }
+
+    /// Tags the commit at revision with a note, and returns the commit's hash. Tag names can be
+    /// used wherever a revision is accepted.
+    pub fn create_tag(
+        &self,
+        name: &str,
+        revision: &Revision,
+        note: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        self.inst().create_tag(name, revision, note)
+    }
+
+    /// Returns the tags, oldest first.
+    pub fn tags(&self) -> Result<Vec<TagInfo>, Box<dyn Error>> {
+        self.inst().tags()
+    }
+
+    /// Describes anything in the revision that isn't part of a project.
+    pub fn unexpected_entries(&self, revision: &Revision) -> Result<Vec<String>, Box<dyn Error>> {
+        self.inst().unexpected_entries(revision)
     }
 
     pub fn untrack(&self, id: &ProjectID, message: &str) -> CommitResult {
-        self.inst.untrack(id, message)
+        self.inst().untrack(id, message)
     }
 
     pub fn log(&self, since: SystemTime) -> Result<LogResult, Box<dyn Error>> {
-        self.inst.log(since)
+        self.inst().log(since)
+    }
+
+    /// Returns every commit, newest first.
+    pub fn history(&self) -> Result<Vec<CommitInfo>, Box<dyn Error>> {
+        Ok(match self.log(UNIX_EPOCH)? {
+            LogResult::Unborn => Vec::new(),
+            LogResult::None(c) => vec![c],
+            LogResult::Some(commits) => commits,
+        })
+    }
+
+    /// Returns the newest commit, or None if there aren't any.
+    pub fn head(&self) -> Result<Option<CommitInfo>, Box<dyn Error>> {
+        Ok(match self.log(SystemTime::now())? {
+            LogResult::Unborn => None,
+            LogResult::None(c) => Some(c),
+            LogResult::Some(commits) => commits.into_iter().next(),
+        })
+    }
+
+    pub fn info(&self) -> Result<StoreInfo, Box<dyn Error>> {
+        let history = self.history()?;
+        let mut projects = self.project_ids()?;
+        projects.sort_by_key(|id| id.to_string());
+        Ok(StoreInfo {
+            size: dir_size(&self.path)?,
+            commits: history.len(),
+            first_commit: history.last().map(|c| c.date),
+            last_commit: history.first().map(|c| c.date),
+            projects,
+        })
+    }
+
+    /// Returns the commits that changed the project, newest first.
+    pub fn project_history(&self, id: &ProjectID) -> Result<Vec<CommitInfo>, Box<dyn Error>> {
+        if let Some(commits) = self.inst().project_history(id)? {
+            return Ok(commits);
+        }
+        let mut commits = self.history()?;
+        commits.retain(|c| c.changed_projects.contains(id));
+        Ok(commits)
+    }
+
+    /// Returns the commits dated from `from` up to, but not including, `to`, newest first. With
+    /// no `to`, there's no upper bound.
+    pub fn commits_between(
+        &self,
+        from: SystemTime,
+        to: Option<SystemTime>,
+    ) -> Result<Vec<CommitInfo>, Box<dyn Error>> {
+        if let Some(commits) = self.inst().commits_between(from, to)? {
+            return Ok(commits);
+        }
+        let mut commits = self.history()?;
+        commits.retain(|c| c.date >= from && to.is_none_or(|to| c.date < to));
+        Ok(commits)
     }
 
     pub fn resolve(&self, expr: &str) -> Result<Revision, Box<dyn Error>> {
-        self.inst.resolve(expr)
+        self.inst().resolve(expr)
+    }
+
+    pub fn changed_projects(
+        &self,
+        from: &Revision,
+        to: &Revision,
+    ) -> Result<ProjectChanges, Box<dyn Error>> {
+        self.inst().changed_projects(from, to)
+    }
+
+    /// Returns the revisions before and after revision in the store's history, if there are any.
+    pub fn adjacent_revisions(
+        &self,
+        revision: &Revision,
+    ) -> Result<(Option<Revision>, Option<Revision>), Box<dyn Error>> {
+        self.inst().adjacent_revisions(revision)
     }
 
     pub(crate) fn store_render(
         &self,
-        rendered: &[(String, Vec<u8>)],
+        rendered: &RenderedTree,
         msg: &str,
         prev_render: Revision,
         source: Revision,
+        branch: Option<&str>,
     ) -> Result<Revision, Box<dyn Error>> {
-        self.inst.store_render(rendered, msg, prev_render, source)
+        self.inst()
+            .store_render(rendered, msg, prev_render, source, branch)
+    }
+
+    pub fn rendered_branch(&self, branch: &str) -> Result<RenderedBranch, Box<dyn Error>> {
+        self.inst().rendered_branch(branch)
+    }
+
+    /// Squashes history older than opts.older_than into hourly or daily snapshots, and deletes
+    /// what's left unused. The original history is kept under a backup ref.
+    pub fn compact(&self, opts: &CompactOptions) -> Result<CompactResult, Box<dyn Error>> {
+        self.inst().compact(opts)
     }
 }
 
@@ -238,7 +664,11 @@ impl Display for Store {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let c = StoreConfig {
             path: self.path.clone(),
-            store_type: self.inst.store_type().as_str().to_string(),
+            store_type: self.inst().store_type().as_str().to_string(),
+            rendered_branch: None,
+            branch: None,
+            passphrase: None,
+            key_file: None,
         };
         write!(f, "{c}")
     }
@@ -246,16 +676,156 @@ impl Display for Store {
 
 impl From<Store> for StoreConfig {
     fn from(val: Store) -> Self {
+        let store_type = val.inst().store_type().as_str().to_string();
         StoreConfig {
             path: val.path,
-            store_type: val.inst.store_type().as_str().to_string(),
+            store_type,
+            rendered_branch: None,
+            branch: None,
+            passphrase: None,
+            key_file: None,
         }
     }
 }
 
+/// The size of a store's files, or of the file itself for stores that are a single file.
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let meta = std::fs::metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut size = 0;
+    for e in std::fs::read_dir(path)? {
+        let e = e?;
+        let meta = e.metadata()?;
+        size += match meta.is_dir() {
+            true => dir_size(&e.path())?,
+            false => meta.len(),
+        };
+    }
+    Ok(size)
+}
+
+/// Moves a store's files to a new path, which must not exist yet or be inside the store. If the
+/// new path is on another device, the files are copied and then removed.
+pub fn relocate(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    if to.exists() {
+        return Err(format!("{} already exists", to.display()).into());
+    }
+    // Copying a directory into itself would never finish, and removing it afterwards would take
+    // the copy with it.
+    if resolve_new(to)?.starts_with(from.canonicalize()?) {
+        return Err(format!("{} is inside {}", to.display(), from.display()).into());
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_and_remove(from, to)
+}
+
+/// Moves a store's files by copying them, for when they can't be renamed (e.g. to another
+/// device).
+fn copy_and_remove(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    if let Err(e) = copy_dir(from, to) {
+        let _ = remove_all(to);
+        return Err(e.into());
+    }
+    remove_all(from)?;
+    Ok(())
+}
+
+/// Removes a directory and everything in it, or a file, e.g. a sqlite store.
+fn remove_all(path: &Path) -> std::io::Result<()> {
+    match path.is_dir() {
+        true => std::fs::remove_dir_all(path),
+        false => std::fs::remove_file(path),
+    }
+}
+
+/// Resolves a path that doesn't exist yet through its nearest existing ancestor, following
+/// symlinks and .. like canonicalize does.
+fn resolve_new(path: &Path) -> std::io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut rest = Vec::new();
+    let mut base = path.as_path();
+    loop {
+        match base.canonicalize() {
+            Ok(resolved) => break Ok(rest.into_iter().rev().fold(resolved, |p, c| p.join(c))),
+            Err(e) => match (base.parent(), base.file_name()) {
+                (Some(parent), Some(name)) => {
+                    rest.push(name);
+                    base = parent;
+                }
+                _ => break Err(e),
+            },
+        }
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    std::fs::create_dir(to)?;
+    for e in std::fs::read_dir(from)? {
+        let e = e?;
+        let target = to.join(e.file_name());
+        match e.file_type()?.is_dir() {
+            true => copy_dir(&e.path(), &target)?,
+            false => {
+                std::fs::copy(e.path(), target)?;
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Splits a revision expression like HEAD~2 into the revision it starts from and how many
+/// commits to go back from there. A bare ~ goes back one.
+fn split_ancestor(expr: &str) -> Option<(&str, usize)> {
+    match expr.split_once('~') {
+        None => Some((expr, 0)),
+        Some((base, "")) => Some((base, 1)),
+        Some((base, back)) => Some((base, back.parse().ok()?)),
+    }
+}
+
 pub fn paths_match<P1: AsRef<Path>, P2: AsRef<Path>>(p1: P1, p2: P2) -> bool {
     match (std::path::absolute(p1), std::path::absolute(p2)) {
         (Ok(p1), Ok(p2)) => p1 == p2,
         _ => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::test_util::{id, python};
+
+    #[test]
+    fn test_copy_and_remove_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let st = |name: &str| StoreConfig {
+            path: dir.path().join(name),
+            store_type: STORE_TYPE_SQLITE.to_string(),
+            rendered_branch: None,
+            branch: None,
+            passphrase: None,
+            key_file: None,
+        };
+        let (from, to) = (st("old.sqlite"), st("new/moved.sqlite"));
+        let store = create(&from).unwrap();
+        store.commit(&[(id("a"), python("1"))], "first").unwrap();
+        drop(store);
+
+        std::fs::create_dir_all(dir.path().join("new")).unwrap();
+        copy_and_remove(&from.path, &to.path).unwrap();
+        assert!(!from.path.exists());
+        let store = open(&to).unwrap();
+        let raw = store.read_project(&id("a"), &Revision::Latest).unwrap();
+        assert_eq!(raw.unwrap().hash(), python("1").hash());
+    }
+}
@@ -0,0 +1,887 @@
+//! A store for syncing through a folder that others can read, e.g. a shared cloud folder.
+//!
+//! Every version of a project is kept as an encrypted object in objects/, named by an HMAC of its
+//! contents so that the names don't give away which projects are the same. Each commit is
+//! an encrypted file of its own in commits/, with a random name, the projects it changed, and the
+//! commits it came after. Two computers that commit at the same time through a sync client just
+//! add different files, and the next commit on either joins the two. Only the header, with what's
+//! needed to derive the key from the passphrase or key file, can be read without the key.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::StoreConfig;
+use crate::project::{self, ProjectID, RawProject};
+
+use super::{CommitInfo, LogResult, ProjectChanges, Revision};
+
+const HEADER_FILE: &str = "mind-meld-encrypted.toml";
+const COMMITS_DIR: &str = "commits";
+const OBJECTS_DIR: &str = "objects";
+/// Encrypted into the header, to tell a wrong passphrase or key file from a damaged store.
+const KEY_CHECK: &[u8] = b"mind-meld";
+const VERSION: u32 = 1;
+const NONCE_LEN: usize = 24;
+/// Labels for the keys derived from the Argon2 output, so that no key is used for two things.
+const ENCRYPTION_KEY_LABEL: &[u8] = b"mind-meld encryption";
+const NAME_KEY_LABEL: &[u8] = b"mind-meld object names";
+
+/// Kept unencrypted, so that the key can be derived.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    /// Random salt for the key derivation, in hex.
+    salt: String,
+    /// Argon2id memory size in KiB, number of passes, and parallelism.
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    /// KEY_CHECK encrypted with the key, in hex.
+    key_check: String,
+}
+
+/// A commit as it's saved in commits/.
+#[derive(Serialize, Deserialize)]
+struct CommitFile {
+    /// The names of the commits that were the newest when it was made: one, or more if commits
+    /// were made on other computers at the same time. None for the first commit.
+    parents: Vec<String>,
+    /// Seconds since the Unix epoch.
+    date: u64,
+    message: String,
+    /// The projects that changed, as "program/name", with the name of the new version's object,
+    /// or None if the project was untracked.
+    changes: BTreeMap<String, Option<String>>,
+}
+
+/// The history, worked out from the commit files.
+#[derive(Default)]
+struct Index {
+    /// Oldest first, with parents before their children and commits made at the same time on
+    /// different computers ordered by date and name, so that every computer sees the same order.
+    /// A commit that shows up later from another computer can come before the newest ones, so
+    /// commits are identified by name rather than position.
+    commits: Vec<IndexCommit>,
+    /// The commits that no other commit came after, which a new commit comes after.
+    heads: Vec<String>,
+    /// Every project in the newest commit, as "program/name", with the name of its object.
+    latest: BTreeMap<String, String>,
+}
+
+struct IndexCommit {
+    /// The name of the commit's file.
+    name: String,
+    /// Seconds since the Unix epoch.
+    date: u64,
+    message: String,
+    /// The projects that are different from the commit before in the index, like
+    /// CommitFile::changes.
+    changes: BTreeMap<String, Option<String>>,
+}
+
+/// The commit files read so far, so that each is only decrypted once.
+#[derive(Default)]
+struct Loaded {
+    files: HashMap<String, CommitFile>,
+    /// The index of the files, if they're still every commit in commits/.
+    index: Option<Arc<Index>>,
+}
+
+pub struct EncryptedStore {
+    path: PathBuf,
+    cipher: XChaCha20Poly1305,
+    /// For naming objects.
+    name_key: [u8; 32],
+    loaded: Mutex<Loaded>,
+}
+
+/// Returns what the store's key is derived from: the contents of the key file, or the
+/// passphrase.
+pub fn secret(st: &StoreConfig) -> Result<Vec<u8>, Box<dyn Error>> {
+    let secret = match (&st.key_file, &st.passphrase) {
+        (Some(_), Some(_)) => {
+            return Err("an encrypted store needs a key_file or a passphrase, not both".into());
+        }
+        (Some(path), None) => {
+            let mut data = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            // So that a key file written with echo has the same key as the passphrase.
+            while data.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+                data.pop();
+            }
+            data
+        }
+        (None, Some(passphrase)) => passphrase.as_bytes().to_vec(),
+        (None, None) => {
+            return Err("an encrypted store needs a key_file or a passphrase in the config".into());
+        }
+    };
+    match secret.is_empty() {
+        true => Err("the key for an encrypted store can't be empty".into()),
+        false => Ok(secret),
+    }
+}
+
+/// Opens the store at p, checking that the secret is right.
+pub fn open<P: AsRef<Path>>(p: P, secret: &[u8]) -> Result<EncryptedStore, Box<dyn Error>> {
+    let path = p.as_ref();
+    let header_path = path.join(HEADER_FILE);
+    let header =
+        fs::read_to_string(&header_path).map_err(|e| format!("{}: {e}", header_path.display()))?;
+    let header: Header = toml::from_str(&header)?;
+    if header.version != VERSION {
+        return Err(format!("unsupported encrypted store version {}", header.version).into());
+    }
+    let store = EncryptedStore::new(path, &header, secret)?;
+    let key_check = unhex(&header.key_check).ok_or("invalid key check in the store's header")?;
+    if store.decrypt(&key_check, HEADER_FILE.as_bytes())? != KEY_CHECK {
+        return Err("can't decrypt: wrong passphrase or key file".into());
+    }
+    Ok(store)
+}
+
+/// Creates a store at p, which must be empty or not exist yet, or opens it if it's already an
+/// encrypted store.
+pub fn create<P: AsRef<Path>>(p: P, secret: &[u8]) -> Result<EncryptedStore, Box<dyn Error>> {
+    create_with_params(
+        p.as_ref(),
+        secret,
+        Params::DEFAULT_M_COST,
+        Params::DEFAULT_T_COST,
+    )
+}
+
+fn create_with_params(
+    path: &Path,
+    secret: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+) -> Result<EncryptedStore, Box<dyn Error>> {
+    if path.join(HEADER_FILE).exists() {
+        return open(path, secret);
+    }
+    if path.exists() && fs::read_dir(path)?.next().is_some() {
+        return Err(format!("{} isn't empty", path.display()).into());
+    }
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let mut header = Header {
+        version: VERSION,
+        salt: hex(&salt),
+        m_cost,
+        t_cost,
+        p_cost: 1,
+        key_check: String::new(),
+    };
+    fs::create_dir_all(path.join(OBJECTS_DIR))?;
+    fs::create_dir_all(path.join(COMMITS_DIR))?;
+    let store = EncryptedStore::new(path, &header, secret)?;
+    header.key_check = hex(&store.encrypt(KEY_CHECK, HEADER_FILE.as_bytes())?);
+    // Written last, so that a partly created store can't be opened.
+    fs::write(path.join(HEADER_FILE), toml::to_string_pretty(&header)?)?;
+    Ok(store)
+}
+
+impl EncryptedStore {
+    fn new(path: &Path, header: &Header, secret: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let salt = unhex(&header.salt).ok_or("invalid salt in the store's header")?;
+        let params = Params::new(header.m_cost, header.t_cost, header.p_cost, Some(32))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(secret, &salt, &mut key)?;
+        let encryption_key = hmac(&key, ENCRYPTION_KEY_LABEL);
+        Ok(Self {
+            path: path.to_path_buf(),
+            cipher: XChaCha20Poly1305::new(&encryption_key.into()),
+            name_key: hmac(&key, NAME_KEY_LABEL),
+            loaded: Mutex::default(),
+        })
+    }
+
+    /// Encrypts data with a random nonce, which is put in front. The associated data ties the
+    /// result to the file it's written to, so that files can't be swapped around.
+    fn encrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .map_err(|_| "encryption failed")?;
+        let mut res = nonce.to_vec();
+        res.extend(ciphertext);
+        Ok(res)
+    }
+
+    fn decrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if data.len() < NONCE_LEN {
+            return Err("encrypted file is too short".into());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| {
+                "can't decrypt: wrong passphrase or key file, or the file is damaged".into()
+            })
+    }
+
+    /// Reads the commits that weren't read before and puts them all in order. The index is only
+    /// worked out again when commits/ has changed, e.g. after a commit or a sync.
+    fn load_index(&self) -> Result<Arc<Index>, Box<dyn Error>> {
+        let mut names = BTreeSet::new();
+        for e in fs::read_dir(self.path.join(COMMITS_DIR))? {
+            let name = e?.file_name().to_string_lossy().to_string();
+            // Skips temporary files and the copies sync clients make of conflicting files.
+            if is_commit_name(&name) {
+                names.insert(name);
+            }
+        }
+
+        let mut loaded = self.loaded();
+        let unchanged =
+            loaded.files.len() == names.len() && names.iter().all(|n| loaded.files.contains_key(n));
+        if let Some(index) = &loaded.index
+            && unchanged
+        {
+            return Ok(index.clone());
+        }
+        loaded.files.retain(|name, _| names.contains(name));
+        for name in names {
+            if loaded.files.contains_key(&name) {
+                continue;
+            }
+            let data = fs::read(self.path.join(COMMITS_DIR).join(&name))?;
+            let data = self.decrypt(&data, name.as_bytes())?;
+            let commit: CommitFile =
+                serde_json::from_slice(&data).map_err(|e| format!("commit {name}: {e}"))?;
+            loaded.files.insert(name, commit);
+        }
+        let index = Arc::new(build_index(&loaded.files)?);
+        loaded.index = Some(index.clone());
+        Ok(index)
+    }
+
+    fn loaded(&self) -> MutexGuard<'_, Loaded> {
+        // Files are only added once they've been read in full, and the index once it's built.
+        self.loaded.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Saves a commit that comes after the index's heads, with the projects that changed since
+    /// the newest commit.
+    fn write_commit(
+        &self,
+        index: &Index,
+        message: &str,
+        date: Option<SystemTime>,
+        changes: BTreeMap<String, Option<String>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let date = date.unwrap_or_else(SystemTime::now);
+        let commit = CommitFile {
+            parents: index.heads.clone(),
+            date: date.duration_since(UNIX_EPOCH)?.as_secs(),
+            message: message.to_string(),
+            changes,
+        };
+        let mut name = [0u8; 16];
+        OsRng.fill_bytes(&mut name);
+        let name = hex(&name);
+        let data = self.encrypt(&serde_json::to_vec(&commit)?, name.as_bytes())?;
+        write_file(&self.path.join(COMMITS_DIR).join(name), &data)
+    }
+
+    fn object_name(&self, raw: &RawProject) -> String {
+        hex(&hmac(&self.name_key, &raw.hash()))
+    }
+
+    /// Saves the project, unless there's already an object with the same contents, and returns
+    /// the object's name.
+    fn write_object(&self, raw: &RawProject) -> Result<String, Box<dyn Error>> {
+        let name = self.object_name(raw);
+        let path = self.path.join(OBJECTS_DIR).join(&name);
+        if !path.exists() {
+            let mut data = Cursor::new(Vec::new());
+            raw.write(&mut data)?;
+            write_file(&path, &self.encrypt(data.get_ref(), name.as_bytes())?)?;
+        }
+        Ok(name)
+    }
+
+    fn read_object(&self, name: &str) -> Result<RawProject, Box<dyn Error>> {
+        let data = fs::read(self.path.join(OBJECTS_DIR).join(name))
+            .map_err(|e| format!("object {name}: {e}"))?;
+        project::read_bytes(self.decrypt(&data, name.as_bytes())?)
+    }
+
+    pub fn project_ids(&self) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        self.project_ids_at(&Revision::Latest)
+    }
+
+    pub(crate) fn project_ids_at(
+        &self,
+        revision: &Revision,
+    ) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        let index = self.load_index()?;
+        let projects = projects_at(&index, revision)?;
+        projects.keys().map(|k| parse_key(k)).collect()
+    }
+
+    pub(crate) fn read_project(
+        &self,
+        id: &ProjectID,
+        revision: &Revision,
+    ) -> Result<Option<RawProject>, Box<dyn Error>> {
+        let index = self.load_index()?;
+        let projects = projects_at(&index, revision)?;
+        let object = projects.get(&key(id));
+        object.map(|name| self.read_object(name)).transpose()
+    }
+
+    pub(crate) fn commit(
+        &self,
+        projects: &[(ProjectID, RawProject)],
+        message: &str,
+        date: Option<SystemTime>,
+    ) -> Result<&'static str, Box<dyn Error>> {
+        let index = self.load_index()?;
+        let mut changes = BTreeMap::new();
+        for (id, raw) in projects {
+            let object = self.write_object(raw)?;
+            if index.latest.get(&key(id)) != Some(&object) {
+                changes.insert(key(id), Some(object));
+            }
+        }
+        if changes.is_empty() {
+            return Ok("already up to date");
+        }
+        self.write_commit(&index, message, date, changes)?;
+        Ok("added")
+    }
+
+    pub(crate) fn untrack(
+        &self,
+        id: &ProjectID,
+        message: &str,
+    ) -> Result<&'static str, Box<dyn Error>> {
+        let index = self.load_index()?;
+        if !index.latest.contains_key(&key(id)) {
+            return Ok("not tracked");
+        }
+        let changes = BTreeMap::from([(key(id), None)]);
+        self.write_commit(&index, message, None, changes)?;
+        Ok("removed")
+    }
+
+    pub fn log(&self, since: SystemTime) -> Result<LogResult, Box<dyn Error>> {
+        let index = self.load_index()?;
+        let Some(newest) = index.commits.len().checked_sub(1) else {
+            return Ok(LogResult::Unborn);
+        };
+        // Imported and queued commits keep their original dates, so the history isn't in date
+        // order and every commit has to be looked at.
+        let mut res = Vec::new();
+        for i in (0..=newest).rev() {
+            let info = commit_info(&index, i)?;
+            if info.date >= since {
+                res.push(info);
+            }
+        }
+        match res.is_empty() {
+            true => Ok(LogResult::None(commit_info(&index, newest)?)),
+            false => Ok(LogResult::Some(res)),
+        }
+    }
+
+    pub(crate) fn latest(&self) -> Result<Option<Revision>, Box<dyn Error>> {
+        let index = self.load_index()?;
+        Ok(index
+            .commits
+            .last()
+            .map(|c| Revision::Encrypted(c.name.clone())))
+    }
+
+    /// Accepts the start of a commit's name (at least 4 characters, like log shows), HEAD for
+    /// the newest commit, and either followed by ~n to go back n commits.
+    pub(crate) fn resolve(&self, expr: &str) -> Result<Revision, Box<dyn Error>> {
+        let index = self.load_index()?;
+        let unknown = || format!("unknown revision {expr}");
+        let (base, back) = super::split_ancestor(expr).ok_or_else(unknown)?;
+        let i = match base {
+            "HEAD" => index.commits.len().checked_sub(1),
+            base if base.len() >= 4 => {
+                let base = base.to_ascii_lowercase();
+                let mut matches =
+                    (0..index.commits.len()).filter(|i| index.commits[*i].name.starts_with(&base));
+                match (matches.next(), matches.next()) {
+                    (Some(_), Some(_)) => return Err(format!("ambiguous revision {expr}").into()),
+                    (i, _) => i,
+                }
+            }
+            _ => None,
+        };
+        match i.and_then(|i| i.checked_sub(back)) {
+            Some(i) => Ok(Revision::Encrypted(index.commits[i].name.clone())),
+            None => Err(unknown().into()),
+        }
+    }
+
+    pub(crate) fn changed_projects(
+        &self,
+        from: &Revision,
+        to: &Revision,
+    ) -> Result<ProjectChanges, Box<dyn Error>> {
+        let index = self.load_index()?;
+        let (old, new) = (projects_at(&index, from)?, projects_at(&index, to)?);
+        diff(&old, &new)
+    }
+
+    pub(crate) fn adjacent_revisions(
+        &self,
+        revision: &Revision,
+    ) -> Result<(Option<Revision>, Option<Revision>), Box<dyn Error>> {
+        let index = self.load_index()?;
+        let Some(i) = position(&index, revision)? else {
+            return Ok((None, None));
+        };
+        let revision = |i: usize| Revision::Encrypted(index.commits[i].name.clone());
+        let prev = i.checked_sub(1).map(revision);
+        let next = (i + 1 < index.commits.len()).then(|| revision(i + 1));
+        Ok((prev, next))
+    }
+
+    /// Describes files in the store's directory that aren't part of it, e.g. conflicting copies
+    /// made by a sync client. Revisions don't have entries of their own, so the revision isn't
+    /// used.
+    pub(crate) fn unexpected_entries(
+        &self,
+        _revision: &Revision,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut res = Vec::new();
+        for e in fs::read_dir(&self.path)? {
+            let name = e?.file_name().to_string_lossy().to_string();
+            if ![HEADER_FILE, COMMITS_DIR, OBJECTS_DIR].contains(&name.as_str()) {
+                res.push(format!("unexpected entry {name}"));
+            }
+        }
+        for dir in [COMMITS_DIR, OBJECTS_DIR] {
+            for e in fs::read_dir(self.path.join(dir))? {
+                let name = e?.file_name().to_string_lossy().to_string();
+                let valid = match dir {
+                    COMMITS_DIR => is_commit_name(&name),
+                    _ => is_object_name(&name),
+                };
+                if !valid {
+                    res.push(format!("unexpected entry {dir}/{name}"));
+                }
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+}
+
+/// For operations that only git stores have.
+pub(crate) fn unsupported(what: &str) -> Box<dyn Error> {
+    format!("{what} aren't supported by encrypted stores").into()
+}
+
+/// Returns the position of revision's commit in the index, or None if it has none (e.g. it's
+/// empty or the store has no commits yet).
+fn position(index: &Index, revision: &Revision) -> Result<Option<usize>, Box<dyn Error>> {
+    match revision {
+        Revision::Empty => Ok(None),
+        Revision::Latest => Ok(index.commits.len().checked_sub(1)),
+        Revision::Encrypted(name) => match index.commits.iter().position(|c| c.name == *name) {
+            Some(i) => Ok(Some(i)),
+            None => Err(format!("there's no commit {name}").into()),
+        },
+        Revision::Git(_) | Revision::Sqlite(_) => {
+            Err("revisions of other store types can't be used with an encrypted store".into())
+        }
+    }
+}
+
+/// Returns the projects in revision's commit, as "program/name" with the name of each one's
+/// object.
+fn projects_at<'a>(
+    index: &'a Index,
+    revision: &Revision,
+) -> Result<Cow<'a, BTreeMap<String, String>>, Box<dyn Error>> {
+    let Some(i) = position(index, revision)? else {
+        return Ok(Cow::Owned(BTreeMap::new()));
+    };
+    if i + 1 == index.commits.len() {
+        return Ok(Cow::Borrowed(&index.latest));
+    }
+    let mut projects = BTreeMap::new();
+    for commit in &index.commits[..=i] {
+        apply(&mut projects, &commit.changes);
+    }
+    Ok(Cow::Owned(projects))
+}
+
+/// Makes changes to projects, and returns the ones that made a difference.
+fn apply(
+    projects: &mut BTreeMap<String, String>,
+    changes: &BTreeMap<String, Option<String>>,
+) -> BTreeMap<String, Option<String>> {
+    let mut res = BTreeMap::new();
+    for (k, object) in changes {
+        let changed = match object {
+            Some(object) => projects.insert(k.clone(), object.clone()).as_ref() != Some(object),
+            None => projects.remove(k).is_some(),
+        };
+        if changed {
+            res.insert(k.clone(), object.clone());
+        }
+    }
+    res
+}
+
+/// Orders the commits so that each comes after its parents. A parent that hasn't been synced yet
+/// is left out.
+fn build_index(files: &HashMap<String, CommitFile>) -> Result<Index, Box<dyn Error>> {
+    let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut waiting: HashMap<&str, usize> = HashMap::new();
+    let mut ready = BTreeSet::new();
+    for (name, commit) in files {
+        let parents = commit.parents.iter().filter(|p| files.contains_key(*p));
+        let parents: Vec<&str> = parents.map(String::as_str).collect();
+        for parent in &parents {
+            children.entry(parent).or_default().push(name);
+        }
+        match parents.len() {
+            0 => {
+                ready.insert((commit.date, name.as_str()));
+            }
+            n => {
+                waiting.insert(name, n);
+            }
+        }
+    }
+    let mut index = Index::default();
+    while let Some((date, name)) = ready.pop_first() {
+        let commit = &files[name];
+        index.commits.push(IndexCommit {
+            name: name.to_string(),
+            date,
+            message: commit.message.clone(),
+            changes: apply(&mut index.latest, &commit.changes),
+        });
+        let children = children.get(name).map_or(&[][..], Vec::as_slice);
+        if children.is_empty() {
+            index.heads.push(name.to_string());
+        }
+        for child in children {
+            let n = waiting.get_mut(child).ok_or("invalid commit history")?;
+            *n -= 1;
+            if *n == 0 {
+                ready.insert((files[*child].date, child));
+            }
+        }
+    }
+    if index.commits.len() != files.len() {
+        return Err("the commits' parents go around in a circle".into());
+    }
+    Ok(index)
+}
+
+/// Commits are named by 16 random bytes in hex.
+fn is_commit_name(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Objects are named by an HMAC-SHA256 in hex.
+fn is_object_name(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn commit_info(index: &Index, i: usize) -> Result<CommitInfo, Box<dyn Error>> {
+    let commit = &index.commits[i];
+    let (changed, removed): (Vec<_>, Vec<_>) =
+        commit.changes.iter().partition(|(_, o)| o.is_some());
+    let changed_projects = changed
+        .into_iter()
+        .chain(removed)
+        .map(|(k, _)| parse_key(k))
+        .collect::<Result<_, _>>()?;
+    Ok(CommitInfo {
+        hash: commit.name[..8].to_string(),
+        revision: Revision::Encrypted(commit.name.clone()),
+        date: UNIX_EPOCH + Duration::from_secs(commit.date),
+        // Like git, only the first line.
+        message: commit
+            .message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        changed_projects,
+    })
+}
+
+/// Compares the projects in two commits.
+fn diff(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Result<ProjectChanges, Box<dyn Error>> {
+    let mut changes = ProjectChanges::default();
+    for (k, object) in new {
+        if old.get(k) != Some(object) {
+            changes.changed.push(parse_key(k)?);
+        }
+    }
+    for k in old.keys() {
+        if !new.contains_key(k) {
+            changes.removed.push(parse_key(k)?);
+        }
+    }
+    Ok(changes)
+}
+
+fn key(id: &ProjectID) -> String {
+    format!("{}/{}", id.program, id.name)
+}
+
+fn parse_key(k: &str) -> Result<ProjectID, Box<dyn Error>> {
+    let (program, name) = k.split_once('/').ok_or("invalid project in index")?;
+    Ok(ProjectID {
+        program: program.parse()?,
+        name: name.to_string(),
+    })
+}
+
+/// Returns the HMAC-SHA256 of data.
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Writes to a temporary file first, so that a sync client never sees half a file.
+fn write_file(path: &Path, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::test_util::{id, python};
+
+    /// Cheap key derivation parameters, since the defaults are slow in debug builds.
+    fn create(path: &Path, secret: &[u8]) -> EncryptedStore {
+        create_with_params(path, secret, 64, 1).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = create(dir.path(), b"secret");
+        assert!(matches!(store.log(UNIX_EPOCH).unwrap(), LogResult::Unborn));
+
+        let date = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let commit = |projects: &[(ProjectID, RawProject)], msg| store.commit(projects, msg, None);
+        assert_eq!(
+            store
+                .commit(
+                    &[(id("a"), python("1")), (id("b"), python("1"))],
+                    "first",
+                    Some(date)
+                )
+                .unwrap(),
+            "added"
+        );
+        assert_eq!(
+            commit(&[(id("a"), python("1"))], "same").unwrap(),
+            "already up to date"
+        );
+        assert_eq!(
+            commit(&[(id("a"), python("2"))], "second").unwrap(),
+            "added"
+        );
+        assert_eq!(store.untrack(&id("b"), "third").unwrap(), "removed");
+        assert_eq!(store.untrack(&id("b"), "again").unwrap(), "not tracked");
+
+        // One object per distinct version.
+        let objects = fs::read_dir(dir.path().join(OBJECTS_DIR)).unwrap().count();
+        assert_eq!(objects, 2);
+
+        let store = open(dir.path(), b"secret").unwrap();
+        assert_eq!(store.project_ids().unwrap(), [id("a")]);
+        let index = store.load_index().unwrap();
+        // The index is only worked out again once there are new commits.
+        assert!(Arc::ptr_eq(&index, &store.load_index().unwrap()));
+        let first = index.commits[0].name.clone();
+        let read = |id, revision| {
+            let raw: RawProject = store.read_project(&id, &revision).unwrap().unwrap();
+            raw.hash()
+        };
+        assert_eq!(read(id("a"), Revision::Latest), python("2").hash());
+        let first_revision = Revision::Encrypted(first.clone());
+        assert_eq!(read(id("a"), first_revision.clone()), python("1").hash());
+        assert!(store
+            .read_project(&id("b"), &Revision::Latest)
+            .unwrap()
+            .is_none());
+
+        let LogResult::Some(log) = store.log(UNIX_EPOCH).unwrap() else {
+            panic!("expected commits");
+        };
+        let messages: Vec<&str> = log.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["third", "second", "first"]);
+        assert_eq!(log[0].changed_projects, [id("b")]);
+        assert_eq!(log[1].changed_projects, [id("a")]);
+        assert_eq!(log[2].date, date);
+
+        assert_eq!(log[2].hash, first[..8]);
+        assert!(matches!(
+            store.resolve("HEAD~2").unwrap(),
+            Revision::Encrypted(name) if name == first
+        ));
+        assert!(matches!(
+            store.resolve(&format!("{}~0", &first[..8])).unwrap(),
+            Revision::Encrypted(name) if name == first
+        ));
+        assert!(store.resolve("HEAD~3").is_err());
+        assert!(store.resolve("1").is_err());
+        let changes = store
+            .changed_projects(&first_revision, &Revision::Latest)
+            .unwrap();
+        assert_eq!(changes.changed, [id("a")]);
+        assert_eq!(changes.removed, [id("b")]);
+        assert!(store
+            .unexpected_entries(&Revision::Latest)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_wrong_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = create(dir.path(), b"secret");
+        store
+            .commit(&[(id("a"), python("1"))], "first", None)
+            .unwrap();
+        assert!(open(dir.path(), b"wrong").is_err());
+
+        // Nothing in the store gives away the project's contents.
+        for e in walk(dir.path()) {
+            let data = fs::read(e).unwrap();
+            assert!(!data.windows(4).any(|w| w == b"main"));
+        }
+    }
+
+    /// Copies the files that dir has and to doesn't, like a sync client would.
+    fn sync(from: &Path, to: &Path) {
+        for path in walk(from) {
+            let dest = to.join(path.strip_prefix(from).unwrap());
+            if !dest.exists() {
+                fs::copy(&path, dest).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let (one, two) = (dir.path().join("one"), dir.path().join("two"));
+        let store_one = create(&one, b"secret");
+        store_one
+            .commit(&[(id("a"), python("1"))], "first", None)
+            .unwrap();
+        fs::create_dir_all(two.join(OBJECTS_DIR)).unwrap();
+        fs::create_dir_all(two.join(COMMITS_DIR)).unwrap();
+        sync(&one, &two);
+        let store_two = open(&two, b"secret").unwrap();
+
+        // Both computers commit before they've synced each other's commits. The commit from the
+        // second is dated earlier, so it goes before the first's once they're synced.
+        store_one
+            .commit(&[(id("a"), python("2"))], "one", None)
+            .unwrap();
+        let revision = store_one.latest().unwrap().unwrap();
+        let earlier = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        store_two
+            .commit(&[(id("b"), python("1"))], "two", Some(earlier))
+            .unwrap();
+        sync(&one, &two);
+        sync(&two, &one);
+
+        // The revision still refers to the same commit.
+        let raw = store_one.read_project(&id("a"), &revision).unwrap();
+        assert_eq!(raw.unwrap().hash(), python("2").hash());
+        let index = store_one.load_index().unwrap();
+        assert_eq!(index.commits[1].message, "two");
+
+        for store in [&store_one, &store_two] {
+            let mut ids = store.project_ids().unwrap();
+            ids.sort_by_key(|id| id.to_string());
+            assert_eq!(ids, [id("a"), id("b")]);
+            assert_eq!(store.load_index().unwrap().commits.len(), 3);
+            assert_eq!(store.load_index().unwrap().heads.len(), 2);
+        }
+        let messages = |store: &EncryptedStore| -> Vec<String> {
+            let index = store.load_index().unwrap();
+            index.commits.iter().map(|c| c.message.clone()).collect()
+        };
+        assert_eq!(messages(&store_one), messages(&store_two));
+
+        // The next commit joins them.
+        store_one.untrack(&id("a"), "untrack").unwrap();
+        let index = store_one.load_index().unwrap();
+        assert_eq!(index.heads.len(), 1);
+        assert_eq!(index.commits.len(), 4);
+        assert_eq!(store_one.project_ids().unwrap(), [id("b")]);
+        assert!(store_one
+            .unexpected_entries(&Revision::Latest)
+            .unwrap()
+            .is_empty());
+
+        fs::write(one.join(COMMITS_DIR).join("a (conflicted copy)"), "").unwrap();
+        assert_eq!(
+            store_one.unexpected_entries(&Revision::Latest).unwrap(),
+            ["unexpected entry commits/a (conflicted copy)"]
+        );
+    }
+
+    fn walk(dir: &Path) -> Vec<PathBuf> {
+        let mut res = Vec::new();
+        for e in fs::read_dir(dir).unwrap() {
+            let path = e.unwrap().path();
+            match path.is_dir() {
+                true => res.extend(walk(&path)),
+                false => res.push(path),
+            }
+        }
+        res
+    }
+}
@@ -1,31 +1,50 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fs;
+use std::ops::Range;
 use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use gix::object::tree;
 use gix::objs::tree::EntryKind;
-use gix::refs::FullName;
+use gix::objs::Kind;
+use gix::refs::transaction::PreviousValue;
+use gix::refs::{FullName, Target};
 use gix::revision::walk::Sorting;
 use gix::{Commit, Id, ObjectId, Tree};
 
+use crate::compact::{self, CompactOptions, CompactResult, Squash};
 use crate::project::*;
 
 use super::Revision;
 
-pub fn open<P: AsRef<Path>>(p: P) -> Result<GitStore, Box<dyn Error>> {
+const NOT_A_GIT_REVISION: &str = "revisions of other store types can't be used with a git store";
+
+/// Where compact keeps the original history, under the time it ran and the name of the ref.
+const BACKUPS: &str = "refs/mind-meld/backups/";
+
+/// Unreachable objects younger than this aren't pruned.
+const PRUNE_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Tags by the commit they point at, as their name and the id the ref points at.
+type Tags = HashMap<ObjectId, Vec<(FullName, ObjectId)>>;
+
+/// Opens the repository at p. Commits go to branch if it's given, or else to the branch that
+/// HEAD points at.
+pub fn open<P: AsRef<Path>>(p: P, branch: Option<&str>) -> Result<GitStore, Box<dyn Error>> {
     let r = gix::discover(&p)?;
-    GitStore::new(r)
+    GitStore::new(r, branch)
 }
 
 pub fn create<P: AsRef<Path>>(p: P) -> Result<GitStore, Box<dyn Error>> {
     let r = gix::init_bare(p)?;
-    GitStore::new(r)
+    GitStore::new(r, None)
 }
 
 pub struct GitStore {
     r: gix::Repository,
+    branch: Option<String>,
 }
 
 struct VersionedProjectID<'a> {
@@ -34,22 +53,118 @@ struct VersionedProjectID<'a> {
 }
 
 impl GitStore {
-    fn new(r: gix::Repository) -> Result<Self, Box<dyn Error>> {
+    fn new(r: gix::Repository, branch: Option<&str>) -> Result<Self, Box<dyn Error>> {
         validate(&r)?;
-        Ok(Self { r })
+        let branch = branch.map(str::to_string);
+        Ok(Self { r, branch })
     }
 
     fn clone_with_cache(&self) -> Self {
         let mut r = self.r.clone();
         r.object_cache_size(Some(1024 * 1024));
-        Self { r }
+        Self {
+            r,
+            branch: self.branch.clone(),
+        }
+    }
+
+    /// The ref that commits go to.
+    fn target_ref(&self) -> Result<FullName, Box<dyn Error>> {
+        match &self.branch {
+            Some(branch) => Ok(branch_ref(branch).try_into()?),
+            None => {
+                let head = self.r.head()?;
+                Ok(head.referent_name().ok_or("invalid head ref")?.to_owned())
+            }
+        }
+    }
+
+    /// Returns the newest commit on the branch that commits go to, or None if it doesn't have
+    /// any yet.
+    fn tip(&self) -> Result<Option<Commit<'_>>, Box<dyn Error>> {
+        match &self.branch {
+            Some(branch) => match self.r.try_find_reference(&branch_ref(branch))? {
+                None => Ok(None),
+                Some(mut r) => Ok(Some(r.peel_to_commit()?)),
+            },
+            None => match self.r.head()?.is_unborn() {
+                true => Ok(None),
+                false => Ok(Some(self.r.head_commit()?)),
+            },
+        }
+    }
+
+    pub(crate) fn latest(&self) -> Result<Option<Revision>, Box<dyn Error>> {
+        Ok(self.tip()?.map(|c| Revision::Git(c.id)))
     }
 
     pub fn project_ids(&self) -> Result<Vec<ProjectID>, Box<dyn Error + 'static>> {
-        if self.r.head()?.is_unborn() {
-            return Ok(Vec::new());
+        match self.tip()? {
+            None => Ok(Vec::new()),
+            Some(commit) => self.project_ids_from_commit(&commit),
+        }
+    }
+
+    /// Returns the name of the branch that commits go to.
+    pub(crate) fn current_branch(&self) -> Result<String, Box<dyn Error>> {
+        let target = self.target_ref()?;
+        Ok(target.shorten().to_string())
+    }
+
+    /// Returns the names of the branches, sorted.
+    pub(crate) fn branches(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut res = Vec::new();
+        for r in self.r.references()?.local_branches()? {
+            let r = r.map_err(|e| e as Box<dyn Error>)?;
+            res.push(r.name().shorten().to_string());
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    /// Creates a branch starting at from, or at the newest commit on the current branch.
+    pub(crate) fn create_branch(
+        &self,
+        name: &str,
+        from: Option<&Revision>,
+    ) -> Result<(), Box<dyn Error>> {
+        let start = match from {
+            Some(revision) => self.find_commit(revision)?,
+            None => self.tip()?,
+        };
+        let start = start.ok_or("there are no commits to start the branch from yet")?;
+        let full_name: FullName = branch_ref(name).try_into()?;
+        if self.r.try_find_reference(&full_name)?.is_some() {
+            return Err(format!("branch {name} already exists").into());
+        }
+        self.r.reference(
+            full_name,
+            start.id,
+            gix::refs::transaction::PreviousValue::MustNotExist,
+            format!("branch: Created from {}", start.id),
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn project_ids_at(
+        &self,
+        revision: &Revision,
+    ) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        match self.find_commit(revision)? {
+            None => Ok(Vec::new()),
+            Some(commit) => self.project_ids_from_commit(&commit),
+        }
+    }
+
+    /// Returns the commit for revision, or None if the revision has no commit (e.g. it's empty or
+    /// the repository is unborn).
+    fn find_commit(&self, revision: &Revision) -> Result<Option<Commit<'_>>, Box<dyn Error>> {
+        match revision {
+            Revision::Empty => Ok(None),
+            Revision::Latest => self.tip(),
+            Revision::Git(oid) => Ok(Some(self.r.find_commit(*oid)?)),
+            Revision::Encrypted(_) | Revision::Sqlite(_) => Err(NOT_A_GIT_REVISION.into()),
         }
-        self.project_ids_from_commit(&self.r.head_commit()?)
     }
 
     fn project_ids_from_commit(&self, commit: &Commit) -> Result<Vec<ProjectID>, Box<dyn Error>> {
@@ -94,13 +209,15 @@ impl GitStore {
     ) -> Result<Option<RawProject>, Box<dyn Error>> {
         let commit = match revision {
             Revision::Empty => return Ok(None),
-            Revision::Latest => match self.r.head_commit() {
-                Ok(c) => c,
+            Revision::Latest => match self.tip() {
+                Ok(Some(c)) => c,
                 // If there is no head commit, behave as if the project simply wasn't found.
-                Err(_) => return Ok(None),
+                Ok(None) | Err(_) => return Ok(None),
             },
             Revision::Git(oid) => self.r.find_commit(*oid)?,
-            // _ => unreachable!(),
+            Revision::Encrypted(_) | Revision::Sqlite(_) => {
+                return Err(NOT_A_GIT_REVISION.into());
+            }
         };
 
         match commit.tree()?.lookup_entry_by_path(Self::path_for(id))? {
@@ -111,6 +228,25 @@ impl GitStore {
         }
     }
 
+    /// Describes top-level entries in the revision's tree other than the program directories.
+    /// Unlike the check when opening the store, this includes files.
+    pub(crate) fn unexpected_entries(
+        &self,
+        revision: &Revision,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let Some(commit) = self.find_commit(revision)? else {
+            return Ok(Vec::new());
+        };
+        let mut res = Vec::new();
+        for e in commit.tree()?.iter() {
+            let e = e?;
+            if !e.mode().is_tree() || program_git(e.filename()).is_err() {
+                res.push(format!("unexpected entry {}", e.filename()));
+            }
+        }
+        Ok(res)
+    }
+
     fn tree_to_archive(&self, tree: Tree) -> Result<RawArchive, Box<dyn Error>> {
         let entries = tree
             .iter()
@@ -138,18 +274,14 @@ impl GitStore {
         &self,
         projects: &[(ProjectID, RawProject)],
         commit_message: &str,
+        date: Option<SystemTime>,
     ) -> Result<&'static str, Box<dyn Error>> {
-        let head = self.r.head()?;
-        let head_ref = head.referent_name().ok_or("invalid head ref")?;
+        let head_ref = self.target_ref()?;
 
         // Get the current tree (or empty tree if unborn)
-        let (current_tree, parent_commit_ids) = if head.is_unborn() {
-            (self.r.empty_tree(), Vec::new())
-        } else {
-            (
-                self.r.head_commit()?.tree()?,
-                vec![self.r.head_commit()?.id],
-            )
+        let (current_tree, parent_commit_ids) = match self.tip()? {
+            None => (self.r.empty_tree(), Vec::new()),
+            Some(commit) => (commit.tree()?, vec![commit.id]),
         };
 
         // Create a new tree with the project changes
@@ -162,12 +294,33 @@ impl GitStore {
 
         // Create the commit
         if current_tree.id != new_root_tree_id {
-            self.r.commit(
-                head_ref,
-                commit_message,
-                new_root_tree_id,
-                parent_commit_ids,
-            )?;
+            match date {
+                None => {
+                    self.r.commit(
+                        head_ref,
+                        commit_message,
+                        new_root_tree_id,
+                        parent_commit_ids,
+                    )?;
+                }
+                Some(date) => {
+                    let committer = self.r.committer().ok_or("no committer configured")??;
+                    let author = self.r.author().ok_or("no author configured")??;
+                    let time = format!("{} +0000", date.duration_since(UNIX_EPOCH)?.as_secs());
+                    let author = gix::actor::SignatureRef {
+                        time: &time,
+                        ..author
+                    };
+                    self.r.commit_as(
+                        committer,
+                        author,
+                        head_ref,
+                        commit_message,
+                        new_root_tree_id,
+                        parent_commit_ids,
+                    )?;
+                }
+            };
             Ok("added")
         } else {
             Ok("already up to date")
@@ -176,36 +329,38 @@ impl GitStore {
 
     pub fn log(&self, since: SystemTime) -> Result<super::LogResult, Box<dyn Error>> {
         use super::LogResult;
-        if self.r.head()?.is_unborn() {
+        let Some(head_commit) = self.tip()? else {
             return Ok(LogResult::Unborn);
-        }
-
-        let head_commit = self.r.head_commit()?;
-        let head_commit_info = self.commit_info(&head_commit)?;
-        if head_commit_info.date < since {
-            return Ok(LogResult::None(head_commit_info));
-        }
+        };
 
         let with_cache = self.clone_with_cache();
 
         let revwalk = with_cache
             .r
-            .rev_walk(Some(with_cache.r.head_commit()?.id()))
+            .rev_walk(Some(head_commit.id))
             .sorting(Sorting::ByCommitTime(Default::default()));
         let commit_infos = revwalk.all()?;
 
+        // Imported and queued commits keep their original author dates but are committed when
+        // they're made, so the walk stops on commit time and the commits are picked by author date.
+        let since_secs = since.duration_since(UNIX_EPOCH)?.as_secs() as i64;
         let mut res = Vec::new();
         for info in commit_infos {
             let info = info?;
             let commit = info.object()?;
-            let commit_info = with_cache.commit_info(&commit)?;
-            if commit_info.date < since {
+            if commit.committer()?.time()?.seconds < since_secs {
                 break;
             }
-            res.push(commit_info);
+            let commit_info = with_cache.commit_info(&commit)?;
+            if commit_info.date >= since {
+                res.push(commit_info);
+            }
         }
 
-        Ok(LogResult::Some(res))
+        match res.is_empty() {
+            true => Ok(LogResult::None(self.commit_info(&head_commit)?)),
+            false => Ok(LogResult::Some(res)),
+        }
     }
 
     fn commit_info(&self, commit: &Commit) -> Result<super::CommitInfo, Box<dyn Error>> {
@@ -223,6 +378,7 @@ impl GitStore {
         let changed_projects = self.get_changes(commit)?;
         Ok(super::CommitInfo {
             hash,
+            revision: Revision::Git(commit.id),
             date,
             message,
             changed_projects,
@@ -248,26 +404,89 @@ impl GitStore {
         new_commit: &Commit,
         old_commit: &Commit,
     ) -> Result<Vec<ProjectID>, Box<dyn Error>> {
-        let mut changed_projects = Vec::new();
-        let mut new_project_versions: HashMap<ProjectID, Id> = self
-            .versioned_project_ids_from_commit(new_commit)?
-            .into_iter()
-            .map(|vpi| (vpi.proj_id, vpi.id))
-            .collect();
-        for vpi in self.versioned_project_ids_from_commit(old_commit)? {
-            let VersionedProjectID {
-                proj_id,
-                id: old_id,
-            } = vpi;
-            match new_project_versions.remove(&proj_id) {
-                Some(new_id) if new_id == old_id => {}
-                _ => changed_projects.push(proj_id),
-            };
+        let super::ProjectChanges {
+            mut changed,
+            mut removed,
+        } = self.project_changes(Some(old_commit), Some(new_commit))?;
+        changed.append(&mut removed);
+        Ok(changed)
+    }
+
+    pub(crate) fn changed_projects(
+        &self,
+        from: &Revision,
+        to: &Revision,
+    ) -> Result<super::ProjectChanges, Box<dyn Error>> {
+        self.project_changes(
+            self.find_commit(from)?.as_ref(),
+            self.find_commit(to)?.as_ref(),
+        )
+    }
+
+    /// Compares the project trees in two commits. A missing commit has no projects.
+    fn project_changes(
+        &self,
+        old_commit: Option<&Commit>,
+        new_commit: Option<&Commit>,
+    ) -> Result<super::ProjectChanges, Box<dyn Error>> {
+        let mut changes = super::ProjectChanges::default();
+        let mut new_project_versions: HashMap<ProjectID, Id> = match new_commit {
+            None => HashMap::new(),
+            Some(c) => self
+                .versioned_project_ids_from_commit(c)?
+                .into_iter()
+                .map(|vpi| (vpi.proj_id, vpi.id))
+                .collect(),
+        };
+        if let Some(old_commit) = old_commit {
+            for vpi in self.versioned_project_ids_from_commit(old_commit)? {
+                let VersionedProjectID {
+                    proj_id,
+                    id: old_id,
+                } = vpi;
+                match new_project_versions.remove(&proj_id) {
+                    Some(new_id) if new_id == old_id => {}
+                    Some(_) => changes.changed.push(proj_id),
+                    None => changes.removed.push(proj_id),
+                };
+            }
         }
         for (proj_id, _) in new_project_versions {
-            changed_projects.push(proj_id);
+            changes.changed.push(proj_id);
         }
-        Ok(changed_projects)
+        Ok(changes)
+    }
+
+    pub(crate) fn adjacent_revisions(
+        &self,
+        revision: &Revision,
+    ) -> Result<(Option<Revision>, Option<Revision>), Box<dyn Error>> {
+        let commit = match self.find_commit(revision)? {
+            None => return Ok((None, None)),
+            Some(c) => c,
+        };
+        let prev = commit
+            .parent_ids()
+            .next()
+            .map(|id| Revision::Git(id.detach()));
+
+        // Walk back from HEAD to find the commit whose first parent is this one.
+        let mut next = None;
+        let Some(mut cur) = self.tip()? else {
+            return Ok((prev, None));
+        };
+        while cur.id != commit.id {
+            let parent = match cur.parent_ids().next() {
+                None => break,
+                Some(id) => id.detach(),
+            };
+            if parent == commit.id {
+                next = Some(Revision::Git(cur.id));
+                break;
+            }
+            cur = self.r.find_commit(parent)?;
+        }
+        Ok((prev, next))
     }
 
     fn path_for(id: &ProjectID) -> String {
@@ -320,17 +539,12 @@ impl GitStore {
         id: &ProjectID,
         commit_message: &str,
     ) -> Result<&'static str, Box<dyn Error>> {
-        let head = self.r.head()?;
-        let head_ref = head.referent_name().ok_or("invalid head ref")?;
+        let head_ref = self.target_ref()?;
 
         // Get the current tree (or empty tree if unborn)
-        let (current_tree, parent_commit_ids) = if head.is_unborn() {
-            (self.r.empty_tree(), Vec::new())
-        } else {
-            (
-                self.r.head_commit()?.tree()?,
-                vec![self.r.head_commit()?.id],
-            )
+        let (current_tree, parent_commit_ids) = match self.tip()? {
+            None => (self.r.empty_tree(), Vec::new()),
+            Some(commit) => (commit.tree()?, vec![commit.id]),
         };
 
         // Create a new tree without the project
@@ -353,44 +567,543 @@ impl GitStore {
     }
 
     pub(crate) fn resolve(&self, expr: &str) -> Result<super::Revision, Box<dyn Error>> {
-        let id = self.r.rev_parse_single(expr)?;
-        Ok(Revision::Git(id.detach()))
+        // Annotated tags point at a tag object, which points at the commit.
+        let commit = self.r.rev_parse_single(expr)?.object()?.peel_to_commit()?;
+        Ok(Revision::Git(commit.id))
+    }
+
+    /// Creates an annotated tag for the commit at revision.
+    pub(crate) fn create_tag(
+        &self,
+        name: &str,
+        revision: &Revision,
+        note: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let commit = self
+            .find_commit(revision)?
+            .ok_or("there are no commits to tag yet")?;
+        if self
+            .r
+            .try_find_reference(&format!("refs/tags/{name}"))?
+            .is_some()
+        {
+            return Err(format!("tag {name} already exists").into());
+        }
+        let tagger = self.r.committer().ok_or("no committer configured")??;
+        self.r.tag(
+            name,
+            commit.id,
+            gix::objs::Kind::Commit,
+            Some(tagger),
+            note,
+            gix::refs::transaction::PreviousValue::MustNotExist,
+        )?;
+        Ok(commit.id().shorten_or_id().to_string())
+    }
+
+    /// Returns the tags, oldest first.
+    pub(crate) fn tags(&self) -> Result<Vec<super::TagInfo>, Box<dyn Error>> {
+        let mut res = Vec::new();
+        for r in self.r.references()?.tags()? {
+            let mut r = r.map_err(|e| e as Box<dyn Error>)?;
+            let name = r.name().shorten().to_string();
+            let commit = r.peel_to_commit()?;
+            let (date, note) = match r.id().object()?.try_into_tag() {
+                // A lightweight tag, which is just a name for the commit.
+                Err(_) => (commit.author()?.time()?.seconds, String::new()),
+                Ok(tag) => {
+                    let tag = tag.decode()?;
+                    let date = match tag.tagger {
+                        Some(tagger) => tagger.time()?.seconds,
+                        None => commit.author()?.time()?.seconds,
+                    };
+                    (date, tag.message.to_string().trim().to_string())
+                }
+            };
+            res.push(super::TagInfo {
+                name,
+                hash: commit.id().shorten_or_id().to_string(),
+                date: UNIX_EPOCH + Duration::from_secs(date as u64),
+                note,
+            });
+        }
+        res.sort_by_key(|t| t.date);
+        Ok(res)
     }
 
     pub(crate) fn store_render(
         &self,
-        rendered: &[(String, Vec<u8>)],
+        rendered: &super::RenderedTree,
         msg: &str,
         prev_render: Revision,
         source: Revision,
+        branch: Option<&str>,
     ) -> Result<Revision, Box<dyn Error>> {
         const RENDER_DUMMY_REF: &str = "RENDER_HEAD";
 
-        let dr: FullName = RENDER_DUMMY_REF.try_into()?;
-        if let Ok(r) = self.r.find_reference(&dr) {
-            r.delete()?;
+        let dr: FullName = match branch {
+            Some(branch) => branch_ref(branch).try_into()?,
+            None => {
+                let dr: FullName = RENDER_DUMMY_REF.try_into()?;
+                if let Ok(r) = self.r.find_reference(&dr) {
+                    r.delete()?;
+                };
+                dr
+            }
         };
 
-        let et = self.r.empty_tree();
-        let mut tb = tree::Editor::new(&et)?;
-        for (path, contents) in rendered {
+        let base = match self.find_commit(&rendered.base)? {
+            None => self.r.empty_tree(),
+            Some(c) => c.tree()?,
+        };
+        let mut tb = tree::Editor::new(&base)?;
+        for path in &rendered.removed {
+            for existing in rendered_paths(&base, path)? {
+                tb.remove(existing)?;
+            }
+        }
+        for (path, contents) in &rendered.files {
             let blob_id = self.r.write_blob(contents)?;
             tb.upsert(path, EntryKind::Blob, blob_id)?;
         }
         let tree_id = tb.write()?;
 
+        let source = match source {
+            Revision::Latest => Revision::Git(self.tip()?.ok_or("no commits yet")?.id),
+            source => source,
+        };
         let id = self
             .r
             .commit(dr, msg, tree_id, render_parents(prev_render, source))?;
         Ok(Revision::Git(id.detach()))
     }
+
+    pub(crate) fn rendered_branch(
+        &self,
+        branch: &str,
+    ) -> Result<super::RenderedBranch, Box<dyn Error>> {
+        // Rendered commits have the previous render (if any) as their first parent and the source
+        // commit as their last parent (see render_parents).
+        let (prev_render, last_source) = match self.r.try_find_reference(&branch_ref(branch))? {
+            None => (Revision::Empty, None),
+            Some(mut r) => {
+                let render = r.peel_to_commit()?;
+                let last_source = render.parent_ids().last().map(|id| id.detach());
+                (Revision::Git(render.id), last_source)
+            }
+        };
+
+        let mut pending = Vec::new();
+        let mut found = false;
+        if let Some(mut commit) = self.tip()? {
+            while Some(commit.id) != last_source {
+                pending.push(commit.id);
+                let parent = match commit.parent_ids().next() {
+                    None => break,
+                    Some(id) => id.object()?.try_into_commit()?,
+                };
+                commit = parent;
+            }
+            found = Some(commit.id) == last_source;
+        }
+        // After a switch to another branch, the last rendered source isn't on this one. Rendering
+        // picks up from where the branches split instead of from the start of the history, since
+        // the first render after that diffs against the last rendered source anyway.
+        if let Some(last_source) = last_source
+            && !found
+        {
+            let mut rendered = HashSet::new();
+            let mut next = Some(last_source);
+            while let Some(id) = next {
+                rendered.insert(id);
+                let commit = self.r.find_commit(id)?;
+                next = commit.parent_ids().next().map(|id| id.detach());
+            }
+            if let Some(split) = pending.iter().position(|id| rendered.contains(id)) {
+                pending.truncate(split);
+            }
+        }
+        let mut pending: Vec<Revision> = pending.into_iter().map(Revision::Git).collect();
+        pending.reverse();
+
+        Ok(super::RenderedBranch {
+            prev_render,
+            last_source: last_source.map_or(Revision::Empty, Revision::Git),
+            pending,
+        })
+    }
+
+    /// Squashes the older history of the branch that commits go to into the runs compact::plan
+    /// picks, keeping a backup ref of the original, then prunes objects nothing refers to any
+    /// more.
+    pub(crate) fn compact(&self, opts: &CompactOptions) -> Result<CompactResult, Box<dyn Error>> {
+        let target = self.target_ref()?;
+        let tip = self.tip()?.ok_or("there are no commits to compact yet")?;
+
+        // The history, oldest first. mind-meld doesn't make merge commits on the branch, so
+        // following first parents finds every commit.
+        let mut chain = Vec::new();
+        let mut next = Some(tip);
+        while let Some(commit) = next {
+            let mut parents = commit.parent_ids();
+            next = match (parents.next(), parents.next()) {
+                (_, Some(_)) => {
+                    let id = commit.id().shorten_or_id();
+                    return Err(format!("{id} is a merge commit, which can't be compacted").into());
+                }
+                (Some(id), None) => Some(id.object()?.try_into_commit()?),
+                (None, None) => None,
+            };
+            chain.push(commit.id);
+        }
+        chain.reverse();
+
+        let tags = self.tags_by_commit()?;
+        let mut dates = Vec::with_capacity(chain.len());
+        for id in &chain {
+            let seconds = self.r.find_commit(*id)?.author()?.time()?.seconds;
+            let date = UNIX_EPOCH + Duration::from_secs(seconds as u64);
+            dates.push((date, tags.contains_key(id)));
+        }
+        let cutoff = SystemTime::now()
+            .checked_sub(opts.older_than)
+            .unwrap_or(UNIX_EPOCH);
+        let runs = compact::plan(&dates, cutoff, opts.keep);
+
+        let mut res = CompactResult {
+            squashed: Vec::new(),
+            kept: chain.len(),
+            backup: None,
+            dropped_backups: match opts.drop_backups {
+                true => self.backups()?,
+                false => Vec::new(),
+            },
+            pruned: None,
+        };
+        for run in &runs {
+            let first = self.r.find_commit(chain[run.start])?;
+            let before = match first.parent_ids().next() {
+                None => None,
+                Some(id) => Some(id.object()?.try_into_commit()?),
+            };
+            let last = self.r.find_commit(chain[run.end - 1])?;
+            let super::ProjectChanges {
+                mut changed,
+                mut removed,
+            } = self.project_changes(before.as_ref(), Some(&last))?;
+            changed.append(&mut removed);
+            changed.sort_by_key(|id| id.to_string());
+            res.squashed.push(Squash {
+                from: dates[run.start].0,
+                to: dates[run.end - 1].0,
+                commits: run.len(),
+                projects: changed,
+            });
+            res.kept -= run.len();
+        }
+        if opts.dry_run {
+            return Ok(res);
+        }
+
+        if !runs.is_empty() {
+            res.backup = Some(self.rewrite(&target, &chain, &runs, &tags, opts.rendered_branch)?);
+        }
+        for name in &res.dropped_backups {
+            self.r.find_reference(name.as_str())?.delete()?;
+        }
+        res.pruned = self.prune()?;
+        Ok(res)
+    }
+
+    /// Writes the new history, with each run squashed into a copy of its last commit and every
+    /// other commit copied onto it, then moves the branch and the tags on it over and starts the
+    /// rendered branch over on it. Returns the backup ref that points at the original history.
+    fn rewrite(
+        &self,
+        target: &FullName,
+        chain: &[ObjectId],
+        runs: &[Range<usize>],
+        tags: &Tags,
+        rendered_branch: Option<&str>,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut all_runs = Vec::new();
+        let mut i = 0;
+        for run in runs {
+            all_runs.extend((i..run.start).map(|i| i..i + 1));
+            all_runs.push(run.clone());
+            i = run.end;
+        }
+        all_runs.extend((i..chain.len()).map(|i| i..i + 1));
+
+        // The new commit for the last commit of each run, and for the others the new commit
+        // before the run, which is the newest one they come after.
+        let mut new_ids: HashMap<ObjectId, ObjectId> = HashMap::new();
+        let mut earlier: HashMap<ObjectId, Option<ObjectId>> = HashMap::new();
+        let mut parent: Option<ObjectId> = None;
+        for run in all_runs {
+            let last = self.r.find_commit(chain[run.end - 1])?;
+            let mut commit: gix::objs::Commit = last.decode()?.into();
+            commit.parents.clear();
+            commit.parents.extend(parent);
+            // A signature wouldn't match the copy.
+            commit
+                .extra_headers
+                .retain(|(name, _)| !name.starts_with(b"gpgsig"));
+            if run.len() > 1 {
+                let mut titles = Vec::new();
+                for id in &chain[run.clone()] {
+                    titles.push(self.r.find_commit(*id)?.message()?.title.to_string());
+                }
+                commit.message = compact::squash_message(&titles).into();
+            }
+            let new = self.r.write_object(&commit)?.detach();
+            for id in &chain[run.start..run.end - 1] {
+                earlier.insert(*id, parent);
+            }
+            new_ids.insert(chain[run.end - 1], new);
+            parent = Some(new);
+        }
+        let old_tip = *chain.last().ok_or("there are no commits to compact")?;
+        let new_tip = parent.ok_or("there are no commits to compact")?;
+
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let backup_ref = |name: &FullName| {
+            let name = name.as_bstr().to_string();
+            let name = name.strip_prefix("refs/").unwrap_or(&name).to_string();
+            format!("{BACKUPS}{stamp}/{name}")
+        };
+        let backup = backup_ref(target);
+        let log_message = "mind-meld compact";
+        self.r.reference(
+            backup.as_str(),
+            old_tip,
+            PreviousValue::MustNotExist,
+            log_message,
+        )?;
+        for (name, tag_target) in tags.values().flatten() {
+            self.r.reference(
+                backup_ref(name).as_str(),
+                *tag_target,
+                PreviousValue::MustNotExist,
+                log_message,
+            )?;
+        }
+
+        // Fails if a commit was made while the history was being rewritten.
+        self.r
+            .reference(
+                target.clone(),
+                new_tip,
+                PreviousValue::MustExistAndMatch(Target::Object(old_tip)),
+                log_message,
+            )
+            .map_err(|e| format!("couldn't move {} to the new history: {e}", target.shorten()))?;
+
+        for (commit, refs) in tags {
+            let Some(&new) = new_ids.get(commit) else {
+                continue;
+            };
+            for (name, tag_target) in refs {
+                let new_target = match self.r.find_object(*tag_target)?.try_into_tag() {
+                    // A lightweight tag, which points straight at the commit.
+                    Err(_) => new,
+                    Ok(tag) => {
+                        let mut tag: gix::objs::Tag = tag.decode()?.into();
+                        tag.target = new;
+                        tag.pgp_signature = None;
+                        self.r.write_object(&tag)?.detach()
+                    }
+                };
+                self.r.reference(
+                    name.clone(),
+                    new_target,
+                    PreviousValue::MustExistAndMatch(Target::Object(*tag_target)),
+                    log_message,
+                )?;
+            }
+        }
+
+        // Starts the rendered branch over on the new history, so that syncing it doesn't render
+        // every commit again and its old renders don't keep the original history around. They're
+        // kept under the backup instead.
+        if let Some(branch) = rendered_branch
+            && let Some(mut r) = self.r.try_find_reference(&branch_ref(branch))?
+        {
+            let name = r.name().to_owned();
+            let render = r.peel_to_commit()?;
+            let last_source = render.parent_ids().last().map(|id| id.detach());
+            let new_source = last_source.and_then(|id| match new_ids.get(&id) {
+                Some(new) => Some(*new),
+                None => earlier.get(&id).copied().flatten(),
+            });
+            if let (Some(old), Some(new)) = (last_source, new_source)
+                && old != new
+            {
+                let mut commit: gix::objs::Commit = render.decode()?.into();
+                commit.parents.clear();
+                commit.parents.push(new);
+                commit.message = "Follow compacted history".into();
+                commit
+                    .extra_headers
+                    .retain(|(name, _)| !name.starts_with(b"gpgsig"));
+                let follow = self.r.write_object(&commit)?.detach();
+                self.r.reference(
+                    backup_ref(&name).as_str(),
+                    render.id,
+                    PreviousValue::MustNotExist,
+                    log_message,
+                )?;
+                self.r.reference(
+                    name,
+                    follow,
+                    PreviousValue::MustExistAndMatch(Target::Object(render.id)),
+                    log_message,
+                )?;
+            }
+        }
+        Ok(backup)
+    }
+
+    /// Returns the tags by the commit they point at, as their name and the id the ref points at
+    /// (a tag object for annotated tags).
+    fn tags_by_commit(&self) -> Result<Tags, Box<dyn Error>> {
+        let mut res: Tags = HashMap::new();
+        for r in self.r.references()?.tags()? {
+            let mut r = r.map_err(|e| e as Box<dyn Error>)?;
+            let target = r.id().detach();
+            let commit = r.peel_to_commit()?.id;
+            res.entry(commit)
+                .or_default()
+                .push((r.name().to_owned(), target));
+        }
+        Ok(res)
+    }
+
+    /// Returns the refs that compact left pointing at earlier histories.
+    fn backups(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut res = Vec::new();
+        for r in self.r.references()?.all()? {
+            let r = r.map_err(|e| e as Box<dyn Error>)?;
+            let name = r.name().as_bstr().to_string();
+            if name.starts_with(BACKUPS) {
+                res.push(name);
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    /// Deletes the loose objects that no ref leads to. Objects written recently are left alone,
+    /// in case a commit that's being made refers to them. Returns None without pruning if the
+    /// repository has a work tree, since its index can refer to objects too.
+    fn prune(&self) -> Result<Option<usize>, Box<dyn Error>> {
+        if !self.r.is_bare() {
+            return Ok(None);
+        }
+        let reachable = self.reachable()?;
+        let mut pruned = 0;
+        for dir in fs::read_dir(self.r.path().join("objects"))? {
+            let dir = dir?;
+            let prefix = dir.file_name().to_string_lossy().to_string();
+            // Skips pack and info, as well as anything else that isn't a loose object.
+            if prefix.len() != 2 || !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())? {
+                let file = file?;
+                let hex = format!("{prefix}{}", file.file_name().to_string_lossy());
+                let Ok(id) = ObjectId::from_hex(hex.as_bytes()) else {
+                    continue;
+                };
+                let age = file.metadata()?.modified()?.elapsed();
+                if reachable.contains(&id) || age.is_ok_and(|age| age < PRUNE_GRACE) {
+                    continue;
+                }
+                fs::remove_file(file.path())?;
+                pruned += 1;
+            }
+        }
+        Ok(Some(pruned))
+    }
+
+    /// Returns every object that can be reached from a ref or HEAD.
+    fn reachable(&self) -> Result<HashSet<ObjectId>, Box<dyn Error>> {
+        let mut stack = Vec::new();
+        for r in self.r.references()?.all()? {
+            let r = r.map_err(|e| e as Box<dyn Error>)?;
+            stack.extend(r.try_id().map(|id| id.detach()));
+        }
+        stack.extend(self.r.head_id().ok().map(|id| id.detach()));
+
+        let mut seen = HashSet::new();
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let object = self.r.find_object(id)?;
+            match object.kind {
+                Kind::Commit => {
+                    let commit = object.into_commit();
+                    stack.push(commit.tree_id()?.detach());
+                    stack.extend(commit.parent_ids().map(|id| id.detach()));
+                }
+                Kind::Tree => {
+                    for e in object.into_tree().iter() {
+                        let e = e?;
+                        match e.mode() {
+                            // Blobs don't refer to anything, so there's no need to read them.
+                            mode if mode.is_tree() => stack.push(e.oid().to_owned()),
+                            mode if mode.is_commit() => {}
+                            _ => {
+                                seen.insert(e.oid().to_owned());
+                            }
+                        }
+                    }
+                }
+                Kind::Tag => stack.push(object.into_tag().target_id()?.detach()),
+                Kind::Blob => {}
+            }
+        }
+        Ok(seen)
+    }
+}
+
+fn branch_ref(branch: &str) -> String {
+    format!("refs/heads/{branch}")
+}
+
+/// Finds the files in tree that were rendered for path, which has no extension (e.g.
+/// "spike/Project 1" matches "spike/Project 1.py").
+fn rendered_paths(tree: &Tree, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let (dir, stem) = match path.rsplit_once('/') {
+        Some(parts) => parts,
+        None => ("", path),
+    };
+    let dir_tree = match dir {
+        "" => tree.clone(),
+        dir => match tree.lookup_entry_by_path(dir)? {
+            None => return Ok(Vec::new()),
+            Some(e) => e.object()?.try_into_tree()?,
+        },
+    };
+    let mut res = Vec::new();
+    for e in dir_tree.iter() {
+        let name = e?.filename().to_string();
+        if name.rsplit_once('.').map(|(prefix, _)| prefix) == Some(stem) {
+            res.push(match dir {
+                "" => name,
+                dir => format!("{dir}/{name}"),
+            });
+        }
+    }
+    Ok(res)
 }
 
 fn render_parents(prev_render: Revision, source: Revision) -> Vec<ObjectId> {
     fn a(v: &mut Vec<ObjectId>, r: Revision) {
         match r {
             Revision::Empty => {}
-            Revision::Latest => unreachable!(),
+            Revision::Latest | Revision::Encrypted(_) | Revision::Sqlite(_) => unreachable!(),
             Revision::Git(oid) => v.push(oid),
         };
     }
@@ -425,3 +1138,220 @@ fn validate_tree(t: gix::Tree) -> Result<(), Box<dyn Error>> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compact::Keep;
+    use crate::project::test_util::{id, python};
+    use crate::store::{LogResult, RenderedTree};
+    use std::fs::{File, FileTimes};
+
+    /// Creates a bare store with an identity to commit as.
+    fn create_store(path: &Path) -> GitStore {
+        create(path).unwrap();
+        let mut config = fs::read_to_string(path.join("config")).unwrap();
+        config.push_str("[user]\n\tname = Test\n\temail = test@example.com\n");
+        fs::write(path.join("config"), config).unwrap();
+        open(path, None).unwrap()
+    }
+
+    #[test]
+    fn test_log_with_imported_tip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = create_store(dir.path());
+        store
+            .commit(&[(id("a"), python("1"))], "new", None)
+            .unwrap();
+        // An import puts a commit with an old date at the tip.
+        let old = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let imported = [(id("b"), python("1"))];
+        store.commit(&imported, "imported", Some(old)).unwrap();
+
+        let since = SystemTime::now() - Duration::from_secs(60 * 60);
+        let LogResult::Some(commits) = store.log(since).unwrap() else {
+            panic!("expected commits");
+        };
+        let messages: Vec<&str> = commits.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["new"]);
+        assert!(matches!(
+            store.log(SystemTime::now()).unwrap(),
+            LogResult::None(c) if c.message == "imported"
+        ));
+    }
+
+    /// Backdates the loose objects, so that prune doesn't leave them alone for being new.
+    fn backdate_objects(path: &Path) {
+        let old = SystemTime::now() - PRUNE_GRACE * 2;
+        for dir in fs::read_dir(path.join("objects")).unwrap() {
+            let dir = dir.unwrap();
+            if dir.file_name().len() != 2 {
+                continue;
+            }
+            for file in fs::read_dir(dir.path()).unwrap() {
+                let file = File::open(file.unwrap().path()).unwrap();
+                file.set_times(FileTimes::new().set_modified(old)).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = create_store(dir.path());
+        let tip = || store.tip().unwrap().unwrap();
+        // Four commits in the same hour long ago, then one now.
+        let mut old = Vec::new();
+        for (i, (name, main)) in [("a", "1"), ("a", "2"), ("b", "1"), ("a", "3")]
+            .into_iter()
+            .enumerate()
+        {
+            let date = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + i as u64 * 60);
+            let msg = format!("commit {i}");
+            store
+                .commit(&[(id(name), python(main))], &msg, Some(date))
+                .unwrap();
+            old.push(tip().id);
+        }
+        store
+            .create_tag("works", &Revision::Git(old[1]), "scored 480")
+            .unwrap();
+        let light = "refs/tags/light";
+        let reason = "test";
+        store
+            .r
+            .reference(light, old[3], PreviousValue::MustNotExist, reason)
+            .unwrap();
+        store
+            .commit(&[(id("b"), python("2"))], "recent", None)
+            .unwrap();
+        let old_tip = tip();
+        let rendered = RenderedTree {
+            base: Revision::Empty,
+            files: vec![("spike/a.py".to_string(), b"3".to_vec())],
+            removed: Vec::new(),
+        };
+        let source = Revision::Git(old[3]);
+        let render = store
+            .store_render(
+                &rendered,
+                "Render",
+                Revision::Empty,
+                source,
+                Some("rendered"),
+            )
+            .unwrap();
+        let Revision::Git(old_render) = render else {
+            panic!("expected a git revision");
+        };
+        backdate_objects(dir.path());
+
+        let res = store
+            .compact(&CompactOptions {
+                older_than: Duration::from_secs(24 * 60 * 60),
+                keep: Keep::Hourly,
+                dry_run: false,
+                drop_backups: false,
+                rendered_branch: Some("rendered"),
+            })
+            .unwrap();
+        // The tags end the runs, so the tagged commits are kept as they were.
+        let runs: Vec<usize> = res.squashed.iter().map(|s| s.commits).collect();
+        assert_eq!(runs, [2, 2]);
+        assert_eq!(res.kept, 1);
+
+        let new_tip = tip();
+        assert_ne!(new_tip.id, old_tip.id);
+        assert_eq!(new_tip.tree_id().unwrap(), old_tip.tree_id().unwrap());
+        let new_ids = store.r.rev_walk([new_tip.id]).all().unwrap();
+        let new_ids: Vec<ObjectId> = new_ids.map(|info| info.unwrap().id).collect();
+        assert_eq!(new_ids.len(), 3);
+        let tagged = |name: &str| {
+            let mut r = store.r.find_reference(name).unwrap();
+            r.peel_to_commit().unwrap()
+        };
+        for (name, was) in [("refs/tags/works", old[1]), (light, old[3])] {
+            let was = store.r.find_commit(was).unwrap();
+            let now = tagged(name);
+            assert!(new_ids.contains(&now.id), "{name} isn't on the new history");
+            assert_eq!(now.tree_id().unwrap(), was.tree_id().unwrap());
+        }
+
+        let backup = res.backup.unwrap();
+        assert_eq!(tagged(&backup).id, old_tip.id);
+        let stamp = backup.strip_suffix("/heads/main").unwrap();
+        assert_eq!(tagged(&format!("{stamp}/heads/rendered")).id, old_render);
+        assert_eq!(tagged(&format!("{stamp}/tags/works")).id, old[1]);
+
+        // The rendered branch starts over from the copy of the commit it rendered.
+        let follow = tagged("refs/heads/rendered");
+        let parents: Vec<ObjectId> = follow.parent_ids().map(|id| id.detach()).collect();
+        assert_eq!(parents, [tagged(light).id]);
+        let old_render = store.r.find_commit(old_render).unwrap();
+        assert_eq!(follow.tree_id().unwrap(), old_render.tree_id().unwrap());
+        assert_eq!(store.rendered_branch("rendered").unwrap().pending.len(), 1);
+
+        // Nothing was pruned that a ref still leads to, including the backups.
+        assert_eq!(res.pruned, Some(0));
+        store.reachable().unwrap();
+
+        // Once the backups go, so does the original history.
+        backdate_objects(dir.path());
+        let res = store
+            .compact(&CompactOptions {
+                older_than: Duration::from_secs(24 * 60 * 60),
+                keep: Keep::Hourly,
+                dry_run: false,
+                drop_backups: true,
+                rendered_branch: Some("rendered"),
+            })
+            .unwrap();
+        assert_eq!(res.dropped_backups.len(), 4);
+        assert!(res.pruned.unwrap() > 0);
+        store.reachable().unwrap();
+        assert!(store.r.find_object(old_tip.id).is_err());
+        assert_eq!(tip().id, new_tip.id);
+    }
+
+    #[test]
+    fn test_rendered_branch_after_switch() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = create_store(dir.path());
+        let mut ids = Vec::new();
+        for main in ["1", "2", "3"] {
+            store
+                .commit(&[(id("a"), python(main))], main, None)
+                .unwrap();
+            ids.push(store.tip().unwrap().unwrap().id);
+        }
+        let rendered = RenderedTree {
+            base: Revision::Empty,
+            files: vec![("spike/a.py".to_string(), b"3".to_vec())],
+            removed: Vec::new(),
+        };
+        let source = Revision::Git(ids[2]);
+        store
+            .store_render(
+                &rendered,
+                "Render",
+                Revision::Empty,
+                source,
+                Some("rendered"),
+            )
+            .unwrap();
+
+        // A branch that split off before the rendered commit has nothing new to render.
+        store
+            .create_branch("feature", Some(&Revision::Git(ids[0])))
+            .unwrap();
+        let store = open(dir.path(), Some("feature")).unwrap();
+        let pending = store.rendered_branch("rendered").unwrap().pending;
+        assert!(pending.is_empty());
+
+        store.commit(&[(id("a"), python("4"))], "4", None).unwrap();
+        let tip = store.tip().unwrap().unwrap().id;
+        let branch = store.rendered_branch("rendered").unwrap();
+        assert!(matches!(branch.pending[..], [Revision::Git(id)] if id == tip));
+        assert!(matches!(branch.last_source, Revision::Git(id) if id == ids[2]));
+    }
+}
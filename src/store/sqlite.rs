@@ -0,0 +1,1022 @@
+//! A store that keeps everything in a single SQLite file, so that the history of a project or
+//! what changed since a date can be found with indexed queries instead of walking every commit.
+//!
+//! Each version of a project is a snapshot, identified by `RawProject::hash`, whose files are kept
+//! once per distinct contents by their SHA-256. Commits only record the projects they change, and
+//! branches point at commits like in git. Rendered commits keep their files in render_files.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{
+    params, Connection, OpenFlags, OptionalExtension, Transaction, TransactionBehavior,
+};
+use sha2::{Digest, Sha256};
+
+use crate::project::{ArchiveEntry, ArchiveEntryContents, ProjectID, RawArchive, RawProject};
+
+use super::{
+    CommitInfo, LogResult, ProjectChanges, RenderedBranch, RenderedTree, Revision, TagInfo,
+};
+
+/// Files by path, with their contents.
+type Files = Vec<(String, Vec<u8>)>;
+
+/// The snapshot of each project, by program and name.
+type Projects = BTreeMap<(String, String), Vec<u8>>;
+
+const SCHEMA_VERSION: i64 = 1;
+const DEFAULT_BRANCH: &str = "main";
+
+const SCHEMA: &str = "
+CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE blobs (hash BLOB PRIMARY KEY, data BLOB NOT NULL) WITHOUT ROWID;
+CREATE TABLE snapshots (hash BLOB PRIMARY KEY) WITHOUT ROWID;
+CREATE TABLE snapshot_entries (
+    snapshot BLOB NOT NULL REFERENCES snapshots(hash),
+    path TEXT NOT NULL,
+    blob BLOB NOT NULL REFERENCES blobs(hash),
+    PRIMARY KEY (snapshot, path)
+) WITHOUT ROWID;
+CREATE TABLE commits (
+    id INTEGER PRIMARY KEY,
+    parent INTEGER REFERENCES commits(id),
+    date INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    -- For rendered commits, the commit that was rendered.
+    source INTEGER REFERENCES commits(id)
+);
+CREATE INDEX commits_parent ON commits(parent);
+CREATE INDEX commits_date ON commits(date);
+-- The projects each commit added, modified (snapshot is the new version) or removed (snapshot is
+-- NULL).
+CREATE TABLE changes (
+    commit_id INTEGER NOT NULL REFERENCES commits(id),
+    program TEXT NOT NULL,
+    name TEXT NOT NULL,
+    snapshot BLOB REFERENCES snapshots(hash),
+    PRIMARY KEY (commit_id, program, name)
+) WITHOUT ROWID;
+CREATE INDEX changes_project ON changes(program, name, commit_id);
+CREATE TABLE render_files (
+    commit_id INTEGER NOT NULL REFERENCES commits(id),
+    path TEXT NOT NULL,
+    blob BLOB NOT NULL REFERENCES blobs(hash),
+    PRIMARY KEY (commit_id, path)
+) WITHOUT ROWID;
+CREATE TABLE branches (name TEXT PRIMARY KEY, head INTEGER NOT NULL REFERENCES commits(id));
+CREATE TABLE tags (
+    name TEXT PRIMARY KEY,
+    commit_id INTEGER NOT NULL REFERENCES commits(id),
+    date INTEGER NOT NULL,
+    note TEXT NOT NULL
+);
+";
+
+/// The commit ?1 and all the commits before it. Commit ids only grow, so the newest change to a
+/// project among them is the one with the largest commit id.
+const ANCESTORS: &str = "WITH RECURSIVE ancestors(id) AS (
+    SELECT ?1 UNION ALL SELECT c.parent FROM commits c JOIN ancestors a ON c.id = a.id
+    WHERE c.parent IS NOT NULL)";
+
+/// Opens the store at p. Commits go to branch if it's given, or else to the store's default
+/// branch.
+pub fn open<P: AsRef<Path>>(p: P, branch: Option<&str>) -> Result<SqliteStore, Box<dyn Error>> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let conn = Connection::open_with_flags(p, flags)?;
+    SqliteStore::new(conn, branch)
+}
+
+/// Creates a store at p, or opens it if it's already there.
+pub fn create<P: AsRef<Path>>(p: P) -> Result<SqliteStore, Box<dyn Error>> {
+    let path = p.as_ref();
+    if path.exists() {
+        return open(path, None);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let conn = Connection::open(path)?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    tx.execute(
+        "INSERT INTO meta (key, value) VALUES ('head', ?1)",
+        [DEFAULT_BRANCH],
+    )?;
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()?;
+    SqliteStore::new(conn, None)
+}
+
+pub struct SqliteStore {
+    conn: Connection,
+    branch: Option<String>,
+}
+
+impl SqliteStore {
+    fn new(conn: Connection, branch: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let version: i64 = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+        if version != SCHEMA_VERSION {
+            return Err("database can not be used for mind-meld, it has another schema".into());
+        }
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let branch = branch.map(str::to_string);
+        Ok(Self { conn, branch })
+    }
+
+    /// The branch that commits go to.
+    fn target_branch(&self) -> Result<String, Box<dyn Error>> {
+        self.target_branch_in(&self.conn)
+    }
+
+    /// Like target_branch, but reads through conn, e.g. a transaction.
+    fn target_branch_in(&self, conn: &Connection) -> Result<String, Box<dyn Error>> {
+        match &self.branch {
+            Some(branch) => Ok(branch.clone()),
+            None => {
+                let sql = "SELECT value FROM meta WHERE key = 'head'";
+                Ok(conn.query_row(sql, [], |r| r.get(0))?)
+            }
+        }
+    }
+
+    /// Returns the newest commit on the branch that commits go to, or None if it doesn't have
+    /// any yet.
+    fn tip(&self) -> Result<Option<i64>, Box<dyn Error>> {
+        self.tip_in(&self.conn)
+    }
+
+    /// Like tip, but reads through conn, e.g. a transaction.
+    fn tip_in(&self, conn: &Connection) -> Result<Option<i64>, Box<dyn Error>> {
+        branch_head(conn, &self.target_branch_in(conn)?)
+    }
+
+    /// Returns the commit for revision, or None if the revision has no commit (e.g. it's empty or
+    /// there are no commits yet).
+    fn find_commit(&self, revision: &Revision) -> Result<Option<i64>, Box<dyn Error>> {
+        match revision {
+            Revision::Empty => Ok(None),
+            Revision::Latest => self.tip(),
+            Revision::Sqlite(id) => match self.commit_exists(*id)? {
+                true => Ok(Some(*id)),
+                false => Err(format!("there's no commit {id}").into()),
+            },
+            Revision::Git(_) | Revision::Encrypted(_) => {
+                Err("revisions of other store types can't be used with a sqlite store".into())
+            }
+        }
+    }
+
+    fn commit_exists(&self, id: i64) -> Result<bool, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT 1 FROM commits WHERE id = ?1", [id], |_| Ok(()))
+            .optional()?
+            .is_some())
+    }
+
+    fn parent(&self, id: i64) -> Result<Option<i64>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT parent FROM commits WHERE id = ?1", [id], |r| {
+                r.get(0)
+            })?)
+    }
+
+    /// Returns the snapshot of every project in the commit, by (program, name).
+    fn projects_at(&self, commit: Option<i64>) -> Result<Projects, Box<dyn Error>> {
+        let Some(commit) = commit else {
+            return Ok(BTreeMap::new());
+        };
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{ANCESTORS}
+            SELECT program, name, snapshot FROM (
+                SELECT program, name, snapshot,
+                    ROW_NUMBER() OVER (PARTITION BY program, name ORDER BY commit_id DESC) AS n
+                FROM changes WHERE commit_id IN ancestors)
+            WHERE n = 1 AND snapshot IS NOT NULL"
+        ))?;
+        let rows = stmt.query_map([commit], |r| Ok(((r.get(0)?, r.get(1)?), r.get(2)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn read_snapshot(&self, hash: &[u8]) -> Result<RawProject, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT e.path, b.data FROM snapshot_entries e JOIN blobs b ON b.hash = e.blob
+            WHERE e.snapshot = ?1 ORDER BY e.path",
+        )?;
+        let files = stmt
+            .query_map([hash], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(RawProject {
+            archive: unflatten(files),
+        })
+    }
+
+    pub fn project_ids(&self) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        self.project_ids_at(&Revision::Latest)
+    }
+
+    pub(crate) fn project_ids_at(
+        &self,
+        revision: &Revision,
+    ) -> Result<Vec<ProjectID>, Box<dyn Error>> {
+        self.projects_at(self.find_commit(revision)?)?
+            .into_keys()
+            .map(|(program, name)| project_id(program, name))
+            .collect()
+    }
+
+    pub(crate) fn read_project(
+        &self,
+        id: &ProjectID,
+        revision: &Revision,
+    ) -> Result<Option<RawProject>, Box<dyn Error>> {
+        let commit = self.find_commit(revision)?;
+        match snapshot_at(&self.conn, commit, id)? {
+            None => Ok(None),
+            Some(hash) => Ok(Some(self.read_snapshot(&hash)?)),
+        }
+    }
+
+    /// Everything in the file belongs to the store, so there's never anything unexpected.
+    pub(crate) fn unexpected_entries(
+        &self,
+        revision: &Revision,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        self.find_commit(revision)?;
+        Ok(Vec::new())
+    }
+
+    pub(crate) fn commit(
+        &self,
+        projects: &[(ProjectID, RawProject)],
+        message: &str,
+        date: Option<SystemTime>,
+    ) -> Result<&'static str, Box<dyn Error>> {
+        // Taking the write lock before reading the tip keeps another process from committing in
+        // between, which would leave its commit off the branch.
+        let tx = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?;
+        let tip = self.tip_in(&tx)?;
+        let mut changed = Vec::new();
+        for (id, raw) in projects {
+            let hash = raw.hash();
+            if snapshot_at(&tx, tip, id)?.as_ref() != Some(&hash) {
+                changed.push((id, raw, hash));
+            }
+        }
+        if changed.is_empty() {
+            return Ok("already up to date");
+        }
+
+        let commit = insert_commit(&tx, tip, message, date, None)?;
+        for (id, raw, hash) in changed {
+            write_snapshot(&tx, raw, &hash)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO changes (commit_id, program, name, snapshot)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![commit, id.program.to_string(), id.name, hash],
+            )?;
+        }
+        set_branch(&tx, &self.target_branch_in(&tx)?, commit)?;
+        tx.commit()?;
+        Ok("added")
+    }
+
+    pub(crate) fn untrack(
+        &self,
+        id: &ProjectID,
+        message: &str,
+    ) -> Result<&'static str, Box<dyn Error>> {
+        let tx = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?;
+        let tip = self.tip_in(&tx)?;
+        if snapshot_at(&tx, tip, id)?.is_none() {
+            return Ok("not tracked");
+        }
+        let commit = insert_commit(&tx, tip, message, None, None)?;
+        tx.execute(
+            "INSERT INTO changes (commit_id, program, name, snapshot) VALUES (?1, ?2, ?3, NULL)",
+            params![commit, id.program.to_string(), id.name],
+        )?;
+        set_branch(&tx, &self.target_branch_in(&tx)?, commit)?;
+        tx.commit()?;
+        Ok("removed")
+    }
+
+    pub fn log(&self, since: SystemTime) -> Result<LogResult, Box<dyn Error>> {
+        let Some(tip) = self.tip()? else {
+            return Ok(LogResult::Unborn);
+        };
+        let commits = self.commits_between(since, None)?;
+        // The tip can be older than commits before it, e.g. after an import.
+        if commits.is_empty() {
+            return Ok(LogResult::None(self.commit_info(tip)?));
+        }
+        Ok(LogResult::Some(commits))
+    }
+
+    /// Returns the commits on the current branch dated from `from` up to, but not including,
+    /// `to`, newest first. Only the commits since the oldest one in the range are walked to check
+    /// they're on the branch, the rest are found through the date index.
+    pub(crate) fn commits_between(
+        &self,
+        from: SystemTime,
+        to: Option<SystemTime>,
+    ) -> Result<Vec<CommitInfo>, Box<dyn Error>> {
+        let Some(tip) = self.tip()? else {
+            return Ok(Vec::new());
+        };
+        let secs = |t: SystemTime| Ok::<_, Box<dyn Error>>(t.duration_since(UNIX_EPOCH)?.as_secs());
+        let from = secs(from)? as i64;
+        let to = to.map(secs).transpose()?.map_or(i64::MAX, |t| t as i64);
+        let mut stmt = self.conn.prepare_cached(
+            "WITH RECURSIVE
+            candidates(id) AS (
+                SELECT id FROM commits INDEXED BY commits_date WHERE date >= ?2 AND date < ?3),
+            ancestors(id) AS (
+                SELECT ?1 UNION ALL SELECT c.parent FROM commits c JOIN ancestors a ON c.id = a.id
+                WHERE c.parent >= (SELECT MIN(id) FROM candidates))
+            SELECT c.id, c.date, c.message, ch.program, ch.name
+            FROM commits c LEFT JOIN changes ch ON ch.commit_id = c.id
+            WHERE c.id IN candidates AND c.id IN ancestors
+            ORDER BY c.id DESC, ch.program, ch.name",
+        )?;
+        let mut rows = stmt.query(params![tip, from, to])?;
+        let mut commits: Vec<CommitInfo> = Vec::new();
+        let mut last = None;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            if last != Some(id) {
+                last = Some(id);
+                let date: i64 = row.get(1)?;
+                let message: String = row.get(2)?;
+                commits.push(CommitInfo {
+                    hash: id.to_string(),
+                    revision: Revision::Sqlite(id),
+                    date: UNIX_EPOCH + Duration::from_secs(date as u64),
+                    // Like git, only the first line.
+                    message: message.lines().next().unwrap_or_default().to_string(),
+                    changed_projects: Vec::new(),
+                });
+            }
+            let program: Option<String> = row.get(3)?;
+            let name: Option<String> = row.get(4)?;
+            if let (Some(program), Some(name)) = (program, name) {
+                let commit = commits.last_mut().expect("pushed above");
+                commit.changed_projects.push(project_id(program, name)?);
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Returns the commits on the current branch that changed the project, newest first.
+    pub(crate) fn project_history(
+        &self,
+        id: &ProjectID,
+    ) -> Result<Vec<CommitInfo>, Box<dyn Error>> {
+        let Some(tip) = self.tip()? else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{ANCESTORS}
+            SELECT commit_id FROM changes
+            WHERE program = ?2 AND name = ?3 AND commit_id IN ancestors
+            ORDER BY commit_id DESC"
+        ))?;
+        let ids = stmt
+            .query_map(params![tip, id.program.to_string(), id.name], |r| r.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        ids.into_iter().map(|id| self.commit_info(id)).collect()
+    }
+
+    fn commit_info(&self, id: i64) -> Result<CommitInfo, Box<dyn Error>> {
+        let (date, message): (i64, String) = self.conn.query_row(
+            "SELECT date, message FROM commits WHERE id = ?1",
+            [id],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )?;
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT program, name FROM changes WHERE commit_id = ?1 ORDER BY program, name",
+        )?;
+        let changed_projects = stmt
+            .query_map([id], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .map(|row| {
+                let (program, name) = row?;
+                project_id(program, name)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(CommitInfo {
+            hash: id.to_string(),
+            revision: Revision::Sqlite(id),
+            date: UNIX_EPOCH + Duration::from_secs(date as u64),
+            // Like git, only the first line.
+            message: message.lines().next().unwrap_or_default().to_string(),
+            changed_projects,
+        })
+    }
+
+    pub(crate) fn latest(&self) -> Result<Option<Revision>, Box<dyn Error>> {
+        Ok(self.tip()?.map(Revision::Sqlite))
+    }
+
+    /// Accepts a commit number, a tag, a branch, or HEAD for the newest commit on the current
+    /// branch, and any of them followed by ~n to go back n commits.
+    pub(crate) fn resolve(&self, expr: &str) -> Result<Revision, Box<dyn Error>> {
+        let unknown = || format!("unknown revision {expr}");
+        let (base, back) = super::split_ancestor(expr).ok_or_else(unknown)?;
+        let mut id = match base {
+            "HEAD" => self.tip()?,
+            base => {
+                let tag = self
+                    .conn
+                    .query_row("SELECT commit_id FROM tags WHERE name = ?1", [base], |r| {
+                        r.get(0)
+                    })
+                    .optional()?;
+                match tag {
+                    Some(id) => Some(id),
+                    None => match branch_head(&self.conn, base)? {
+                        Some(id) => Some(id),
+                        None => base
+                            .parse()
+                            .ok()
+                            .filter(|id| self.commit_exists(*id).unwrap_or(false)),
+                    },
+                }
+            }
+        }
+        .ok_or_else(unknown)?;
+        for _ in 0..back {
+            id = self.parent(id)?.ok_or_else(unknown)?;
+        }
+        Ok(Revision::Sqlite(id))
+    }
+
+    pub(crate) fn changed_projects(
+        &self,
+        from: &Revision,
+        to: &Revision,
+    ) -> Result<ProjectChanges, Box<dyn Error>> {
+        let old = self.projects_at(self.find_commit(from)?)?;
+        let mut new = self.projects_at(self.find_commit(to)?)?;
+        let mut changes = ProjectChanges::default();
+        for (key, old_snapshot) in old {
+            match new.remove(&key) {
+                Some(new_snapshot) if new_snapshot == old_snapshot => {}
+                Some(_) => changes.changed.push(project_id(key.0, key.1)?),
+                None => changes.removed.push(project_id(key.0, key.1)?),
+            }
+        }
+        for (program, name) in new.into_keys() {
+            changes.changed.push(project_id(program, name)?);
+        }
+        Ok(changes)
+    }
+
+    pub(crate) fn adjacent_revisions(
+        &self,
+        revision: &Revision,
+    ) -> Result<(Option<Revision>, Option<Revision>), Box<dyn Error>> {
+        let Some(id) = self.find_commit(revision)? else {
+            return Ok((None, None));
+        };
+        let prev = self.parent(id)?.map(Revision::Sqlite);
+        let Some(tip) = self.tip()? else {
+            return Ok((prev, None));
+        };
+        // The child of this commit that leads to the newest commit.
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "{ANCESTORS} SELECT id FROM commits WHERE parent = ?2 AND id IN ancestors"
+        ))?;
+        let next: Option<i64> = stmt.query_row([tip, id], |r| r.get(0)).optional()?;
+        Ok((prev, next.map(Revision::Sqlite)))
+    }
+
+    /// Returns the name of the branch that commits go to.
+    pub(crate) fn current_branch(&self) -> Result<String, Box<dyn Error>> {
+        self.target_branch()
+    }
+
+    /// Returns the names of the branches, sorted.
+    pub(crate) fn branches(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name FROM branches ORDER BY name")?;
+        let names = stmt.query_map([], |r| r.get(0))?;
+        Ok(names.collect::<Result<_, _>>()?)
+    }
+
+    /// Creates a branch starting at from, or at the newest commit on the current branch.
+    pub(crate) fn create_branch(
+        &self,
+        name: &str,
+        from: Option<&Revision>,
+    ) -> Result<(), Box<dyn Error>> {
+        let start = match from {
+            Some(revision) => self.find_commit(revision)?,
+            None => self.tip()?,
+        };
+        let start = start.ok_or("there are no commits to start the branch from yet")?;
+        if branch_head(&self.conn, name)?.is_some() {
+            return Err(format!("branch {name} already exists").into());
+        }
+        set_branch(&self.conn, name, start)
+    }
+
+    /// Tags the commit at revision.
+    pub(crate) fn create_tag(
+        &self,
+        name: &str,
+        revision: &Revision,
+        note: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let commit = self
+            .find_commit(revision)?
+            .ok_or("there are no commits to tag yet")?;
+        let date = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO tags (name, commit_id, date, note) VALUES (?1, ?2, ?3, ?4)",
+            params![name, commit, date, note],
+        )?;
+        if inserted == 0 {
+            return Err(format!("tag {name} already exists").into());
+        }
+        Ok(commit.to_string())
+    }
+
+    /// Returns the tags, oldest first.
+    pub(crate) fn tags(&self) -> Result<Vec<TagInfo>, Box<dyn Error>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name, commit_id, date, note FROM tags ORDER BY date, name")?;
+        let tags = stmt.query_map([], |r| {
+            Ok(TagInfo {
+                name: r.get(0)?,
+                hash: r.get::<_, i64>(1)?.to_string(),
+                date: UNIX_EPOCH + Duration::from_secs(r.get::<_, i64>(2)? as u64),
+                note: r.get(3)?,
+            })
+        })?;
+        Ok(tags.collect::<Result<_, _>>()?)
+    }
+
+    pub(crate) fn store_render(
+        &self,
+        rendered: &RenderedTree,
+        msg: &str,
+        prev_render: Revision,
+        source: Revision,
+        branch: Option<&str>,
+    ) -> Result<Revision, Box<dyn Error>> {
+        let mut files: BTreeMap<String, Vec<u8>> = match self.find_commit(&rendered.base)? {
+            None => BTreeMap::new(),
+            Some(base) => {
+                let mut stmt = self
+                    .conn
+                    .prepare_cached("SELECT path, blob FROM render_files WHERE commit_id = ?1")?;
+                let rows = stmt.query_map([base], |r| Ok((r.get(0)?, r.get(1)?)))?;
+                rows.collect::<Result<_, _>>()?
+            }
+        };
+        // Removed files are given without an extension (e.g. "spike/Project 1" matches
+        // "spike/Project 1.py").
+        files.retain(|path, _| {
+            let stem = path.rsplit_once('.').map(|(stem, _)| stem);
+            !rendered.removed.iter().any(|r| Some(r.as_str()) == stem)
+        });
+
+        let source = match source {
+            Revision::Latest => self.tip()?.ok_or("no commits yet")?,
+            source => self.find_commit(&source)?.ok_or("no source commit")?,
+        };
+        let parent = self.find_commit(&prev_render)?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        for (path, contents) in &rendered.files {
+            files.insert(path.clone(), write_blob(&tx, contents)?);
+        }
+        let commit = insert_commit(&tx, parent, msg, None, Some(source))?;
+        for (path, blob) in files {
+            tx.execute(
+                "INSERT INTO render_files (commit_id, path, blob) VALUES (?1, ?2, ?3)",
+                params![commit, path, blob],
+            )?;
+        }
+        if let Some(branch) = branch {
+            set_branch(&tx, branch, commit)?;
+        }
+        tx.commit()?;
+        Ok(Revision::Sqlite(commit))
+    }
+
+    pub(crate) fn rendered_branch(&self, branch: &str) -> Result<RenderedBranch, Box<dyn Error>> {
+        // Rendered commits have the previous render (if any) as their parent, and the commit
+        // they were rendered from as their source.
+        let (prev_render, last_source) = match branch_head(&self.conn, branch)? {
+            None => (Revision::Empty, None),
+            Some(render) => {
+                let source: Option<i64> = self.conn.query_row(
+                    "SELECT source FROM commits WHERE id = ?1",
+                    [render],
+                    |r| r.get(0),
+                )?;
+                (Revision::Sqlite(render), source)
+            }
+        };
+
+        let mut pending = Vec::new();
+        if let Some(tip) = self.tip()? {
+            let mut stmt = self
+                .conn
+                .prepare_cached(&format!("{ANCESTORS} SELECT id FROM ancestors"))?;
+            for id in stmt.query_map([tip], |r| r.get(0))? {
+                let id: i64 = id?;
+                if Some(id) == last_source {
+                    break;
+                }
+                pending.push(Revision::Sqlite(id));
+            }
+        }
+        pending.reverse();
+
+        Ok(RenderedBranch {
+            prev_render,
+            last_source: last_source.map_or(Revision::Empty, Revision::Sqlite),
+            pending,
+        })
+    }
+}
+
+fn project_id(program: String, name: String) -> Result<ProjectID, Box<dyn Error>> {
+    Ok(ProjectID {
+        program: program.parse()?,
+        name,
+    })
+}
+
+fn insert_commit(
+    conn: &Connection,
+    parent: Option<i64>,
+    message: &str,
+    date: Option<SystemTime>,
+    source: Option<i64>,
+) -> Result<i64, Box<dyn Error>> {
+    let date = date.unwrap_or_else(SystemTime::now);
+    let date = date.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    conn.execute(
+        "INSERT INTO commits (parent, date, message, source) VALUES (?1, ?2, ?3, ?4)",
+        params![parent, date, message, source],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn branch_head(conn: &Connection, branch: &str) -> Result<Option<i64>, Box<dyn Error>> {
+    Ok(conn
+        .query_row("SELECT head FROM branches WHERE name = ?1", [branch], |r| {
+            r.get(0)
+        })
+        .optional()?)
+}
+
+/// Returns the snapshot of the project in the commit, if it's there.
+fn snapshot_at(
+    conn: &Connection,
+    commit: Option<i64>,
+    id: &ProjectID,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let Some(commit) = commit else {
+        return Ok(None);
+    };
+    let mut stmt = conn.prepare_cached(&format!(
+        "{ANCESTORS}
+        SELECT snapshot FROM changes
+        WHERE program = ?2 AND name = ?3 AND commit_id IN ancestors
+        ORDER BY commit_id DESC LIMIT 1"
+    ))?;
+    let snapshot: Option<Option<Vec<u8>>> = stmt
+        .query_row(params![commit, id.program.to_string(), id.name], |r| {
+            r.get(0)
+        })
+        .optional()?;
+    Ok(snapshot.flatten())
+}
+
+fn set_branch(conn: &Connection, branch: &str, commit: i64) -> Result<(), Box<dyn Error>> {
+    conn.execute(
+        "INSERT INTO branches (name, head) VALUES (?1, ?2)
+        ON CONFLICT (name) DO UPDATE SET head = excluded.head",
+        params![branch, commit],
+    )?;
+    Ok(())
+}
+
+/// Saves the file contents unless they're already there, and returns their hash.
+fn write_blob(conn: &Connection, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let hash = Sha256::digest(data).to_vec();
+    conn.execute(
+        "INSERT OR IGNORE INTO blobs (hash, data) VALUES (?1, ?2)",
+        params![hash, data],
+    )?;
+    Ok(hash)
+}
+
+/// Saves the project as a snapshot with the given hash, unless it's already there.
+fn write_snapshot(conn: &Connection, raw: &RawProject, hash: &[u8]) -> Result<(), Box<dyn Error>> {
+    if conn.execute("INSERT OR IGNORE INTO snapshots (hash) VALUES (?1)", [hash])? == 0 {
+        return Ok(());
+    }
+    let mut files = Vec::new();
+    flatten(&raw.archive, "", &mut files);
+    for (path, data) in files {
+        let blob = write_blob(conn, data)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO snapshot_entries (snapshot, path, blob) VALUES (?1, ?2, ?3)",
+            params![hash, path, blob],
+        )?;
+    }
+    Ok(())
+}
+
+/// Lists the files in the archive by path. Like in git stores, nested archives (i.e.
+/// scratch.sb3) become directories.
+fn flatten<'a>(archive: &'a RawArchive, prefix: &str, files: &mut Vec<(String, &'a [u8])>) {
+    for e in &archive.entries {
+        match &e.contents {
+            ArchiveEntryContents::Data(data) => files.push((format!("{prefix}{}", e.name), data)),
+            ArchiveEntryContents::Archive(arch) => {
+                flatten(arch, &format!("{prefix}{}/", e.name), files)
+            }
+        }
+    }
+}
+
+fn unflatten(files: Files) -> RawArchive {
+    let mut entries = Vec::new();
+    let mut dirs: Vec<(String, Files)> = Vec::new();
+    for (path, data) in files {
+        match path.split_once('/') {
+            None => entries.push(ArchiveEntry {
+                name: path,
+                contents: ArchiveEntryContents::Data(data),
+            }),
+            Some((dir, rest)) => {
+                let file = (rest.to_string(), data);
+                match dirs.iter_mut().find(|(name, _)| name == dir) {
+                    Some((_, files)) => files.push(file),
+                    None => dirs.push((dir.to_string(), vec![file])),
+                }
+            }
+        }
+    }
+    entries.extend(dirs.into_iter().map(|(name, files)| ArchiveEntry {
+        name,
+        contents: ArchiveEntryContents::Archive(unflatten(files)),
+    }));
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    RawArchive { entries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::test_util::id;
+
+    fn data(s: &str) -> ArchiveEntryContents {
+        ArchiveEntryContents::Data(s.as_bytes().to_vec())
+    }
+
+    fn blocks(project_json: &str) -> RawProject {
+        let entry = |name: &str, contents| ArchiveEntry {
+            name: name.to_string(),
+            contents,
+        };
+        let sb3 = RawArchive {
+            entries: vec![entry("project.json", data(project_json))],
+        };
+        RawProject {
+            archive: RawArchive {
+                entries: vec![
+                    entry("manifest.json", data(r#"{"type": "word-blocks"}"#)),
+                    entry("scratch.sb3", ArchiveEntryContents::Archive(sb3)),
+                ],
+            },
+        }
+    }
+
+    fn history(store: &SqliteStore) -> Vec<CommitInfo> {
+        match store.log(UNIX_EPOCH).unwrap() {
+            LogResult::Some(commits) => commits,
+            _ => panic!("expected commits"),
+        }
+    }
+
+    #[test]
+    fn test_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.sqlite");
+        let store = create(&path).unwrap();
+        assert!(matches!(store.log(UNIX_EPOCH).unwrap(), LogResult::Unborn));
+
+        let date = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let commit = |projects: &[(ProjectID, RawProject)], msg| store.commit(projects, msg, None);
+        assert_eq!(
+            store
+                .commit(
+                    &[(id("a"), blocks("{}")), (id("b"), blocks("{}"))],
+                    "first",
+                    Some(date)
+                )
+                .unwrap(),
+            "added"
+        );
+        assert_eq!(
+            commit(&[(id("a"), blocks("{}"))], "same").unwrap(),
+            "already up to date"
+        );
+        assert_eq!(
+            commit(&[(id("a"), blocks("[]"))], "second").unwrap(),
+            "added"
+        );
+        assert_eq!(store.untrack(&id("b"), "third").unwrap(), "removed");
+        assert_eq!(store.untrack(&id("b"), "again").unwrap(), "not tracked");
+
+        // Both projects started out the same, so they share a snapshot.
+        let count = |table: &str| -> i64 {
+            let sql = format!("SELECT COUNT(*) FROM {table}");
+            store.conn.query_row(&sql, [], |r| r.get(0)).unwrap()
+        };
+        assert_eq!(count("snapshots"), 2);
+        assert_eq!(count("blobs"), 3);
+
+        let store = open(&path, None).unwrap();
+        assert_eq!(store.project_ids().unwrap(), [id("a")]);
+        let read = |id, revision| {
+            let raw: RawProject = store.read_project(&id, &revision).unwrap().unwrap();
+            raw.hash()
+        };
+        assert_eq!(read(id("a"), Revision::Latest), blocks("[]").hash());
+        assert_eq!(read(id("b"), Revision::Sqlite(1)), blocks("{}").hash());
+        assert!(store
+            .read_project(&id("b"), &Revision::Latest)
+            .unwrap()
+            .is_none());
+
+        let log = history(&store);
+        let messages: Vec<&str> = log.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["third", "second", "first"]);
+        assert_eq!(log[0].changed_projects, [id("b")]);
+        assert_eq!(log[2].changed_projects, [id("a"), id("b")]);
+        assert_eq!(log[2].date, date);
+        let messages: Vec<String> = store
+            .project_history(&id("a"))
+            .unwrap()
+            .into_iter()
+            .map(|c| c.message)
+            .collect();
+        assert_eq!(messages, ["second", "first"]);
+
+        assert!(matches!(
+            store.resolve("HEAD~2").unwrap(),
+            Revision::Sqlite(1)
+        ));
+        assert!(store.resolve("HEAD~3").is_err());
+        let changes = store
+            .changed_projects(&Revision::Sqlite(1), &Revision::Latest)
+            .unwrap();
+        assert_eq!(changes.changed, [id("a")]);
+        assert_eq!(changes.removed, [id("b")]);
+        let (prev, next) = store.adjacent_revisions(&Revision::Sqlite(2)).unwrap();
+        assert!(matches!(
+            (prev, next),
+            (Some(Revision::Sqlite(1)), Some(Revision::Sqlite(3)))
+        ));
+    }
+
+    #[test]
+    fn test_commits_between() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.sqlite");
+        let store = create(&path).unwrap();
+        let day = |n: u64| UNIX_EPOCH + Duration::from_secs(1_700_000_000 + n * 86_400);
+        let commit = |store: &SqliteStore, n, body, msg| {
+            store
+                .commit(&[(id("a"), blocks(body))], msg, Some(day(n)))
+                .unwrap();
+        };
+        commit(&store, 1, "1", "one");
+        commit(&store, 3, "3", "three");
+        // Imported after, but dated before.
+        commit(&store, 2, "2", "two");
+        store.create_branch("trial", None).unwrap();
+        commit(&open(&path, Some("trial")).unwrap(), 2, "t", "trial");
+
+        let messages = |from, to| -> Vec<String> {
+            let commits = store.commits_between(day(from), to).unwrap();
+            commits.into_iter().map(|c| c.message).collect()
+        };
+        assert_eq!(messages(2, Some(day(3))), ["two"]);
+        assert_eq!(messages(1, Some(day(4))), ["two", "three", "one"]);
+        assert_eq!(messages(2, None), ["two", "three"]);
+        assert!(messages(4, None).is_empty());
+        let commits = store.commits_between(day(3), None).unwrap();
+        assert_eq!(commits[0].changed_projects, [id("a")]);
+        assert!(matches!(commits[0].revision, Revision::Sqlite(2)));
+    }
+
+    #[test]
+    fn test_branches_and_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.sqlite");
+        let store = create(&path).unwrap();
+        store
+            .commit(&[(id("a"), blocks("{}"))], "first", None)
+            .unwrap();
+        store.create_branch("trial", None).unwrap();
+        store
+            .create_tag("works", &Revision::Latest, "scored 480")
+            .unwrap();
+        assert!(store.create_tag("works", &Revision::Latest, "").is_err());
+
+        let trial = open(&path, Some("trial")).unwrap();
+        trial
+            .commit(&[(id("a"), blocks("[]"))], "try something", None)
+            .unwrap();
+        assert_eq!(trial.current_branch().unwrap(), "trial");
+        assert_eq!(store.branches().unwrap(), ["main", "trial"]);
+        assert_eq!(history(&store).len(), 1);
+        assert_eq!(history(&trial).len(), 2);
+
+        let tags = store.tags().unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(
+            (tags[0].hash.as_str(), tags[0].note.as_str()),
+            ("1", "scored 480")
+        );
+        let tagged = trial.resolve("works").unwrap();
+        let raw = trial.read_project(&id("a"), &tagged).unwrap().unwrap();
+        assert_eq!(raw.hash(), blocks("{}").hash());
+        assert!(matches!(
+            trial.resolve("main").unwrap(),
+            Revision::Sqlite(1)
+        ));
+    }
+
+    #[test]
+    fn test_rendered_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = create(dir.path().join("store.sqlite")).unwrap();
+        store
+            .commit(&[(id("a"), blocks("{}"))], "first", None)
+            .unwrap();
+        let branch = store.rendered_branch("rendered").unwrap();
+        assert!(matches!(branch.prev_render, Revision::Empty));
+        assert_eq!(branch.pending.len(), 1);
+
+        let tree = RenderedTree {
+            base: Revision::Empty,
+            files: vec![("spike/a.txt".to_string(), b"when program starts".to_vec())],
+            removed: Vec::new(),
+        };
+        let render = store
+            .store_render(
+                &tree,
+                "Rendered",
+                Revision::Empty,
+                Revision::Latest,
+                Some("rendered"),
+            )
+            .unwrap();
+        store.untrack(&id("a"), "remove").unwrap();
+        let branch = store.rendered_branch("rendered").unwrap();
+        assert!(matches!(branch.last_source, Revision::Sqlite(1)));
+        assert_eq!(branch.pending.len(), 1);
+
+        let tree = RenderedTree {
+            base: render.clone(),
+            files: Vec::new(),
+            removed: vec!["spike/a".to_string()],
+        };
+        store
+            .store_render(
+                &tree,
+                "Rendered",
+                render,
+                Revision::Latest,
+                Some("rendered"),
+            )
+            .unwrap();
+        let files: i64 = store
+            .conn
+            .query_row("SELECT COUNT(*) FROM render_files", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(files, 1);
+        assert!(store
+            .rendered_branch("rendered")
+            .unwrap()
+            .pending
+            .is_empty());
+    }
+}
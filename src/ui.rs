@@ -0,0 +1,439 @@
+//! A full-screen terminal interface for tracking projects, browsing their history, and restoring
+//! older versions.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use rayon::prelude::*;
+
+use crate::cache::Cache;
+use crate::config::{Config, StoreConfig};
+use crate::dirs::Dirs;
+use crate::project::ProjectID;
+use crate::render::txt;
+use crate::store::{self, CommitInfo, CommitResult, Revision, Store};
+use crate::{app, restore, status, track, untrack};
+
+pub fn run(cfg: Config, cache: Cache) -> Result<(), Box<dyn Error>> {
+    let mut ui = Ui::new(cfg, cache)?;
+    let mut terminal = ratatui::init();
+    let res = ui.run(&mut terminal);
+    ratatui::restore();
+    res
+}
+
+enum State {
+    Untracked,
+    UpToDate,
+    /// Number of stores that don't have the local version.
+    OutOfDate(usize),
+    LocalMissing,
+    Error(String),
+}
+
+impl State {
+    fn label(&self, store_count: usize) -> Span<'static> {
+        match self {
+            State::Untracked => "untracked".dark_gray(),
+            State::UpToDate => "up to date".green(),
+            State::OutOfDate(n) => format!("{n}/{store_count} stores need sync").yellow(),
+            State::LocalMissing => "local copy deleted".red(),
+            State::Error(e) => format!("error: {e}").red(),
+        }
+    }
+}
+
+struct Row {
+    id: ProjectID,
+    state: State,
+}
+
+enum View {
+    Projects,
+    History {
+        id: ProjectID,
+        commits: Vec<CommitInfo>,
+        list: ListState,
+    },
+    Text {
+        title: String,
+        lines: Vec<Line<'static>>,
+        scroll: u16,
+    },
+}
+
+struct Ui {
+    cfg: Config,
+    dirs: Dirs,
+    stores: Vec<(StoreConfig, Arc<Store>)>,
+    cache: Cache,
+    /// Store used for history, previews, and restores.
+    store: usize,
+    rows: Vec<Row>,
+    list: ListState,
+    /// Views on top of the project list; Esc goes back to the previous one.
+    views: Vec<View>,
+    message: Option<String>,
+    /// Revision waiting for the user to confirm the restore.
+    pending_restore: Option<(ProjectID, String)>,
+}
+
+impl Ui {
+    fn new(cfg: Config, cache: Cache) -> Result<Self, Box<dyn Error>> {
+        let dirs = Dirs::new(&cfg)?;
+        let (stores, store_errs) = store::open_all(&cfg.stores);
+        let message = store_errs
+            .first()
+            .map(|(st, e)| format!("{st}: error opening store: {e}"));
+        let mut ui = Self {
+            cfg,
+            dirs,
+            stores: stores
+                .into_iter()
+                .map(|(sc, s)| (sc, Arc::new(s)))
+                .collect(),
+            cache,
+            store: 0,
+            rows: Vec::new(),
+            list: ListState::default(),
+            views: vec![View::Projects],
+            message,
+            pending_restore: None,
+        };
+        ui.load_rows()?;
+        Ok(ui)
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if !self.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn load_rows(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut tracked = HashSet::new();
+        for (_, store) in &self.stores {
+            tracked.extend(store.project_ids()?);
+        }
+        let mut ids: Vec<ProjectID> = tracked.iter().cloned().collect();
+        for id in app::all_projects(&self.dirs)? {
+            if !tracked.contains(&id) {
+                ids.push(id);
+            }
+        }
+        ids.sort_by_key(|id| id.to_string());
+
+        let stores: Vec<Arc<Store>> = self.stores.iter().map(|(_, s)| s.clone()).collect();
+        self.rows = ids
+            .into_par_iter()
+            .map(|id| {
+                let state = match tracked.contains(&id) {
+                    false => State::Untracked,
+                    true => match status::get_status(&id, &stores, &self.dirs, &self.cache) {
+                        Ok(status::Status::NoDifferences) => State::UpToDate,
+                        Ok(status::Status::LocalMissing) => State::LocalMissing,
+                        Ok(status::Status::Differences(d)) => State::OutOfDate(d.len()),
+                        Err(e) => State::Error(e.to_string()),
+                    },
+                };
+                Row { id, state }
+            })
+            .collect();
+        // The cache only saves time, so there's nothing to do if it can't be saved.
+        let _ = self.cache.save();
+        let last = self.rows.len().checked_sub(1);
+        self.list.select(match self.list.selected() {
+            Some(i) => last.map(|last| i.min(last)),
+            None => last.map(|_| 0),
+        });
+        Ok(())
+    }
+
+    fn current_store(&self) -> Result<&Arc<Store>, Box<dyn Error>> {
+        match self.stores.get(self.store) {
+            Some((_, store)) => Ok(store),
+            None => Err(format!(
+                "no stores yet, create one with '{} store create'",
+                crate::exe()
+            )
+            .into()),
+        }
+    }
+
+    /// Returns false when it's time to quit.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        if let Some((id, hash)) = self.pending_restore.take() {
+            self.message = match code {
+                KeyCode::Char('y') => Some(match self.restore(&id, &hash) {
+                    Ok(path) => format!("Restored {id} from {hash} to {path:?}"),
+                    Err(e) => format!("Error restoring {id}: {e}"),
+                }),
+                _ => Some("Restore cancelled".to_string()),
+            };
+            return true;
+        }
+        self.message = None;
+
+        match code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Esc | KeyCode::Backspace | KeyCode::Left => {
+                if self.views.len() == 1 {
+                    return false;
+                }
+                self.views.pop();
+                return true;
+            }
+            _ => {}
+        };
+
+        let res = match self.views.last_mut() {
+            Some(View::Projects) | None => self.projects_key(code),
+            Some(View::History { .. }) => self.history_key(code),
+            Some(View::Text { scroll, .. }) => {
+                match code {
+                    KeyCode::Up | KeyCode::Char('k') => *scroll = scroll.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') => *scroll = scroll.saturating_add(1),
+                    KeyCode::PageUp => *scroll = scroll.saturating_sub(20),
+                    KeyCode::PageDown | KeyCode::Char(' ') => *scroll = scroll.saturating_add(20),
+                    KeyCode::Home => *scroll = 0,
+                    _ => {}
+                };
+                Ok(())
+            }
+        };
+        if let Err(e) = res {
+            self.message = Some(format!("Error: {e}"));
+        }
+        true
+    }
+
+    fn projects_key(&mut self, code: KeyCode) -> Result<(), Box<dyn Error>> {
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Char('r') => {
+                self.load_rows()?;
+                self.message = Some("Refreshed".to_string());
+            }
+            KeyCode::Char('s') if !self.stores.is_empty() => {
+                self.store = (self.store + 1) % self.stores.len();
+                self.message = Some(format!("Using {}", self.stores[self.store].0));
+            }
+            KeyCode::Char('t') => {
+                let Some(row) = self.list.selected().and_then(|i| self.rows.get(i)) else {
+                    return Ok(());
+                };
+                self.message = Some(self.toggle_tracking(&row.id, &row.state)?);
+                self.load_rows()?;
+            }
+            KeyCode::Enter | KeyCode::Right => {
+                let Some(row) = self.list.selected().and_then(|i| self.rows.get(i)) else {
+                    return Ok(());
+                };
+                let id = row.id.clone();
+                let commits = self.current_store()?.project_history(&id)?;
+                if commits.is_empty() {
+                    self.message = Some(format!("{id} has no history in this store"));
+                    return Ok(());
+                }
+                self.views.push(View::History {
+                    id,
+                    commits,
+                    list: ListState::default().with_selected(Some(0)),
+                });
+            }
+            _ => {}
+        };
+        Ok(())
+    }
+
+    fn history_key(&mut self, code: KeyCode) -> Result<(), Box<dyn Error>> {
+        let Some(View::History { id, commits, list }) = self.views.last_mut() else {
+            return Ok(());
+        };
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => list.select_next(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('v') | KeyCode::Char('d') => {
+                let Some(i) = list.selected().filter(|i| *i < commits.len()) else {
+                    return Ok(());
+                };
+                let id = id.clone();
+                let hash = commits[i].hash.clone();
+                // Compare with the previous commit that changed this project.
+                let prev = commits.get(i + 1).map(|c| c.hash.clone());
+                let view = match code {
+                    KeyCode::Char('d') => self.diff_view(&id, prev.as_deref(), &hash)?,
+                    _ => self.preview(&id, &hash)?,
+                };
+                self.views.push(view);
+            }
+            KeyCode::Char('R') => {
+                if let Some(commit) = list.selected().and_then(|i| commits.get(i)) {
+                    self.message = Some(format!(
+                        "Replace the local copy of {id} with the version from {}? (y/n)",
+                        commit.hash
+                    ));
+                    self.pending_restore = Some((id.clone(), commit.hash.clone()));
+                }
+            }
+            _ => {}
+        };
+        Ok(())
+    }
+
+    fn toggle_tracking(&self, id: &ProjectID, state: &State) -> Result<String, Box<dyn Error>> {
+        let file_name = PathBuf::from(&id.name);
+        let (verb, store_results) = match state {
+            State::Untracked => (
+                "Now tracking",
+                track::track(self.cfg.clone(), id.program, file_name)?.store_results,
+            ),
+            _ => (
+                "Stopped tracking",
+                untrack::untrack(self.cfg.clone(), id.program, file_name)?.store_results,
+            ),
+        };
+        Ok(summarize(&format!("{verb} {id}"), store_results))
+    }
+
+    fn preview(&self, id: &ProjectID, hash: &str) -> Result<View, Box<dyn Error>> {
+        let store = self.current_store()?;
+        let text = txt::project_text(store, id, &store.resolve(hash)?)?;
+        Ok(View::Text {
+            title: format!("{id} at {hash}"),
+            lines: text.lines().map(|l| Line::raw(l.to_string())).collect(),
+            scroll: 0,
+        })
+    }
+
+    fn diff_view(
+        &self,
+        id: &ProjectID,
+        from: Option<&str>,
+        to: &str,
+    ) -> Result<View, Box<dyn Error>> {
+        let store = self.current_store()?;
+        let from_revision = match from {
+            Some(hash) => store.resolve(hash)?,
+            None => Revision::Empty,
+        };
+        let from = from.unwrap_or("empty");
+        let diff = txt::diff(store, id, (from, &from_revision), (to, &store.resolve(to)?))?;
+        let lines = diff
+            .lines()
+            .map(|l| {
+                let style = match l.chars().next() {
+                    Some('+') => Style::new().fg(Color::Green),
+                    Some('-') => Style::new().fg(Color::Red),
+                    Some('@') => Style::new().fg(Color::Cyan),
+                    _ => Style::new(),
+                };
+                Line::styled(l.to_string(), style)
+            })
+            .collect();
+        Ok(View::Text {
+            title: format!("{id}: {from}..{to}"),
+            lines,
+            scroll: 0,
+        })
+    }
+
+    fn restore(&self, id: &ProjectID, hash: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let store = self.current_store()?;
+        let path = restore::restore(store, id, &store.resolve(hash)?, &self.dirs)?;
+        Ok(path)
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let store_name = match self.stores.get(self.store) {
+            Some((sc, _)) => sc.to_string(),
+            None => "no stores".to_string(),
+        };
+
+        let help = match self.views.last_mut() {
+            Some(View::Projects) | None => {
+                let store_count = self.stores.len();
+                let items: Vec<ListItem> = self
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        ListItem::new(Line::from(vec![
+                            Span::raw(format!("{:<40} ", row.id.to_string())),
+                            row.state.label(store_count),
+                        ]))
+                    })
+                    .collect();
+                let list = List::new(items)
+                    .block(Block::bordered().title(format!(" Projects ({store_name}) ")))
+                    .highlight_style(Modifier::REVERSED);
+                frame.render_stateful_widget(list, main, &mut self.list);
+                "↑↓ move · enter history · t track/untrack · s switch store · r refresh · q quit"
+            }
+            Some(View::History { id, commits, list }) => {
+                let items: Vec<ListItem> = commits
+                    .iter()
+                    .map(|c| {
+                        ListItem::new(Line::from(vec![
+                            c.hash.clone().yellow(),
+                            Span::raw(format!("  {}  ", crate::format_datetime(c.date))),
+                            Span::raw(c.message.lines().next().unwrap_or("").to_string()),
+                        ]))
+                    })
+                    .collect();
+                let widget = List::new(items)
+                    .block(Block::bordered().title(format!(" History of {id} ")))
+                    .highlight_style(Modifier::REVERSED);
+                frame.render_stateful_widget(widget, main, list);
+                "↑↓ move · enter view · d diff with previous · R restore · esc back · q quit"
+            }
+            Some(View::Text {
+                title,
+                lines,
+                scroll,
+            }) => {
+                let widget = Paragraph::new(lines.clone())
+                    .block(Block::bordered().title(format!(" {title} ")))
+                    .scroll((*scroll, 0));
+                frame.render_widget(widget, main);
+                "↑↓ pgup pgdn scroll · esc back · q quit"
+            }
+        };
+
+        let footer_text = match &self.message {
+            Some(msg) => Line::from(msg.clone().bold()),
+            None => Line::from(help.dark_gray()),
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+}
+
+/// Describes the result of a track or untrack in one line.
+fn summarize(what: &str, store_results: Vec<(StoreConfig, CommitResult)>) -> String {
+    let errors: Vec<String> = store_results
+        .into_iter()
+        .filter_map(|(st, res)| res.err().map(|e| format!("{st}: {e}")))
+        .collect();
+    match errors.is_empty() {
+        true => what.to_string(),
+        false => format!("{what}, with errors: {}", errors.join("; ")),
+    }
+}
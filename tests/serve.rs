@@ -0,0 +1,228 @@
+//! Runs `mind-meld serve` against a temporary store and talks to it over HTTP.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use tempfile::TempDir;
+use zip::write::SimpleFileOptions;
+
+struct Env {
+    dir: TempDir,
+}
+
+impl Env {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("spike")).unwrap();
+        fs::create_dir(dir.path().join("mindstorms")).unwrap();
+        let cfg = format!(
+            "spike_path = {:?}\nmindstorms_path = {:?}\n",
+            dir.path().join("spike"),
+            dir.path().join("mindstorms")
+        );
+        fs::write(dir.path().join("cfg.toml"), cfg).unwrap();
+        Self { dir }
+    }
+
+    fn mm(&self) -> Command {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_mind-meld"));
+        cmd.arg("--config")
+            .arg(self.dir.path().join("cfg.toml"))
+            .env("GIT_AUTHOR_NAME", "Test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com");
+        cmd
+    }
+
+    fn run(&self, args: &[&str]) {
+        let out = self.mm().args(args).output().unwrap();
+        assert!(out.status.success(), "{args:?}: {out:?}");
+    }
+
+    fn spike_project(&self, name: &str) -> PathBuf {
+        self.dir.path().join("spike").join(name)
+    }
+
+    fn serve(&self) -> Server {
+        let mut child = self
+            .mm()
+            .args(["serve", "--port", "0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("Listening on http://")
+            .and_then(|a| a.strip_suffix('/'))
+            .unwrap_or_else(|| panic!("unexpected output: {line:?}"))
+            .to_string();
+        Server {
+            child,
+            _stdout: stdout,
+            addr,
+        }
+    }
+}
+
+struct Server {
+    child: Child,
+    /// Kept open so that the server can keep logging requests.
+    _stdout: BufReader<ChildStdout>,
+    addr: String,
+}
+
+impl Server {
+    fn request(&self, method: &str, path: &str, headers: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: 0\r\n{headers}\r\n",
+            self.addr
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, b)| b.to_string())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    fn get(&self, path: &str) -> String {
+        let (status, body) = self.request("GET", path, "");
+        assert_eq!(status, 200, "GET {path}: {body}");
+        body
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn write_python_project(path: &Path, source: &str) {
+    let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+    let options = SimpleFileOptions::default();
+    zip.start_file("manifest.json", options).unwrap();
+    zip.write_all(br#"{"type": "python", "name": "Project 1"}"#)
+        .unwrap();
+    zip.start_file("projectbody.json", options).unwrap();
+    let body = serde_json::json!({ "main": source });
+    zip.write_all(body.to_string().as_bytes()).unwrap();
+    zip.finish().unwrap();
+}
+
+fn read_python_source(path: &Path) -> String {
+    let mut zip = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+    let mut body = String::new();
+    zip.by_name("projectbody.json")
+        .unwrap()
+        .read_to_string(&mut body)
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    body["main"].as_str().unwrap().to_string()
+}
+
+/// Creates a store with two commits of one project, returning their short hashes (newest first).
+fn setup(env: &Env) -> Vec<String> {
+    let store = env.dir.path().join("store");
+    env.run(&["store", "create", "--type", "git", store.to_str().unwrap()]);
+    let project = env.spike_project("Project 1.llsp3");
+    write_python_project(&project, "print('one')\n");
+    env.run(&["track", "--spike", "Project 1.llsp3"]);
+    write_python_project(&project, "print('two')\n");
+    env.run(&["commit"]);
+
+    let out = env.mm().args(["log", "--since", "1h"]).output().unwrap();
+    let log = String::from_utf8_lossy(&out.stdout).to_string();
+    let hashes: Vec<String> = log
+        .split_whitespace()
+        .filter(|w| w.len() >= 7 && w.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(hashes.len(), 2, "{log}");
+    hashes
+}
+
+#[test]
+fn browse_history() {
+    let env = Env::new();
+    let hashes = setup(&env);
+    let server = env.serve();
+
+    assert!(server.get("/").contains(r#"<a href="/stores/0">"#));
+    let project_url = "/stores/0/projects/spike/Project%201.llsp3/";
+    assert!(server.get("/stores/0").contains(project_url));
+
+    let history = server.get(project_url);
+    for hash in &hashes {
+        assert!(history.contains(hash.as_str()), "{history}");
+    }
+
+    let old = server.get(&format!("{project_url}at/{}", hashes[1]));
+    assert!(old.contains("one"), "{old}");
+    assert!(!old.contains("two"), "{old}");
+
+    let diff = server.get(&format!("{project_url}diff/{}/{}", hashes[1], hashes[0]));
+    assert!(
+        diff.contains(r#"<span class="del">-print(&#39;one&#39;)</span>"#),
+        "{diff}"
+    );
+    assert!(
+        diff.contains(r#"<span class="add">+print(&#39;two&#39;)</span>"#),
+        "{diff}"
+    );
+
+    let (status, _) = server.request("GET", "/stores/7", "");
+    assert_eq!(status, 500);
+    let (status, _) = server.request("GET", "/nope", "");
+    assert_eq!(status, 404);
+}
+
+#[test]
+fn restore_project() {
+    let env = Env::new();
+    let hashes = setup(&env);
+    let server = env.serve();
+    let project = env.spike_project("Project 1.llsp3");
+    let restore_url = format!(
+        "/stores/0/projects/spike/Project%201.llsp3/restore/{}",
+        hashes[1]
+    );
+
+    // Posts from other sites are refused.
+    let (status, _) = server.request("POST", &restore_url, "Origin: http://example.com\r\n");
+    assert_eq!(status, 403);
+    assert_eq!(read_python_source(&project), "print('two')\n");
+
+    let origin = format!("Origin: http://{}\r\n", server.addr);
+    let (status, body) = server.request("POST", &restore_url, &origin);
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(read_python_source(&project), "print('one')\n");
+}
+
+#[test]
+fn rejects_other_hosts() {
+    let env = Env::new();
+    let server = env.serve();
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\nHost: evil.example.com\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+}